@@ -0,0 +1,1170 @@
+use std::collections;
+use std::sync;
+
+use futures::{future, sink, stream};
+use tokio::time;
+
+use crate::bus;
+use crate::candump;
+use crate::motion;
+use crate::servo_cmd::{self, ProtocolError, ServoOpcode, ServoRequest, ServoResponse};
+use crate::sim;
+use crate::Axis;
+
+pub mod moves;
+
+/// How long to wait for a servo to respond to a request.
+const RESPONSE_TIMEOUT: time::Duration = time::Duration::from_millis(100);
+/// How long to wait for a servo to complete a motion, after it has acknowledged starting it.
+const MOTION_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+/// How often to update the speed of a servo that follows a speed profile.
+const PROFILE_PERIOD: time::Duration = time::Duration::from_millis(20);
+/// How fast to correct the difference between the planned and the measured position of a servo
+/// that follows a speed profile, in RPM per rotation of difference.
+const POSITION_GAIN: f64 = 120.0;
+/// How far a servo that follows a speed profile may fall behind or run ahead, in rotations,
+/// before the motion is stopped.
+const MAX_FOLLOWING_ERROR: f64 = 2.0;
+/// The fastest speed that the servos support, in RPM.
+const MAX_SPEED: f64 = 3000.0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The CAN socket failed.
+    #[error("CAN bus error")]
+    Bus(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// The bus dispatcher has stopped, so frames can no longer be sent or received.
+    #[error("CAN bus closed")]
+    Closed,
+    /// A channel didn't keep up with the frames received from the bus, and missed some of them.
+    #[error("missed {0} frames from the CAN bus")]
+    Lagged(u64),
+    /// A frame couldn't be encoded or decoded.
+    #[error("malformed servo frame")]
+    Protocol(#[from] ProtocolError),
+    /// The servo didn't respond in time.
+    #[error("didn't get a response to {opcode:?} for axis {axis:?}")]
+    Timeout { axis: Axis, opcode: ServoOpcode },
+    /// The servo responded, but reported that the request failed.
+    #[error("axis {axis:?} failed to execute {opcode:?}")]
+    Rejected { axis: Axis, opcode: ServoOpcode },
+    /// The servo strayed too far from its speed profile, so the motion was stopped.
+    #[error("axis {axis:?} is {error} rotations away from its speed profile")]
+    FollowingError { axis: Axis, error: f64 },
+    /// The request isn't safe in the current state of the axis, so it wasn't sent.
+    #[error("axis {axis:?} is {state:?}, but this needs it to be {needs:?}")]
+    State {
+        axis: Axis,
+        state: State,
+        needs: State,
+    },
+}
+
+/// The state of an axis, or of the whole arm, as far as the [`Arm`] has seen.
+///
+/// States are ordered by how far an axis has been brought up, except for `Faulted`, which allows
+/// nothing but reads, disabling and initializing the axis again.
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    /// Nothing is known about the servo yet.
+    #[default]
+    Unknown,
+    /// The servo has been initialized, but its motor isn't enabled.
+    Configured,
+    /// The motor is enabled, but the axis hasn't been homed, so its origin may be anywhere.
+    Enabled,
+    /// The axis has been homed (or its origin set), but may still be moving.
+    Homed,
+    /// The axis has been homed, and is at rest.
+    Ready,
+    /// A command to the servo failed, so the axis has to be initialized again.
+    Faulted,
+}
+
+/// What is known about an axis, from acknowledgements and status reads.
+#[derive(Clone, Copy, Debug, Default)]
+struct Tracked {
+    configured: bool,
+    enabled: bool,
+    homed: bool,
+    at_rest: bool,
+    faulted: bool,
+}
+
+impl Tracked {
+    /// Faults the axis.  It may have been left anywhere, so it has to be homed again, and may
+    /// still be moving.
+    fn fault(&mut self) {
+        self.faulted = true;
+        self.homed = false;
+        self.at_rest = false;
+    }
+}
+
+/// A motor position, as reported by the motor encoder.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize)]
+pub struct MotorPosition {
+    /// Raw encoder value, where `0x4000` is a full turn.
+    pub raw: i64,
+    /// Number of servo rotations from origin.
+    pub rotations: f64,
+}
+
+/// A single telemetry sample from an axis motor.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+pub struct Telemetry {
+    #[serde(flatten)]
+    pub position: MotorPosition,
+    /// Current speed in RPM.
+    pub speed: i16,
+    /// Current difference from the set position, in `0..51200 ~= 0..360 deg`.
+    pub position_error: i32,
+    /// The motor status, or `None` if the motor failed to report its status.
+    pub status: Option<servo_cmd::MotorStatus>,
+    pub in_1: bool,
+    pub in_2: bool,
+    pub out_1: bool,
+    pub out_2: bool,
+}
+
+/// A client for an Arctos arm, connected to its CAN bus.
+///
+/// The arm keeps track of the [`State`] of every axis, and refuses requests that aren't safe in
+/// the current state, such as moving an axis that hasn't been homed; see
+/// [`Arm::without_state_checks`].
+pub struct Arm {
+    bus: sync::Arc<bus::Dispatcher>,
+    states: sync::Arc<sync::Mutex<collections::BTreeMap<Axis, Tracked>>>,
+    checked: bool,
+}
+
+/// A client for a single axis of an [`Arm`].
+#[derive(Clone, Copy)]
+pub struct ServoAxis<'a> {
+    bus: &'a bus::Dispatcher,
+    states: &'a sync::Mutex<collections::BTreeMap<Axis, Tracked>>,
+    checked: bool,
+    axis: Axis,
+}
+
+/// Exclusive access to an axis for the duration of a multi-step operation.
+struct Session<'a> {
+    axis: Axis,
+    channel: bus::Channel,
+    _guard: tokio::sync::MutexGuard<'a, ()>,
+}
+
+impl MotorPosition {
+    pub fn from_raw(raw: i64) -> Self {
+        Self {
+            raw,
+            rotations: raw as f64 / 0x4000 as f64,
+        }
+    }
+}
+
+impl Arm {
+    pub fn new(bus: bus::Dispatcher) -> Self {
+        Self {
+            bus: sync::Arc::new(bus),
+            states: Default::default(),
+            checked: true,
+        }
+    }
+
+    /// The same arm, sharing its bus and states, but sending requests regardless of the states
+    /// of the axes.  States are still tracked.
+    pub fn without_state_checks(&self) -> Self {
+        Self {
+            bus: self.bus.clone(),
+            states: self.states.clone(),
+            checked: false,
+        }
+    }
+
+    /// The state of the whole arm: `Faulted` if any axis is, or else the state of the axis that
+    /// is the least far along.
+    pub fn state(&self) -> State {
+        let states = Axis::ALL.map(|axis| self.axis(axis).state());
+        if states.contains(&State::Faulted) {
+            return State::Faulted;
+        }
+        states.into_iter().min().unwrap_or_default()
+    }
+
+    /// Takes the states of all axes that are still unknown from their servos, concurrently; see
+    /// [`ServoAxis::seed`].  Axes that don't answer stay unknown.
+    pub async fn seed_states(&self) {
+        future::join_all(Axis::ALL.map(|axis| async move {
+            if let Err(err) = self.axis(axis).seed().await {
+                tracing::warn!("state of axis {axis:?} is unknown: {err}");
+            }
+        }))
+        .await;
+    }
+
+    /// Opens the CAN network interface with the given name.
+    ///
+    /// The returned future drives the bus (see [`bus::Dispatcher::new`]), and must be polled for
+    /// as long as the arm is in use.
+    pub fn open(
+        ifname: &str,
+    ) -> Result<(Self, impl future::Future<Output = Result<(), Error>>), Error> {
+        use stream::StreamExt as _;
+
+        let socket = socketcan::tokio::CanSocket::open(ifname).map_err(|e| Error::Bus(e.into()))?;
+        let (can_tx, can_rx) = socket.split();
+        let (bus, pump) = bus::Dispatcher::new(can_tx, can_rx);
+        Ok((Self::new(bus), pump))
+    }
+
+    /// Creates an arm for the given CAN socket halves; see [`bus::Dispatcher::new`].
+    pub fn from_socket<Tx, Rx, TxE, RxE>(
+        can_tx: Tx,
+        can_rx: Rx,
+    ) -> (Self, impl future::Future<Output = Result<(), Error>>)
+    where
+        Tx: sink::Sink<socketcan::CanFrame, Error = TxE> + Unpin,
+        Rx: stream::Stream<Item = Result<socketcan::CanFrame, RxE>> + Unpin,
+        TxE: std::error::Error + Send + Sync + 'static,
+        RxE: std::error::Error + Send + Sync + 'static,
+    {
+        let (bus, pump) = bus::Dispatcher::new(can_tx, can_rx);
+        (Self::new(bus), pump)
+    }
+
+    /// Creates an arm that replays a log instead of using a CAN network; see
+    /// [`candump::replay`].
+    pub fn replay(
+        entries: Vec<candump::Entry>,
+    ) -> (Self, impl future::Future<Output = Result<(), Error>>) {
+        let (can_tx, can_rx, driver) = candump::replay(entries);
+        let (arm, pump) = Self::from_socket(can_tx, can_rx);
+        let pump = async move {
+            let ((), result) = future::join(driver, pump).await;
+            result
+        };
+        (arm, pump)
+    }
+
+    /// Creates an arm with simulated servos instead of a CAN network; see [`sim::simulate`].
+    pub fn simulated() -> (Self, impl future::Future<Output = Result<(), Error>>) {
+        let (can_tx, can_rx, driver) = sim::simulate();
+        let (arm, pump) = Self::from_socket(can_tx, can_rx);
+        let pump = async move {
+            let ((), result) = future::join(driver, pump).await;
+            result
+        };
+        (arm, pump)
+    }
+
+    pub fn bus(&self) -> &bus::Dispatcher {
+        &self.bus
+    }
+
+    pub fn axis(&self, axis: Axis) -> ServoAxis<'_> {
+        ServoAxis {
+            bus: &self.bus,
+            states: &self.states,
+            checked: self.checked,
+            axis,
+        }
+    }
+}
+
+impl<'a> ServoAxis<'a> {
+    pub fn axis(&self) -> Axis {
+        self.axis
+    }
+
+    /// Sends a request to the servo, and returns its (first) response.
+    ///
+    /// Requests that command the axis to do something need the same state as the methods that
+    /// send them, and the state of the axis is updated from the response.
+    pub async fn request(&self, request: ServoRequest) -> Result<ServoResponse, Error> {
+        let (needs, commands) = requirements(&request);
+        if let Some(needs) = needs {
+            self.check(needs)?;
+        }
+        let result = self.session().await.request(request).await;
+        let update = |tracked: &mut Tracked, response: &ServoResponse| {
+            acknowledge(tracked, &request, response)
+        };
+        if commands {
+            self.track(result, update)
+        } else {
+            self.observe(result, update)
+        }
+    }
+
+    /// Initializes (configures settings for) the axis motor.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn init(&self) -> Result<(), Error> {
+        let result = async {
+            let mut session = self.session().await;
+            session
+                .expect_success(ServoRequest::SetWorkMode {
+                    work_mode: servo_cmd::WorkMode::SrVFoc,
+                })
+                .await?;
+            tracing::info!("set SR_vFOC work mode: success");
+            session
+                .expect_success(ServoRequest::SetAutoSSD { enable: true })
+                .await?;
+            tracing::info!("turn off display: success");
+            Ok(())
+        }
+        .await;
+        // Whatever the axis did before, it has to be homed again after being initialized.
+        self.track(result, |tracked, _| {
+            *tracked = Tracked {
+                configured: true,
+                enabled: tracked.enabled,
+                ..Tracked::default()
+            }
+        })
+    }
+
+    /// Enables (powers on) the axis motor.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn enable(&self) -> Result<(), Error> {
+        self.check(State::Configured)?;
+        let result = async {
+            let mut session = self.session().await;
+            session
+                .expect_success(ServoRequest::Enable { enabled: true })
+                .await?;
+            tracing::info!("enable: success");
+            Ok(())
+        }
+        .await;
+        self.track(result, |tracked, _| tracked.enabled = true)
+    }
+
+    /// Disables (powers off) the axis motor, so that it no longer holds its position.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn disable(&self) -> Result<(), Error> {
+        let result = async {
+            let mut session = self.session().await;
+            session
+                .expect_success(ServoRequest::Enable { enabled: false })
+                .await?;
+            tracing::info!("disable: success");
+            Ok(())
+        }
+        .await;
+        self.track(result, |tracked, _| tracked.enabled = false)
+    }
+
+    /// Stops the axis motor right away, whatever it was doing, so that it holds its position.
+    /// Like disabling, stopping is allowed in any state, to get an axis out of trouble.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn stop(&self) -> Result<(), Error> {
+        let result = async {
+            let mut session = self.session().await;
+            session.run_speed(0.0).await?;
+            tracing::info!("stop: success");
+            Ok(())
+        }
+        .await;
+        // The motor may still be slowing down, until its status says otherwise.
+        self.track(result, |tracked, _| tracked.at_rest = false)
+    }
+
+    /// Sets the origin of the axis to wherever the motor currently is.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn set_origin(&self) -> Result<(), Error> {
+        self.check(State::Configured)?;
+        let result = async {
+            let mut session = self.session().await;
+            session.expect_success(ServoRequest::SetAxisZero).await?;
+            tracing::info!("set origin: success");
+            Ok(())
+        }
+        .await;
+        self.track(result, |tracked, _| tracked.homed = true)
+    }
+
+    /// Moves the axis motor to its home position, using the homing settings of the servo, and
+    /// waits for it to get there.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn go_home(&self) -> Result<(), Error> {
+        self.check(State::Enabled)?;
+        let result = async {
+            let mut session = self.session().await;
+            let mut response = session.request(ServoRequest::GoHome).await?;
+            loop {
+                let ServoResponse::GoHome { progress } = response else {
+                    unreachable!("responses are matched by opcode")
+                };
+                tracing::info!("go home: {progress:?}");
+                match progress {
+                    servo_cmd::ProgressStatus::Busy => {
+                        response = session
+                            .response(ServoOpcode::GoHome, MOTION_TIMEOUT)
+                            .await?;
+                    }
+                    servo_cmd::ProgressStatus::Success => return Ok(()),
+                    servo_cmd::ProgressStatus::Fail => {
+                        return Err(Error::Rejected {
+                            axis: self.axis,
+                            opcode: ServoOpcode::GoHome,
+                        })
+                    }
+                }
+            }
+        }
+        .await;
+        self.track(result, |tracked, _| {
+            tracked.homed = true;
+            tracked.at_rest = true;
+        })
+    }
+
+    /// Reads the current position of the axis motor.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn position(&self) -> Result<MotorPosition, Error> {
+        let result = async {
+            let mut session = self.session().await;
+            let position = session.position().await?;
+            tracing::info!("read encoder value: {}", position.raw);
+            Ok(position)
+        }
+        .await;
+        // A failed read says nothing about the axis, so there is nothing to track.
+        result
+    }
+
+    /// Moves the axis motor to an absolute position, in number of servo rotations from origin,
+    /// and waits for the motion to complete.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn move_to(&self, position: f64, speed: u16, accel: u8) -> Result<(), Error> {
+        self.check(State::Homed)?;
+        let result = async {
+            let mut session = self.session().await;
+            let request = ServoRequest::RunPositionAbsoluteMotionMode {
+                speed,
+                accel,
+                abs_axis: (position * 0x4000 as f64) as i32,
+            };
+            let mut response = session.request(request).await?;
+            loop {
+                let status = motion_status(&response);
+                tracing::info!("set axis pos: {status:?}");
+                match status {
+                    Some(servo_cmd::MotionStatus::Busy) => {
+                        response = session.response(request.opcode(), MOTION_TIMEOUT).await?;
+                    }
+                    Some(servo_cmd::MotionStatus::Success) => return Ok(()),
+                    Some(servo_cmd::MotionStatus::LimitReached) => {
+                        tracing::warn!(
+                            "endstop triggered when trying to set axis position to {position} \
+                             for axis {:?}",
+                            self.axis
+                        );
+                        return Ok(());
+                    }
+                    Some(servo_cmd::MotionStatus::Fail) | None => {
+                        return Err(Error::Rejected {
+                            axis: self.axis,
+                            opcode: request.opcode(),
+                        })
+                    }
+                }
+            }
+        }
+        .await;
+        self.track(result, |tracked, _| tracked.at_rest = true)
+    }
+
+    /// Starts moving the axis motor to an absolute position, in number of servo rotations from
+    /// origin, without waiting for the motion to complete.
+    ///
+    /// A new motion can be started before the previous one completes, so this can be used to
+    /// stream setpoints to the motor.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn start_move_to(&self, position: f64, speed: u16, accel: u8) -> Result<(), Error> {
+        self.check(State::Homed)?;
+        let result = async {
+            let mut session = self.session().await;
+            let request = ServoRequest::RunPositionAbsoluteMotionMode {
+                speed,
+                accel,
+                abs_axis: (position * 0x4000 as f64) as i32,
+            };
+            let response = session.request(request).await?;
+            match motion_status(&response) {
+                Some(servo_cmd::MotionStatus::Busy | servo_cmd::MotionStatus::Success) => Ok(()),
+                Some(servo_cmd::MotionStatus::LimitReached) => {
+                    tracing::warn!(
+                        "endstop triggered when trying to set axis position to {position}"
+                    );
+                    Ok(())
+                }
+                Some(servo_cmd::MotionStatus::Fail) | None => Err(Error::Rejected {
+                    axis: self.axis,
+                    opcode: request.opcode(),
+                }),
+            }
+        }
+        .await;
+        self.track(result, |tracked, _| tracked.at_rest = false)
+    }
+
+    /// Moves the axis motor to an absolute position, in number of servo rotations from origin,
+    /// following a speed profile generated on the host, and waits for the motion to complete.
+    ///
+    /// The speed is streamed to the servo in speed mode every [`PROFILE_PERIOD`], corrected by
+    /// the difference between the planned and the measured position.  The servo then moves to the
+    /// exact position on its own, to make up for whatever difference is left.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn move_profiled(
+        &self,
+        position: f64,
+        shape: motion::Shape,
+        limits: &motion::Limits,
+    ) -> Result<(), Error> {
+        self.check(State::Homed)?;
+        let result = async {
+            {
+                let mut session = self.session().await;
+                let start = session.position().await?.rotations;
+                let profile = motion::SpeedProfile::new(position - start, shape, limits);
+                tracing::info!("following profile for {}s", profile.duration());
+
+                let mut interval = time::interval(PROFILE_PERIOD);
+                interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+                let started = time::Instant::now();
+                let streamed = async {
+                    loop {
+                        interval.tick().await;
+                        let elapsed = started.elapsed().as_secs_f64();
+                        if elapsed >= profile.duration() {
+                            return Ok(());
+                        }
+                        let measured = session.position().await?.rotations;
+                        let error = start + profile.position(elapsed) - measured;
+                        if error.abs() > MAX_FOLLOWING_ERROR {
+                            return Err(Error::FollowingError {
+                                axis: self.axis,
+                                error,
+                            });
+                        }
+                        session
+                            .run_speed(profile.speed(elapsed) + POSITION_GAIN * error)
+                            .await?;
+                    }
+                }
+                .await;
+                // Stop however streaming ended, or the servo keeps running at the last speed.
+                let stopped = session.run_speed(0.0).await;
+                streamed?;
+                stopped?;
+            }
+            self.move_to(
+                position,
+                self.axis.default_speed(),
+                self.axis.default_accel(),
+            )
+            .await
+        }
+        .await;
+        self.track(result, |_, _| {})
+    }
+
+    /// Reads a full telemetry sample from the axis motor.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn telemetry(&self) -> Result<Telemetry, Error> {
+        let result = async {
+            let mut session = self.session().await;
+            let position = session.position().await?;
+            let ServoResponse::ReadSpeed { speed } =
+                session.request(ServoRequest::ReadSpeed).await?
+            else {
+                unreachable!("responses are matched by opcode")
+            };
+            let ServoResponse::ReadError {
+                error: position_error,
+            } = session.request(ServoRequest::ReadError).await?
+            else {
+                unreachable!("responses are matched by opcode")
+            };
+            let ServoResponse::QueryStatus { status } =
+                session.request(ServoRequest::QueryStatus).await?
+            else {
+                unreachable!("responses are matched by opcode")
+            };
+            let ServoResponse::ReadIOPorts {
+                in_1,
+                in_2,
+                out_1,
+                out_2,
+            } = session.request(ServoRequest::ReadIOPorts).await?
+            else {
+                unreachable!("responses are matched by opcode")
+            };
+
+            Ok(Telemetry {
+                position,
+                speed,
+                position_error,
+                status,
+                in_1,
+                in_2,
+                out_1,
+                out_2,
+            })
+        }
+        .await;
+        self.observe(result, |tracked, telemetry| {
+            tracked.at_rest = telemetry.status == Some(servo_cmd::MotorStatus::MotorStopped);
+        })
+    }
+
+    /// Reads the status of the motor.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn status(&self) -> Result<Option<servo_cmd::MotorStatus>, Error> {
+        let result = async {
+            let mut session = self.session().await;
+            match session.request(ServoRequest::QueryStatus).await? {
+                ServoResponse::QueryStatus { status } => {
+                    tracing::info!("query status: {status:?}");
+                    Ok(status)
+                }
+                _ => unreachable!("responses are matched by opcode"),
+            }
+        }
+        .await;
+        self.observe(result, |tracked, status| {
+            tracked.at_rest = *status == Some(servo_cmd::MotorStatus::MotorStopped);
+        })
+    }
+
+    /// Takes the state of an axis that the arm hasn't seen yet from the servo itself, for axes
+    /// that were brought up before the arm was created, such as by an earlier command.
+    ///
+    /// A servo that answers is taken to be configured and homed, since it keeps its settings and
+    /// its origin (which can't be read back) until it is power cycled, and its `en` pin tells
+    /// whether its motor is enabled.  Axes whose state is already known are left as they are.
+    #[tracing::instrument(skip(self), fields(axis = ?self.axis))]
+    pub async fn seed(&self) -> Result<State, Error> {
+        if self.state() != State::Unknown {
+            return Ok(self.state());
+        }
+        let result = async {
+            let mut session = self.session().await;
+            let ServoResponse::ReadEnPin { enabled } =
+                session.request(ServoRequest::ReadEnPin).await?
+            else {
+                unreachable!("responses are matched by opcode")
+            };
+            let ServoResponse::QueryStatus { status } =
+                session.request(ServoRequest::QueryStatus).await?
+            else {
+                unreachable!("responses are matched by opcode")
+            };
+            Ok((enabled, status))
+        }
+        .await;
+        self.observe(result, |tracked, &(enabled, status)| {
+            *tracked = Tracked {
+                configured: true,
+                enabled,
+                homed: true,
+                at_rest: status == Some(servo_cmd::MotorStatus::MotorStopped),
+                faulted: false,
+            };
+        })?;
+        Ok(self.state())
+    }
+
+    /// The state of the axis, as far as the arm has seen.
+    pub fn state(&self) -> State {
+        let tracked = self.tracked(|tracked| *tracked);
+        if tracked.faulted {
+            State::Faulted
+        } else if !tracked.configured {
+            State::Unknown
+        } else if !tracked.enabled {
+            State::Configured
+        } else if !tracked.homed {
+            State::Enabled
+        } else if !tracked.at_rest {
+            State::Homed
+        } else {
+            State::Ready
+        }
+    }
+
+    /// Fails if the axis isn't in state `needs` yet, or is faulted, unless states aren't checked.
+    fn check(&self, needs: State) -> Result<(), Error> {
+        let state = self.state();
+        if self.checked && (state == State::Faulted || state < needs) {
+            return Err(Error::State {
+                axis: self.axis,
+                state,
+                needs,
+            });
+        }
+        Ok(())
+    }
+
+    /// Updates the state of the axis from the outcome of a command: on success with `update`,
+    /// and on failure by faulting the axis, since it may have been left anywhere.
+    fn track<T>(
+        &self,
+        result: Result<T, Error>,
+        update: impl FnOnce(&mut Tracked, &T),
+    ) -> Result<T, Error> {
+        match &result {
+            Ok(value) => self.tracked(|tracked| update(tracked, value)),
+            Err(err) => {
+                tracing::warn!("axis {:?} faulted: {err}", self.axis);
+                self.tracked(Tracked::fault);
+            }
+        }
+        result
+    }
+
+    /// Updates the state of the axis from the outcome of a read, if it succeeded.  A read that
+    /// timed out or missed frames says nothing about the axis, so the state is left as it was.
+    fn observe<T>(
+        &self,
+        result: Result<T, Error>,
+        update: impl FnOnce(&mut Tracked, &T),
+    ) -> Result<T, Error> {
+        if let Ok(value) = &result {
+            self.tracked(|tracked| update(tracked, value));
+        }
+        result
+    }
+
+    fn tracked<R>(&self, f: impl FnOnce(&mut Tracked) -> R) -> R {
+        let mut states = self
+            .states
+            .lock()
+            .unwrap_or_else(sync::PoisonError::into_inner);
+        f(states.entry(self.axis).or_default())
+    }
+
+    async fn session(&self) -> Session<'a> {
+        let guard = self.bus.lock(self.axis).await;
+        Session {
+            axis: self.axis,
+            channel: self.bus.channel(),
+            _guard: guard,
+        }
+    }
+}
+
+impl Session<'_> {
+    async fn request(&mut self, request: ServoRequest) -> Result<ServoResponse, Error> {
+        let frame = request.to_frame(self.axis.id())?;
+        self.channel.send(frame).await?;
+        self.response(request.opcode(), RESPONSE_TIMEOUT).await
+    }
+
+    async fn expect_success(&mut self, request: ServoRequest) -> Result<(), Error> {
+        let response = self.request(request).await?;
+        if succeeded(&response) == Some(false) {
+            return Err(Error::Rejected {
+                axis: self.axis,
+                opcode: request.opcode(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn position(&mut self) -> Result<MotorPosition, Error> {
+        match self.request(ServoRequest::ReadEncoderValueAddition).await? {
+            ServoResponse::ReadEncoderValueAddition { value } => Ok(MotorPosition::from_raw(value)),
+            _ => unreachable!("responses are matched by opcode"),
+        }
+    }
+
+    /// Runs the motor at a speed in RPM, where positive speeds make the encoder count up, until
+    /// the next speed is set.
+    async fn run_speed(&mut self, speed: f64) -> Result<(), Error> {
+        let dir = if speed < 0.0 {
+            servo_cmd::Direction::CCW
+        } else {
+            servo_cmd::Direction::CW
+        };
+        // The speed is ramped on the host, so the servo should change speed right away.
+        let request = ServoRequest::RunSpeedMode {
+            dir,
+            speed: speed.abs().round().min(MAX_SPEED) as u16,
+            acc: 0,
+        };
+        let response = self.request(request).await?;
+        // The servo acknowledges speed mode with a status of 1, which decodes as busy.
+        if matches!(
+            motion_status(&response),
+            Some(servo_cmd::MotionStatus::Fail) | None
+        ) {
+            return Err(Error::Rejected {
+                axis: self.axis,
+                opcode: request.opcode(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Waits for the next response from the servo with the given opcode.
+    async fn response(
+        &mut self,
+        opcode: ServoOpcode,
+        timeout: time::Duration,
+    ) -> Result<ServoResponse, Error> {
+        use socketcan::EmbeddedFrame as _;
+
+        let axis = self.axis;
+        let await_response = async {
+            loop {
+                let frame = self.channel.recv().await?;
+                if frame.id() != axis.id() {
+                    continue;
+                }
+                match ServoResponse::from_frame(axis.id(), &frame) {
+                    Ok(response) if response.opcode() == opcode => return Ok(response),
+                    Ok(_) => {}
+                    // The servo answered, but with something we can't make sense of.
+                    Err(
+                        err @ (ProtocolError::TooShort { opcode: o, .. }
+                        | ProtocolError::InvalidValue { opcode: o, .. }),
+                    ) if o == opcode => return Err(err.into()),
+                    // Corrupted frames and frames for other opcodes might just be noise, so keep
+                    // waiting for a proper response.
+                    Err(err) => tracing::warn!("ignoring frame from axis {axis:?}: {err}"),
+                }
+            }
+        };
+        time::timeout(timeout, await_response)
+            .await
+            .unwrap_or(Err(Error::Timeout { axis, opcode }))
+    }
+}
+
+/// Whether the servo reported success, for responses that carry a success flag.
+fn succeeded(response: &ServoResponse) -> Option<bool> {
+    match *response {
+        ServoResponse::ReleaseMotorShaft { success }
+        | ServoResponse::SetWorkMode { success }
+        | ServoResponse::SetCurrent { success }
+        | ServoResponse::SetSubdivision { success }
+        | ServoResponse::SetEnPinActiveMode { success }
+        | ServoResponse::SetDir { success }
+        | ServoResponse::SetAutoSSD { success }
+        | ServoResponse::SetMotorShaftLockedRotor { success }
+        | ServoResponse::SetSubdivisionInterpolation { success }
+        | ServoResponse::SetCanBitRate { success }
+        | ServoResponse::SetCanId { success }
+        | ServoResponse::SetCanEnableResponses { success }
+        | ServoResponse::SetKeyLocked { success }
+        | ServoResponse::SetGroupId { success }
+        | ServoResponse::SetHome { success }
+        | ServoResponse::SetAxisZero { success }
+        | ServoResponse::SetZeroOnPowerOnMode { success }
+        | ServoResponse::RestoreDefaults { success }
+        | ServoResponse::Enable { success }
+        | ServoResponse::SaveRunModeParams { success } => Some(success),
+        _ => None,
+    }
+}
+
+/// What a raw request needs: the state that the axis has to be in first, if any, and whether the
+/// request commands the axis to do something, so that its failure faults the axis.
+fn requirements(request: &ServoRequest) -> (Option<State>, bool) {
+    match *request {
+        // Disabling and stopping are always allowed, to get an axis out of trouble.
+        ServoRequest::Enable { enabled: false } | ServoRequest::RunSpeedMode { speed: 0, .. } => {
+            (None, true)
+        }
+        ServoRequest::Enable { enabled: true } | ServoRequest::SetAxisZero => {
+            (Some(State::Configured), true)
+        }
+        // Calibrating turns the motor, like homing does.
+        ServoRequest::GoHome | ServoRequest::Calibrate => (Some(State::Enabled), true),
+        ServoRequest::RunSpeedMode { .. }
+        | ServoRequest::RunPositionRelativePulsesMode { .. }
+        | ServoRequest::RunPositionRelativeMotionMode { .. }
+        | ServoRequest::RunPositionAbsoluteMotionMode { .. } => (Some(State::Homed), true),
+        _ => (None, false),
+    }
+}
+
+/// Updates what is known about an axis from the response to a raw request.
+fn acknowledge(tracked: &mut Tracked, request: &ServoRequest, response: &ServoResponse) {
+    use servo_cmd::ProgressStatus;
+
+    match *response {
+        ServoResponse::Enable { success: true } => {
+            if let ServoRequest::Enable { enabled } = *request {
+                tracked.enabled = enabled;
+            }
+        }
+        ServoResponse::SetAxisZero { success: true } => tracked.homed = true,
+        ServoResponse::GoHome {
+            progress: ProgressStatus::Success,
+        } => {
+            tracked.homed = true;
+            tracked.at_rest = true;
+        }
+        ServoResponse::GoHome {
+            progress: ProgressStatus::Busy,
+        }
+        | ServoResponse::Calibrate {
+            status: ProgressStatus::Busy,
+        } => tracked.at_rest = false,
+        ServoResponse::Enable { success: false }
+        | ServoResponse::SetAxisZero { success: false }
+        | ServoResponse::GoHome {
+            progress: ProgressStatus::Fail,
+        }
+        | ServoResponse::Calibrate {
+            status: ProgressStatus::Fail,
+        } => tracked.fault(),
+        // The servo forgets its settings, so it has to be initialized again.
+        ServoResponse::RestoreDefaults { success: true } => *tracked = Tracked::default(),
+        ServoResponse::QueryStatus { status } => {
+            tracked.at_rest = status == Some(servo_cmd::MotorStatus::MotorStopped);
+        }
+        _ => match motion_status(response) {
+            Some(servo_cmd::MotionStatus::Fail) => tracked.fault(),
+            // Even a stopping servo may still be slowing down, until its status says otherwise.
+            Some(_) => tracked.at_rest = false,
+            None => {}
+        },
+    }
+}
+
+/// The motion status, for responses to motion requests.
+fn motion_status(response: &ServoResponse) -> Option<servo_cmd::MotionStatus> {
+    match *response {
+        ServoResponse::RunSpeedMode { status }
+        | ServoResponse::RunPositionRelativePulsesMode { status }
+        | ServoResponse::RunPositionRelativeMotionMode { status }
+        | ServoResponse::RunPositionAbsoluteMotionMode { status } => Some(status),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that a request was refused, because the axis was in `state` instead of `needs`.
+    fn assert_refused<T: std::fmt::Debug>(result: Result<T, Error>, state: State, needs: State) {
+        match result {
+            Err(Error::State {
+                axis: Axis::X,
+                state: s,
+                needs: n,
+            }) => assert_eq!((s, n), (state, needs)),
+            result => panic!("expected the request to be refused, got {result:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn state_transitions() {
+        let (arm, pump) = Arm::simulated();
+        let pump = tokio::spawn(pump);
+        let axis = arm.axis(Axis::X);
+        let (speed, accel) = (Axis::X.default_speed(), Axis::X.default_accel());
+
+        assert_eq!(axis.state(), State::Unknown);
+        axis.init().await.unwrap();
+        assert_eq!(axis.state(), State::Configured);
+        axis.enable().await.unwrap();
+        assert_eq!(axis.state(), State::Enabled);
+        axis.go_home().await.unwrap();
+        assert_eq!(axis.state(), State::Ready);
+        axis.start_move_to(1.0, speed, accel).await.unwrap();
+        assert_eq!(axis.state(), State::Homed);
+        axis.status().await.unwrap();
+        assert_eq!(axis.state(), State::Ready);
+        // The arm is only as far along as the axes that haven't been touched.
+        assert_eq!(arm.state(), State::Unknown);
+
+        // Raw requests are tracked from their acknowledgements.
+        axis.request(ServoRequest::Enable { enabled: false })
+            .await
+            .unwrap();
+        assert_eq!(axis.state(), State::Configured);
+        axis.request(ServoRequest::Enable { enabled: true })
+            .await
+            .unwrap();
+        assert_eq!(axis.state(), State::Ready);
+        axis.request(ServoRequest::RunPositionAbsoluteMotionMode {
+            speed,
+            accel,
+            abs_axis: 0,
+        })
+        .await
+        .unwrap();
+        assert_eq!(axis.state(), State::Homed);
+        axis.request(ServoRequest::QueryStatus).await.unwrap();
+        assert_eq!(axis.state(), State::Ready);
+        pump.abort();
+    }
+
+    #[tokio::test]
+    async fn move_refused_before_homing() {
+        let (arm, pump) = Arm::simulated();
+        let pump = tokio::spawn(pump);
+        let axis = arm.axis(Axis::X);
+        let (speed, accel) = (Axis::X.default_speed(), Axis::X.default_accel());
+        let motion = ServoRequest::RunPositionAbsoluteMotionMode {
+            speed,
+            accel,
+            abs_axis: 0x4000,
+        };
+
+        assert_refused(axis.enable().await, State::Unknown, State::Configured);
+        assert_refused(
+            axis.request(ServoRequest::Enable { enabled: true }).await,
+            State::Unknown,
+            State::Configured,
+        );
+        axis.init().await.unwrap();
+        axis.request(ServoRequest::Enable { enabled: true })
+            .await
+            .unwrap();
+        assert_refused(
+            axis.move_to(1.0, speed, accel).await,
+            State::Enabled,
+            State::Homed,
+        );
+        assert_refused(axis.request(motion).await, State::Enabled, State::Homed);
+        // Nothing was sent, so the axis didn't move.
+        assert_eq!(axis.position().await.unwrap().raw, 0);
+
+        // Stopping is always allowed, and skipping the checks lets the motion through.
+        let stop = ServoRequest::RunSpeedMode {
+            dir: servo_cmd::Direction::CW,
+            speed: 0,
+            acc: accel,
+        };
+        axis.request(stop).await.unwrap();
+        let unchecked = arm.without_state_checks();
+        unchecked.axis(Axis::X).request(motion).await.unwrap();
+        assert_eq!(axis.position().await.unwrap().raw, 0x4000);
+        assert_eq!(axis.state(), State::Enabled);
+        pump.abort();
+    }
+
+    /// A tap that keeps the frames sent to the servos.
+    #[derive(Clone, Default)]
+    struct Sent(sync::Arc<sync::Mutex<Vec<socketcan::CanFrame>>>);
+
+    impl bus::Tap for Sent {
+        fn frame(
+            &mut self,
+            direction: bus::Direction,
+            frame: &socketcan::CanFrame,
+        ) -> std::io::Result<()> {
+            if direction == bus::Direction::Sent {
+                self.0.lock().unwrap().push(*frame);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn profiled_move_stops_on_failure() {
+        use socketcan::EmbeddedFrame as _;
+        use stream::StreamExt as _;
+
+        // Only the first two position reads are answered, so that a read fails while streaming.
+        let (can_tx, can_rx, driver) = sim::simulate();
+        let mut reads = 0;
+        let can_rx = can_rx.filter(move |frame| {
+            let read = ServoOpcode::ReadEncoderValueAddition as u8;
+            if matches!(frame, Ok(frame) if frame.data().first() == Some(&read)) {
+                reads += 1;
+            }
+            future::ready(reads <= 2)
+        });
+        let (arm, pump) = Arm::from_socket(can_tx, can_rx);
+        let pump = tokio::spawn(future::join(driver, pump));
+        let sent = Sent::default();
+        arm.bus().tap(sent.clone());
+
+        let arm = arm.without_state_checks();
+        let limits = motion::Limits {
+            speed: 100.0,
+            accel: 100.0,
+            jerk: 1000.0,
+        };
+        let result = arm
+            .axis(Axis::X)
+            .move_profiled(10.0, motion::Shape::Trapezoid, &limits)
+            .await;
+        let read = ServoOpcode::ReadEncoderValueAddition;
+        assert!(
+            matches!(result, Err(Error::Timeout { opcode, .. }) if opcode == read),
+            "{result:?}"
+        );
+        let last = *sent.0.lock().unwrap().last().unwrap();
+        assert_eq!(
+            ServoRequest::from_frame(last.id(), &last),
+            Ok(ServoRequest::RunSpeedMode {
+                dir: servo_cmd::Direction::CW,
+                speed: 0,
+                acc: 0,
+            })
+        );
+        pump.abort();
+    }
+
+    #[tokio::test]
+    async fn init_clears_fault() {
+        let (arm, pump) = Arm::simulated();
+        let pump = tokio::spawn(pump);
+        let axis = arm.axis(Axis::X);
+        axis.init().await.unwrap();
+        axis.enable().await.unwrap();
+        axis.set_origin().await.unwrap();
+        axis.tracked(Tracked::fault);
+        assert_eq!(axis.state(), State::Faulted);
+        assert_eq!(arm.state(), State::Faulted);
+
+        // Reads and disabling are still allowed, but nothing else.
+        axis.status().await.unwrap();
+        assert_refused(axis.enable().await, State::Faulted, State::Configured);
+        assert_refused(axis.go_home().await, State::Faulted, State::Enabled);
+        axis.disable().await.unwrap();
+        assert_eq!(axis.state(), State::Faulted);
+
+        // The axis may have been left anywhere, so it has to be homed again.
+        axis.init().await.unwrap();
+        assert_eq!(axis.state(), State::Configured);
+        axis.enable().await.unwrap();
+        assert_eq!(axis.state(), State::Enabled);
+        pump.abort();
+    }
+
+    #[tokio::test]
+    async fn seeded_states() {
+        let (arm, pump) = Arm::simulated();
+        let pump = tokio::spawn(pump);
+        arm.axis(Axis::Y)
+            .request(ServoRequest::Enable { enabled: false })
+            .await
+            .unwrap();
+        assert_eq!(arm.axis(Axis::Y).state(), State::Unknown);
+
+        // Servos that answer are taken to be homed, so enabling is all that is left to do.
+        arm.seed_states().await;
+        assert_eq!(arm.axis(Axis::X).state(), State::Ready);
+        assert_eq!(arm.axis(Axis::Y).state(), State::Configured);
+        arm.axis(Axis::Y).enable().await.unwrap();
+        assert_eq!(arm.axis(Axis::Y).state(), State::Ready);
+
+        // States that are already known are kept.
+        arm.axis(Axis::X).tracked(Tracked::fault);
+        assert_eq!(arm.axis(Axis::X).seed().await.unwrap(), State::Faulted);
+        pump.abort();
+    }
+}
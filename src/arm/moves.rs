@@ -0,0 +1,915 @@
+//! Motions and sequences that coordinate several axes of an [`Arm`], such as moving to joint
+//! angles so that all axes finish together, following a straight line, or bringing up the arm.
+//!
+//! Every motion goes through the requests of [`ServoAxis`], so the states of the axes are
+//! checked and tracked as usual.
+use std::collections;
+
+use futures::future;
+use tokio::time;
+
+use super::{Arm, ServoAxis};
+use crate::{kinematics, motion, servo_cmd, Axis};
+
+/// Speed of the servo of a gripper in RPM.
+const GRIPPER_SPEED: u16 = 300;
+/// Raw acceleration of the servo of a gripper.
+const GRIPPER_ACCEL: u8 = 236;
+/// How long to wait for a gripper to open or close.
+const GRIPPER_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Arm(#[from] super::Error),
+    #[error(transparent)]
+    Kinematics(#[from] kinematics::Error),
+    /// One of the axes of a motion failed, so the motion didn't go on.
+    #[error("axis {axis:?} failed")]
+    Axis {
+        axis: Axis,
+        #[source]
+        source: super::Error,
+    },
+    /// A speed or rate isn't a positive number.
+    #[error("{name} must be a positive number, got {value}")]
+    NotPositive { name: &'static str, value: f64 },
+    /// The tool strayed too far from a straight line, so the axes were stopped where they were.
+    #[error("tool strayed {deviation:.1} mm from the line, more than {max_deviation} mm; stopped")]
+    Deviation { deviation: f64, max_deviation: f64 },
+    #[error("the robot profile has no startup sequence")]
+    NoStartupSequence,
+    #[error("step {step} of the startup sequence has no axes")]
+    EmptyStartupStep { step: usize },
+    #[error("axis {axis:?} is in the startup sequence more than once")]
+    RepeatedStartupAxis { axis: Axis },
+    /// An axis that was brought up is still moving.
+    #[error("motor is not at rest, its status is {status:?}")]
+    NotAtRest {
+        status: Option<servo_cmd::MotorStatus>,
+    },
+    /// An axis that was brought up is outside of the soft limits of its joint.
+    #[error("joint angle {angle:.1}° is outside of the soft limits {min}° to {max}°")]
+    OutsideLimits { angle: f64, min: f64, max: f64 },
+    #[error("not a standard CAN id: {id}")]
+    InvalidGripperId { id: u16 },
+    #[error("the gripper didn't finish moving in time")]
+    GripperTimeout,
+    #[error("the gripper failed to move")]
+    GripperFailed,
+}
+
+/// The result of an action on one of several axes.
+#[derive(Debug)]
+pub struct Outcome<T, E = super::Error> {
+    pub axis: Axis,
+    pub result: Result<T, E>,
+    pub elapsed: time::Duration,
+}
+
+/// The position that an axis is moved to, to reach joint angles.
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct JointTarget {
+    /// The joint angle in degrees.
+    pub angle: f64,
+    /// The raw position in number of servo rotations from origin.
+    pub position: f64,
+    #[serde(flatten)]
+    pub ramp: motion::Ramp,
+}
+
+/// The positions of all axes at some time, in number of servo rotations from origin.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct PositionSample {
+    /// Seconds from the first sample.
+    pub time: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+}
+
+/// Progress of a path, after a setpoint has been sent.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize)]
+pub struct PathProgress {
+    pub step: usize,
+    #[serde(flatten)]
+    pub target: PositionSample,
+}
+
+/// Progress of a straight-line move, after a setpoint should have been reached.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize)]
+pub struct LinearProgress {
+    pub step: usize,
+    #[serde(flatten)]
+    pub target: kinematics::Pose,
+    /// Distance of the tool from the setpoint, in millimeters.
+    pub deviation: f64,
+}
+
+/// How far a stage of bringing up or shutting down an axis got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Done,
+    Failed,
+    /// Not attempted, because an earlier stage failed.
+    Skipped,
+    /// Not part of the sequence.
+    Unused,
+}
+
+/// How far bringing up an axis got, after the startup sequence has stopped.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct StartupReport {
+    /// Number of the step of the sequence, starting at 1.
+    pub step: usize,
+    pub axis: Axis,
+    pub success: bool,
+    pub init: Stage,
+    pub enable: Stage,
+    pub home: Stage,
+    pub verify: Stage,
+    /// Joint angle in degrees, once verified.
+    pub angle: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// Whether an axis is still powered after the arm has been shut down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerState {
+    Disabled,
+    /// Left as it was, because shutting down stopped before the axis was disabled.
+    Unchanged,
+}
+
+/// How far shutting down an axis got.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct ShutdownReport {
+    pub axis: Axis,
+    pub success: bool,
+    pub park: Stage,
+    pub disable: Stage,
+    pub state: PowerState,
+    pub error: Option<String>,
+}
+
+impl PositionSample {
+    pub fn new(time: f64, positions: &motion::Positions) -> Self {
+        let [x, y, z, a, b, c] = *positions;
+        Self {
+            time,
+            x,
+            y,
+            z,
+            a,
+            b,
+            c,
+        }
+    }
+
+    pub fn positions(&self) -> motion::Positions {
+        [self.x, self.y, self.z, self.a, self.b, self.c]
+    }
+}
+
+/// Runs `action` for all of the axes concurrently.
+pub async fn par_map<'a, F, R, T>(arm: &'a Arm, axes: Vec<Axis>, action: F) -> Vec<Outcome<T>>
+where
+    F: Fn(ServoAxis<'a>) -> R,
+    R: future::Future<Output = Result<T, super::Error>>,
+{
+    let action = &action;
+    future::join_all(axes.into_iter().map(|axis| async move {
+        let start = time::Instant::now();
+        let result = action(arm.axis(axis)).await;
+        Outcome {
+            axis,
+            result,
+            elapsed: start.elapsed(),
+        }
+    }))
+    .await
+}
+
+/// Fails with the error of the first outcome that failed, if any.
+pub fn ensure_success<T>(outcomes: Vec<Outcome<T>>) -> Result<(), Error> {
+    for outcome in outcomes {
+        if let Err(source) = outcome.result {
+            return Err(Error::Axis {
+                axis: outcome.axis,
+                source,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The default speed and acceleration of an axis.
+pub fn default_ramp(axis: Axis) -> motion::Ramp {
+    motion::Ramp {
+        speed: axis.default_speed(),
+        accel: axis.default_accel(),
+    }
+}
+
+/// The ramp that turns the joint of an axis at `speed` degrees per second, or the default ramp
+/// of the axis if no speed is given.
+pub fn joint_ramp(
+    profile: &kinematics::Profile,
+    axis: Axis,
+    speed: Option<f64>,
+) -> Result<motion::Ramp, kinematics::Error> {
+    let mut ramp = default_ramp(axis);
+    if let Some(speed) = speed {
+        ramp.speed = (speed / 360.0 * 60.0 * profile.gear_ratio(axis)?.abs())
+            .round()
+            .clamp(1.0, super::MAX_SPEED) as u16;
+    }
+    Ok(ramp)
+}
+
+/// Reads the positions of all axes.
+pub async fn read_positions(arm: &Arm) -> Result<motion::Positions, super::Error> {
+    let positions =
+        future::try_join_all(Axis::ALL.map(|axis| async move { arm.axis(axis).position().await }))
+            .await?;
+    let mut rotations = motion::Positions::default();
+    for (rotations, position) in rotations.iter_mut().zip(positions) {
+        *rotations = position.rotations;
+    }
+    Ok(rotations)
+}
+
+/// Reads the positions of all axes, and converts them to joint angles.
+pub async fn read_joints(
+    arm: &Arm,
+    profile: &kinematics::Profile,
+) -> Result<kinematics::Joints, Error> {
+    let positions = read_positions(arm).await?;
+    let mut joints = kinematics::Joints::default();
+    for ((joint, axis), rotations) in joints.iter_mut().zip(Axis::ALL).zip(positions) {
+        *joint = profile.joint_angle(axis, rotations)?;
+    }
+    Ok(joints)
+}
+
+/// Picks the speed and acceleration for moving each axis to a position, from the fastest `limit`
+/// that the axis may use.
+///
+/// With `sync`, the axes with less travel are slowed down so that all axes start and finish
+/// together, which needs the current positions of the axes.
+pub async fn plan_ramps(
+    arm: &Arm,
+    targets: &[(Axis, f64)],
+    limit: impl Fn(Axis) -> motion::Ramp,
+    sync: bool,
+) -> Result<collections::BTreeMap<Axis, motion::Ramp>, super::Error> {
+    if !sync {
+        return Ok(targets
+            .iter()
+            .map(|&(axis, _)| (axis, limit(axis)))
+            .collect());
+    }
+    let positions = future::try_join_all(
+        targets
+            .iter()
+            .map(|&(axis, _)| async move { arm.axis(axis).position().await }),
+    )
+    .await?;
+    let motions = targets
+        .iter()
+        .zip(positions)
+        .map(|(&(axis, target), current)| (target - current.rotations, limit(axis)))
+        .collect::<Vec<_>>();
+    Ok(targets
+        .iter()
+        .map(|&(axis, _)| axis)
+        .zip(motion::synchronize(&motions))
+        .collect())
+}
+
+/// Moves axes to raw positions, with the fastest ramp for each axis given by `limit`; see
+/// [`plan_ramps`] for `sync`.
+pub async fn move_axes(
+    arm: &Arm,
+    targets: &[(Axis, f64)],
+    limit: impl Fn(Axis) -> motion::Ramp,
+    sync: bool,
+) -> Result<Vec<Outcome<motion::Ramp>>, Error> {
+    let ramps = &plan_ramps(arm, targets, limit, sync).await?;
+    let positions = &targets
+        .iter()
+        .copied()
+        .collect::<collections::BTreeMap<_, _>>();
+    let axes = targets.iter().map(|&(axis, _)| axis).collect();
+    Ok(par_map(arm, axes, |a| async move {
+        let ramp = ramps[&a.axis()];
+        a.move_to(positions[&a.axis()], ramp.speed, ramp.accel)
+            .await?;
+        Ok(ramp)
+    })
+    .await)
+}
+
+/// Moves axes to a raw position, each following a speed profile with the `limits` of the axis;
+/// see [`ServoAxis::move_profiled`].
+pub async fn move_profiled(
+    arm: &Arm,
+    axes: Vec<Axis>,
+    position: f64,
+    shape: motion::Shape,
+    limits: impl Fn(Axis) -> motion::Limits,
+) -> Vec<Outcome<motion::Limits>> {
+    let limits = &limits;
+    par_map(arm, axes, |a| async move {
+        let limits = limits(a.axis());
+        a.move_profiled(position, shape, &limits).await?;
+        Ok(limits)
+    })
+    .await
+}
+
+/// Moves axes to joint angles in degrees, so that they all start and finish together, with the
+/// fastest ramp for each axis given by `limit`.
+///
+/// Nothing moves if any of the angles is outside of the soft limits of its joint.
+pub async fn move_joints(
+    arm: &Arm,
+    profile: &kinematics::Profile,
+    joints: &[(Axis, f64)],
+    limit: impl Fn(Axis) -> motion::Ramp,
+) -> Result<Vec<Outcome<JointTarget>>, Error> {
+    let positions = joints
+        .iter()
+        .map(|&(axis, angle)| {
+            profile.check_limit(axis, angle)?;
+            Ok((axis, profile.motor_position(axis, angle)?))
+        })
+        .collect::<Result<Vec<_>, kinematics::Error>>()?;
+    let ramps = plan_ramps(arm, &positions, limit, true).await?;
+    let targets = &joints
+        .iter()
+        .zip(&positions)
+        .map(|(&(axis, angle), &(_, position))| {
+            let ramp = ramps[&axis];
+            (
+                axis,
+                JointTarget {
+                    angle,
+                    position,
+                    ramp,
+                },
+            )
+        })
+        .collect::<collections::BTreeMap<_, _>>();
+    let axes = joints.iter().map(|&(axis, _)| axis).collect();
+    Ok(par_map(arm, axes, |a| async move {
+        let target = targets[&a.axis()];
+        a.move_to(target.position, target.ramp.speed, target.ramp.accel)
+            .await?;
+        Ok(target)
+    })
+    .await)
+}
+
+/// Starts moving all axes from one setpoint to the next, at the speeds that reach it by the time
+/// of the setpoint after it, `1 / rate` seconds later.
+pub async fn stream_setpoint(
+    arm: &Arm,
+    from: &motion::Positions,
+    to: &motion::Positions,
+    rate: f64,
+) -> Result<(), super::Error> {
+    future::try_join_all(
+        Axis::ALL
+            .into_iter()
+            .enumerate()
+            .map(|(i, axis)| async move {
+                let speed = ((to[i] - from[i]).abs() * rate * 60.0)
+                    .ceil()
+                    .clamp(1.0, super::MAX_SPEED);
+                arm.axis(axis)
+                    .start_move_to(to[i], speed as u16, axis.default_accel())
+                    .await
+            }),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Samples the positions of all axes `rate` times per second, passing every sample to
+/// `on_sample`, until reading or `on_sample` fails.
+pub async fn sample_positions<E: From<Error>>(
+    arm: &Arm,
+    rate: f64,
+    mut on_sample: impl FnMut(PositionSample) -> Result<(), E>,
+) -> Result<(), E> {
+    check_positive("sampling rate", rate)?;
+    let mut interval = time::interval(time::Duration::from_secs_f64(1.0 / rate));
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+    let start = time::Instant::now();
+    loop {
+        interval.tick().await;
+        let time = start.elapsed().as_secs_f64();
+        let positions = read_positions(arm).await.map_err(Error::from)?;
+        on_sample(PositionSample::new(time, &positions))?;
+    }
+}
+
+/// Moves all axes to the start of a path, and then along it at `speed` times its timing, by
+/// streaming `rate` setpoints per second.  `on_progress` is called after every setpoint.
+pub async fn play_path<E: From<Error>>(
+    arm: &Arm,
+    path: &motion::Spline,
+    speed: f64,
+    rate: f64,
+    mut on_progress: impl FnMut(PathProgress) -> Result<(), E>,
+) -> Result<(), E> {
+    check_positive("setpoint rate", rate)?;
+    check_positive("playback speed", speed)?;
+    // Go to the start first, so that the first setpoint is close to where the axes are.
+    let start = Axis::ALL
+        .into_iter()
+        .zip(path.position(0.0))
+        .collect::<Vec<_>>();
+    ensure_success(move_axes(arm, &start, default_ramp, true).await?)?;
+
+    let steps = (path.duration() / speed * rate).ceil() as usize;
+    let mut interval = time::interval(time::Duration::from_secs_f64(1.0 / rate));
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    let mut previous = path.position(0.0);
+    for step in 1..=steps {
+        interval.tick().await;
+        let time = (step as f64 / rate * speed).min(path.duration());
+        let target = path.position(time);
+        stream_setpoint(arm, &previous, &target, rate)
+            .await
+            .map_err(Error::from)?;
+        on_progress(PathProgress {
+            step,
+            target: PositionSample::new(time, &target),
+        })?;
+        previous = target;
+    }
+    Ok(())
+}
+
+/// Moves the tool to a pose along a straight line at `speed` millimeters per second, by
+/// streaming `rate` setpoints per second.
+///
+/// Every setpoint is checked once the next one is due, and `on_progress` is called with its
+/// deviation from the line.  If the tool strays more than `max_deviation` millimeters, all axes
+/// are stopped where they are.
+pub async fn move_linear<E: From<Error>>(
+    arm: &Arm,
+    profile: &kinematics::Profile,
+    pose: &kinematics::Pose,
+    speed: f64,
+    rate: f64,
+    max_deviation: f64,
+    mut on_progress: impl FnMut(LinearProgress) -> Result<(), E>,
+) -> Result<(), E> {
+    check_positive("setpoint rate", rate)?;
+    check_positive("speed", speed)?;
+    let current = read_joints(arm, profile).await?;
+    let start = profile.forward(&current).map_err(Error::from)?;
+    let path = profile
+        .linear_path(&start, pose, &current, speed / rate)
+        .map_err(Error::from)?;
+
+    let mut interval = time::interval(time::Duration::from_secs_f64(1.0 / rate));
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    let mut previous: Option<(kinematics::Pose, kinematics::Joints)> = None;
+    // Check the setpoint of every step on the next one, and the last one after the end.
+    for (step, sample) in path.iter().map(Some).chain([None]).enumerate() {
+        interval.tick().await;
+        if let Some((target, _)) = previous {
+            let deviation = check_deviation(arm, profile, &target, max_deviation).await?;
+            on_progress(LinearProgress {
+                step,
+                target,
+                deviation,
+            })?;
+        }
+        let Some(&(target, joints)) = sample else {
+            break;
+        };
+        let from = previous.map_or(current, |(_, joints)| joints);
+        let (mut from_positions, mut positions): (motion::Positions, motion::Positions) =
+            Default::default();
+        for (i, axis) in Axis::ALL.into_iter().enumerate() {
+            from_positions[i] = profile.motor_position(axis, from[i]).map_err(Error::from)?;
+            positions[i] = profile
+                .motor_position(axis, joints[i])
+                .map_err(Error::from)?;
+        }
+        stream_setpoint(arm, &from_positions, &positions, rate)
+            .await
+            .map_err(Error::from)?;
+        previous = Some((target, joints));
+    }
+    Ok(())
+}
+
+/// Reads back the pose of the tool, and returns its distance from `target` in millimeters.
+///
+/// If the distance is larger than `max_deviation`, all axes are stopped where they are, and an
+/// error is returned.
+pub async fn check_deviation(
+    arm: &Arm,
+    profile: &kinematics::Profile,
+    target: &kinematics::Pose,
+    max_deviation: f64,
+) -> Result<f64, Error> {
+    let joints = read_joints(arm, profile).await?;
+    let actual = profile.forward(&joints)?;
+    let deviation = ((actual.x - target.x).powi(2)
+        + (actual.y - target.y).powi(2)
+        + (actual.z - target.z).powi(2))
+    .sqrt();
+    if deviation > max_deviation {
+        // Hold the current position, instead of finishing the last setpoint.
+        future::try_join_all(
+            Axis::ALL
+                .into_iter()
+                .zip(joints)
+                .map(|(axis, angle)| async move {
+                    let position = profile.motor_position(axis, angle)?;
+                    arm.axis(axis)
+                        .start_move_to(position, axis.default_speed(), axis.default_accel())
+                        .await?;
+                    Ok::<_, Error>(())
+                }),
+        )
+        .await?;
+        return Err(Error::Deviation {
+            deviation,
+            max_deviation,
+        });
+    }
+    Ok(deviation)
+}
+
+/// Moves the servo of a gripper to a position, in number of servo rotations from origin, and
+/// waits for it to get there.
+///
+/// The gripper is not one of the axes, so its requests are sent on the bus directly, and the
+/// arm doesn't track its state: it isn't brought up with the axes, and its servo only turns
+/// within the travel of the gripper, so no state of the arm makes its moves unsafe.
+pub async fn move_gripper(
+    arm: &Arm,
+    gripper: &kinematics::Gripper,
+    position: f64,
+) -> Result<(), Error> {
+    use socketcan::EmbeddedFrame as _;
+
+    let id =
+        socketcan::StandardId::new(gripper.id).ok_or(Error::InvalidGripperId { id: gripper.id })?;
+    let id = socketcan::Id::Standard(id);
+    let request = servo_cmd::ServoRequest::RunPositionAbsoluteMotionMode {
+        speed: GRIPPER_SPEED,
+        accel: GRIPPER_ACCEL,
+        abs_axis: (position * 0x4000 as f64) as i32,
+    };
+    let mut channel = arm.bus().channel();
+    channel
+        .send(request.to_frame(id).map_err(super::Error::from)?)
+        .await?;
+    let deadline = time::Instant::now() + GRIPPER_TIMEOUT;
+    loop {
+        let frame = time::timeout_at(deadline, channel.recv())
+            .await
+            .map_err(|_| Error::GripperTimeout)??;
+        if frame.id() != id {
+            continue;
+        }
+        let Ok(servo_cmd::ServoResponse::RunPositionAbsoluteMotionMode { status }) =
+            servo_cmd::ServoResponse::from_frame(id, &frame)
+        else {
+            continue;
+        };
+        match status {
+            servo_cmd::MotionStatus::Busy => {}
+            servo_cmd::MotionStatus::Success => return Ok(()),
+            servo_cmd::MotionStatus::LimitReached => {
+                tracing::warn!("endstop triggered when moving the gripper to {position}");
+                return Ok(());
+            }
+            servo_cmd::MotionStatus::Fail => return Err(Error::GripperFailed),
+        }
+    }
+}
+
+/// Brings up the axes step by step, in the order of the startup sequence of `profile`,
+/// returning a report for every axis of the sequence.
+///
+/// Every stage runs for all axes of a step at once, and the sequence stops as soon as any of them
+/// fails.  Axes that were already brought up stay enabled, so that they keep holding the arm.
+pub async fn startup(
+    arm: &Arm,
+    profile: &kinematics::Profile,
+) -> Result<Vec<StartupReport>, Error> {
+    if profile.startup.is_empty() {
+        return Err(Error::NoStartupSequence);
+    }
+    let mut reports = Vec::<StartupReport>::new();
+    for (index, step) in profile.startup.iter().enumerate() {
+        if step.axes.is_empty() {
+            return Err(Error::EmptyStartupStep { step: index + 1 });
+        }
+        for &axis in &step.axes {
+            if reports.iter().any(|report| report.axis == axis) {
+                return Err(Error::RepeatedStartupAxis { axis });
+            }
+            reports.push(StartupReport {
+                step: index + 1,
+                axis,
+                success: false,
+                init: Stage::Skipped,
+                enable: Stage::Skipped,
+                home: if step.home {
+                    Stage::Skipped
+                } else {
+                    Stage::Unused
+                },
+                verify: Stage::Skipped,
+                angle: None,
+                error: None,
+            });
+        }
+    }
+
+    for (index, step) in profile.startup.iter().enumerate() {
+        tracing::info!("startup step {}: {:?}", index + 1, step.axes);
+        let axes = &step.axes;
+        let outcomes = par_map(arm, axes.clone(), |a| async move { a.init().await }).await;
+        if !record_stage(&mut reports, "init", |r| &mut r.init, &outcomes) {
+            break;
+        }
+        let outcomes = par_map(arm, axes.clone(), |a| async move { a.enable().await }).await;
+        if !record_stage(&mut reports, "enable", |r| &mut r.enable, &outcomes) {
+            break;
+        }
+        if step.home {
+            let outcomes = par_map(arm, axes.clone(), |a| async move { a.go_home().await }).await;
+            if !record_stage(&mut reports, "home", |r| &mut r.home, &outcomes) {
+                break;
+            }
+        }
+        let outcomes = par_map(arm, axes.clone(), |a| async move {
+            Ok((a.status().await?, a.position().await?.rotations))
+        })
+        .await
+        .into_iter()
+        .map(|outcome| Outcome {
+            axis: outcome.axis,
+            result: outcome
+                .result
+                .map_err(Error::from)
+                .and_then(|(status, rotations)| {
+                    verify_axis(profile, outcome.axis, status, rotations)
+                }),
+            elapsed: outcome.elapsed,
+        })
+        .collect::<Vec<_>>();
+        let verified = record_stage(&mut reports, "verify", |r| &mut r.verify, &outcomes);
+        for outcome in &outcomes {
+            if let Ok(angle) = outcome.result {
+                let report = startup_report(&mut reports, outcome.axis);
+                report.angle = Some(angle);
+                report.success = true;
+            }
+        }
+        if !verified {
+            break;
+        }
+    }
+    Ok(reports)
+}
+
+/// Moves the axes to the `park` joint angles and waits for them to get there, and then disables
+/// all axes one at a time, from the wrist to the base, so that nothing drops.  Returns a report
+/// for every axis.
+///
+/// Shutting down stops at the first failure, leaving the remaining axes enabled.
+pub async fn shutdown(
+    arm: &Arm,
+    profile: &kinematics::Profile,
+    park: &collections::BTreeMap<Axis, f64>,
+) -> Result<Vec<ShutdownReport>, Error> {
+    let mut reports = Axis::ALL
+        .into_iter()
+        .rev()
+        .map(|axis| ShutdownReport {
+            axis,
+            success: false,
+            park: if park.contains_key(&axis) {
+                Stage::Skipped
+            } else {
+                Stage::Unused
+            },
+            disable: Stage::Skipped,
+            state: PowerState::Unchanged,
+            error: None,
+        })
+        .collect::<Vec<_>>();
+
+    let joints = park
+        .iter()
+        .map(|(&axis, &angle)| (axis, angle))
+        .collect::<Vec<_>>();
+    let mut parked = true;
+    for outcome in move_joints(arm, profile, &joints, default_ramp).await? {
+        let report = reports
+            .iter_mut()
+            .find(|report| report.axis == outcome.axis)
+            .expect("every axis has a report");
+        match outcome.result {
+            Ok(_) => report.park = Stage::Done,
+            Err(err) => {
+                let error = chain(&err);
+                tracing::error!("parking axis {:?} failed: {error}", outcome.axis);
+                report.park = Stage::Failed;
+                report.error = Some(format!("park: {error}"));
+                parked = false;
+            }
+        }
+    }
+
+    if parked {
+        for report in &mut reports {
+            match arm.axis(report.axis).disable().await {
+                Ok(()) => {
+                    report.disable = Stage::Done;
+                    report.state = PowerState::Disabled;
+                    report.success = true;
+                }
+                Err(err) => {
+                    let error = chain(&err);
+                    tracing::error!("disabling axis {:?} failed: {error}", report.axis);
+                    report.disable = Stage::Failed;
+                    report.error = Some(format!("disable: {error}"));
+                    break;
+                }
+            }
+        }
+    }
+    Ok(reports)
+}
+
+/// Records the outcomes of a stage of bringing up axes in their reports, returning whether all
+/// of them succeeded.
+fn record_stage<T, E: std::error::Error + 'static>(
+    reports: &mut [StartupReport],
+    name: &str,
+    stage: fn(&mut StartupReport) -> &mut Stage,
+    outcomes: &[Outcome<T, E>],
+) -> bool {
+    let mut success = true;
+    for outcome in outcomes {
+        let report = startup_report(reports, outcome.axis);
+        match &outcome.result {
+            Ok(_) => *stage(report) = Stage::Done,
+            Err(err) => {
+                let error = chain(err);
+                tracing::error!("{name} of axis {:?} failed: {error}", outcome.axis);
+                *stage(report) = Stage::Failed;
+                report.error = Some(format!("{name}: {error}"));
+                success = false;
+            }
+        }
+    }
+    success
+}
+
+fn startup_report(reports: &mut [StartupReport], axis: Axis) -> &mut StartupReport {
+    reports
+        .iter_mut()
+        .find(|report| report.axis == axis)
+        .expect("every axis of the startup sequence has a report")
+}
+
+/// Checks that an axis that was brought up is at rest, within the soft limits of its joint, and
+/// returns its joint angle.
+fn verify_axis(
+    profile: &kinematics::Profile,
+    axis: Axis,
+    status: Option<servo_cmd::MotorStatus>,
+    rotations: f64,
+) -> Result<f64, Error> {
+    if status != Some(servo_cmd::MotorStatus::MotorStopped) {
+        return Err(Error::NotAtRest { status });
+    }
+    let angle = profile.joint_angle(axis, rotations)?;
+    if let Some((min, max)) = profile.limits(axis)? {
+        if !(min..=max).contains(&angle) {
+            return Err(Error::OutsideLimits { angle, min, max });
+        }
+    }
+    Ok(angle)
+}
+
+/// Fails unless `value` is a positive number.
+fn check_positive(name: &'static str, value: f64) -> Result<(), Error> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err(Error::NotPositive { name, value })
+    }
+}
+
+/// An error followed by its causes, separated by colons.
+pub fn chain(err: &(dyn std::error::Error + 'static)) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message = format!("{message}: {err}");
+        source = err.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arm::State;
+
+    /// A profile with the gearing of the axes, limits of a turn either way, and a startup
+    /// sequence that brings up the wrist first.
+    fn profile() -> kinematics::Profile {
+        let joint = kinematics::Joint {
+            gear_ratio: Some(10.0),
+            limits: Some((-360.0, 360.0)),
+            ..kinematics::Joint::default()
+        };
+        kinematics::Profile {
+            joints: Axis::ALL.map(|axis| (axis, joint)).into(),
+            startup: vec![
+                kinematics::StartupStep {
+                    axes: vec![Axis::C, Axis::B, Axis::A],
+                    home: true,
+                },
+                kinematics::StartupStep {
+                    axes: vec![Axis::Z, Axis::Y, Axis::X],
+                    home: true,
+                },
+            ],
+            ..kinematics::Profile::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn startup_and_shutdown() {
+        let (arm, pump) = Arm::simulated();
+        let pump = tokio::spawn(pump);
+        let profile = profile();
+
+        let reports = startup(&arm, &profile).await.unwrap();
+        assert_eq!(
+            reports.iter().map(|r| r.axis).collect::<Vec<_>>(),
+            [Axis::C, Axis::B, Axis::A, Axis::Z, Axis::Y, Axis::X]
+        );
+        for report in &reports {
+            assert!(report.success, "{report:?}");
+            assert_eq!(report.angle, Some(0.0));
+        }
+        assert_eq!(arm.state(), State::Ready);
+
+        let park = Axis::ALL.map(|axis| (axis, 9.0)).into();
+        let reports = shutdown(&arm, &profile, &park).await.unwrap();
+        for report in &reports {
+            assert!(report.success, "{report:?}");
+            assert_eq!(report.state, PowerState::Disabled);
+        }
+        let positions = read_positions(&arm).await.unwrap();
+        assert_eq!(positions, [0.25; 6]);
+        pump.abort();
+    }
+
+    #[tokio::test]
+    async fn joints_outside_of_limits_move_nothing() {
+        let (arm, pump) = Arm::simulated();
+        let pump = tokio::spawn(pump);
+        let profile = profile();
+        startup(&arm, &profile).await.unwrap();
+
+        let joints = [(Axis::X, 90.0), (Axis::Y, 400.0)];
+        let result = move_joints(&arm, &profile, &joints, default_ramp).await;
+        assert!(
+            matches!(
+                result,
+                Err(Error::Kinematics(kinematics::Error::OutOfLimits {
+                    axis: Axis::Y,
+                    ..
+                }))
+            ),
+            "{result:?}"
+        );
+        assert_eq!(read_positions(&arm).await.unwrap(), [0.0; 6]);
+        pump.abort();
+    }
+}
@@ -0,0 +1,79 @@
+/// One of the six axes of the arm, each driven by its own servo motor.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+    A,
+    B,
+    C,
+}
+
+impl Axis {
+    /// All axes, from the base to the tool.
+    pub const ALL: [Axis; 6] = [Axis::X, Axis::Y, Axis::Z, Axis::A, Axis::B, Axis::C];
+
+    /// The CAN id of the servo motor driving this axis.
+    pub fn id(&self) -> embedded_can::Id {
+        match *self {
+            Axis::X => embedded_can::Id::Standard(embedded_can::StandardId::new(1).unwrap()),
+            Axis::Y => embedded_can::Id::Standard(embedded_can::StandardId::new(2).unwrap()),
+            Axis::Z => embedded_can::Id::Standard(embedded_can::StandardId::new(3).unwrap()),
+            Axis::A => embedded_can::Id::Standard(embedded_can::StandardId::new(4).unwrap()),
+            Axis::B => embedded_can::Id::Standard(embedded_can::StandardId::new(5).unwrap()),
+            Axis::C => embedded_can::Id::Standard(embedded_can::StandardId::new(6).unwrap()),
+        }
+    }
+
+    /// The axis whose servo motor has the given CAN id, if any.
+    pub fn from_id(id: embedded_can::Id) -> Option<Axis> {
+        Axis::ALL.into_iter().find(|axis| axis.id() == id)
+    }
+
+    pub fn default_speed(&self) -> u16 {
+        match *self {
+            Axis::X => 300,
+            Axis::Y => 300,
+            Axis::Z => 300,
+            Axis::A => 500,
+            Axis::B => 500,
+            Axis::C => 500,
+        }
+    }
+
+    pub fn default_accel(&self) -> u8 {
+        match *self {
+            Axis::X => 176,
+            Axis::Y => 176,
+            Axis::Z => 176,
+            Axis::A => 216,
+            Axis::B => 236,
+            Axis::C => 236,
+        }
+    }
+
+    pub fn gearing_factor(&self) -> Option<f64> {
+        match *self {
+            Axis::X => Some(13.6),
+            Axis::Y => None,
+            Axis::Z => None,
+            Axis::A => Some(5.1),
+            Axis::B => None,
+            Axis::C => None,
+        }
+    }
+
+    pub fn actuation_range(&self) -> Option<(f64, f64)> {
+        match *self {
+            Axis::X => None,
+            Axis::Y => Some((-60.0, 30.0)),
+            Axis::Z => Some((50.0, 0.0)),
+            Axis::A => None,
+            Axis::B => None,
+            Axis::C => None,
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections;
+use std::io;
+use std::sync;
+
+use futures::{future, sink, stream};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::arm;
+use crate::Axis;
+
+/// Shares a single CAN socket between many concurrent operations.
+///
+/// Every frame received from the bus is broadcast to all open channels, and frames sent on any
+/// channel are forwarded to the bus.  Operations on the same axis should hold the lock for that
+/// axis (see [`Dispatcher::lock`]), so that two operations never see each other's responses.
+pub struct Dispatcher {
+    received: broadcast::Sender<socketcan::CanFrame>,
+    outgoing: mpsc::Sender<socketcan::CanFrame>,
+    axis_locks: collections::BTreeMap<Axis, tokio::sync::Mutex<()>>,
+    taps: Taps,
+}
+
+type Taps = sync::Arc<sync::Mutex<Vec<Box<dyn Tap>>>>;
+
+/// Whether a frame was sent to the bus or received from it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Observes every frame that passes between a [`Dispatcher`] and the bus, for example to record a
+/// session to a file.
+pub trait Tap: Send {
+    /// Called for every frame, in the order that frames were sent or received.
+    ///
+    /// If this fails, the tap is removed from the dispatcher.
+    fn frame(&mut self, direction: Direction, frame: &socketcan::CanFrame) -> io::Result<()>;
+}
+
+/// A channel to the bus, that sees all frames received after it was opened.
+pub struct Channel {
+    received: broadcast::Receiver<socketcan::CanFrame>,
+    outgoing: mpsc::Sender<socketcan::CanFrame>,
+}
+
+impl Dispatcher {
+    /// Creates a dispatcher for the given CAN socket halves.
+    ///
+    /// The returned future moves frames between the socket and the dispatcher channels, and must
+    /// be polled for as long as the dispatcher is in use.  It completes when the socket is closed,
+    /// or when the dispatcher and all of its channels have been dropped.
+    pub fn new<Tx, Rx, TxE, RxE>(
+        mut can_tx: Tx,
+        mut can_rx: Rx,
+    ) -> (Self, impl future::Future<Output = Result<(), arm::Error>>)
+    where
+        Tx: sink::Sink<socketcan::CanFrame, Error = TxE> + Unpin,
+        Rx: stream::Stream<Item = Result<socketcan::CanFrame, RxE>> + Unpin,
+        TxE: std::error::Error + Send + Sync + 'static,
+        RxE: std::error::Error + Send + Sync + 'static,
+    {
+        use sink::SinkExt as _;
+        use stream::StreamExt as _;
+
+        let (received, _) = broadcast::channel(64);
+        let (outgoing, mut outgoing_rx) = mpsc::channel(1);
+        let broadcast_tx = received.clone();
+        let taps = Taps::default();
+        let pump_taps = taps.clone();
+        let pump = async move {
+            loop {
+                tokio::select! {
+                    item = can_rx.next() => {
+                        match item.transpose().map_err(|e| arm::Error::Bus(e.into()))? {
+                            Some(frame) => {
+                                notify(&pump_taps, Direction::Received, &frame);
+                                // It's fine for nobody to be listening right now.
+                                let _ = broadcast_tx.send(frame);
+                            }
+                            None => return Ok(()),
+                        }
+                    }
+                    frame = outgoing_rx.recv() => {
+                        // The dispatcher and all of its channels are gone.
+                        let Some(frame) = frame else { return Ok(()) };
+                        can_tx.send(frame).await.map_err(|e| arm::Error::Bus(e.into()))?;
+                        notify(&pump_taps, Direction::Sent, &frame);
+                    }
+                }
+            }
+        };
+        let axis_locks = Axis::ALL
+            .iter()
+            .map(|&axis| (axis, tokio::sync::Mutex::new(())))
+            .collect();
+
+        let dispatcher = Self {
+            received,
+            outgoing,
+            axis_locks,
+            taps,
+        };
+        (dispatcher, pump)
+    }
+
+    /// Opens a new channel to the bus.
+    pub fn channel(&self) -> Channel {
+        Channel {
+            received: self.received.subscribe(),
+            outgoing: self.outgoing.clone(),
+        }
+    }
+
+    /// Starts passing every frame sent or received from now on to the tap.
+    pub fn tap(&self, tap: impl Tap + 'static) {
+        self.taps.lock().unwrap().push(Box::new(tap));
+    }
+
+    /// Waits for exclusive access to an axis.
+    pub async fn lock(&self, axis: Axis) -> tokio::sync::MutexGuard<'_, ()> {
+        self.axis_locks[&axis].lock().await
+    }
+}
+
+impl Channel {
+    pub async fn send(&mut self, frame: socketcan::CanFrame) -> Result<(), arm::Error> {
+        self.outgoing
+            .send(frame)
+            .await
+            .map_err(|_| arm::Error::Closed)
+    }
+
+    pub async fn recv(&mut self) -> Result<socketcan::CanFrame, arm::Error> {
+        self.received.recv().await.map_err(|e| match e {
+            broadcast::error::RecvError::Closed => arm::Error::Closed,
+            broadcast::error::RecvError::Lagged(n) => arm::Error::Lagged(n),
+        })
+    }
+}
+
+fn notify(taps: &Taps, direction: Direction, frame: &socketcan::CanFrame) {
+    taps.lock()
+        .unwrap()
+        .retain_mut(|tap| match tap.frame(direction, frame) {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::warn!("removing failed bus tap: {err}");
+                false
+            }
+        });
+}
@@ -0,0 +1,343 @@
+//! Recording and replaying bus traffic in the log format of `candump -l`.
+//!
+//! Each line of a log holds one frame, for example `(1436509052.249713) can0 001#31AA30`.  Frames
+//! that were recorded by [`Writer`] additionally end with `T` if they were sent by us, or `R` if
+//! they were received from the bus, like `candump -x` does.  Logs of `candump -l` itself have no
+//! direction, and also hold the requests that were sent by us; see [`replay`] for how those are
+//! told apart.
+use std::convert;
+use std::io;
+use std::time;
+
+use futures::{channel::mpsc, future, sink, stream};
+use socketcan::EmbeddedFrame as _;
+
+use crate::{bus, servo_cmd};
+
+/// How long a replay waits at least for us to send a request that is in a log without directions,
+/// before it is taken to be a frame from the bus instead.
+const SEND_GRACE: time::Duration = time::Duration::from_millis(20);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read log")]
+    Io(#[from] io::Error),
+    #[error("malformed log entry on line {line}: {text:?}")]
+    Malformed { line: usize, text: String },
+}
+
+/// A single frame of a log.
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    /// Time since the Unix epoch.
+    pub timestamp: time::Duration,
+    pub direction: Option<bus::Direction>,
+    pub frame: socketcan::CanFrame,
+}
+
+/// A bus tap that writes every frame to a log.
+pub struct Writer<W> {
+    out: W,
+    ifname: String,
+}
+
+impl<W> Writer<W>
+where
+    W: io::Write,
+{
+    /// Creates a writer for frames on the named CAN interface.
+    pub fn new(out: W, ifname: &str) -> Self {
+        Self {
+            out,
+            ifname: ifname.to_owned(),
+        }
+    }
+}
+
+impl<W> bus::Tap for Writer<W>
+where
+    W: io::Write + Send,
+{
+    fn frame(&mut self, direction: bus::Direction, frame: &socketcan::CanFrame) -> io::Result<()> {
+        let timestamp = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let entry = Entry {
+            timestamp,
+            direction: Some(direction),
+            frame: *frame,
+        };
+        writeln!(self.out, "{}", format_entry(&entry, &self.ifname))?;
+        // Flush every frame, so that nothing is lost if the process is killed.
+        self.out.flush()
+    }
+}
+
+/// Formats an entry as a log line, without a trailing newline.
+pub fn format_entry(entry: &Entry, ifname: &str) -> String {
+    let id = match entry.frame.id() {
+        socketcan::Id::Standard(id) => format!("{:03X}", id.as_raw()),
+        socketcan::Id::Extended(id) => format!("{:08X}", id.as_raw()),
+    };
+    let data = entry
+        .frame
+        .data()
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<String>();
+    let direction = match entry.direction {
+        Some(bus::Direction::Sent) => " T",
+        Some(bus::Direction::Received) => " R",
+        None => "",
+    };
+    format!(
+        "({}.{:06}) {ifname} {id}#{data}{direction}",
+        entry.timestamp.as_secs(),
+        entry.timestamp.subsec_micros()
+    )
+}
+
+/// Parses a single log line, or returns `None` if it isn't a valid entry.
+pub fn parse_entry(line: &str) -> Option<Entry> {
+    let mut fields = line.split_whitespace();
+    let timestamp = fields.next()?.strip_prefix('(')?.strip_suffix(')')?;
+    let _ifname = fields.next()?;
+    let (id, data) = fields.next()?.split_once('#')?;
+    let direction = match fields.next() {
+        Some("T") => Some(bus::Direction::Sent),
+        Some("R") => Some(bus::Direction::Received),
+        None => None,
+        Some(_) => return None,
+    };
+
+    let (secs, micros) = timestamp.split_once('.')?;
+    let timestamp = time::Duration::new(secs.parse().ok()?, 0)
+        + time::Duration::from_micros(micros.parse().ok()?);
+    let raw_id = u32::from_str_radix(id, 16).ok()?;
+    let id = if id.len() > 3 {
+        socketcan::Id::Extended(socketcan::ExtendedId::new(raw_id)?)
+    } else {
+        socketcan::Id::Standard(socketcan::StandardId::new(raw_id.try_into().ok()?)?)
+    };
+    // Remote frames (`R` instead of data) and CAN FD frames (`##`) are not used by the servos.
+    if data.len() % 2 != 0 {
+        return None;
+    }
+    let data = (0..data.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(data.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    let frame = socketcan::CanFrame::new(id, &data)?;
+
+    Some(Entry {
+        timestamp,
+        direction,
+        frame,
+    })
+}
+
+/// Reads all entries of a log, skipping empty lines.
+pub fn read(log: impl io::BufRead) -> Result<Vec<Entry>, Error> {
+    let mut entries = Vec::new();
+    for (i, line) in log.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = parse_entry(&line).ok_or_else(|| Error::Malformed {
+            line: i + 1,
+            text: line.clone(),
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Creates a fake bus that replays a log, for use with [`bus::Dispatcher::new`].
+///
+/// Received frames are replayed with the same timing as in the log, but only once the frames
+/// that were sent before them in the log have been sent again, so that responses are replayed
+/// after the requests that caused them.  Sent frames that differ from the log are logged as
+/// warnings.
+///
+/// Frames without a direction are taken to be sent if they decode as a request, and we send the
+/// same frame before the log says it is due (but at least [`SEND_GRACE`] after the frame before
+/// it).  Otherwise they are replayed as received, which is also how responses that look exactly
+/// like their request, such as a successful `Enable`, are told apart from it.
+///
+/// The returned future drives the replay, and must be polled for as long as the bus is in use.
+pub fn replay(
+    entries: Vec<Entry>,
+) -> (
+    impl sink::Sink<socketcan::CanFrame, Error = mpsc::SendError> + Unpin,
+    impl stream::Stream<Item = Result<socketcan::CanFrame, convert::Infallible>> + Unpin,
+    impl future::Future<Output = ()>,
+) {
+    use sink::SinkExt as _;
+    use stream::StreamExt as _;
+
+    let (can_tx, mut sent) = mpsc::channel::<socketcan::CanFrame>(1);
+    let (mut received, can_rx) = mpsc::channel(1);
+    let driver = async move {
+        let mut previous = None;
+        // A frame that was sent by us, but didn't match the direction-less entry it was waited for.
+        let mut pending = None;
+        for entry in entries {
+            let delay = previous
+                .map(|previous| entry.timestamp.saturating_sub(previous))
+                .unwrap_or_default();
+            previous = Some(entry.timestamp);
+
+            match entry.direction {
+                Some(bus::Direction::Sent) => {
+                    let frame = match pending.take() {
+                        Some(frame) => frame,
+                        None => match sent.next().await {
+                            Some(frame) => frame,
+                            None => return,
+                        },
+                    };
+                    if !same_frame(&frame, &entry.frame) {
+                        tracing::warn!(
+                            "replay diverged from log: sent {:?} but expected {:?}",
+                            frame,
+                            entry.frame
+                        );
+                    }
+                }
+                Some(bus::Direction::Received) => {
+                    tokio::time::sleep(delay).await;
+                    if received.send(Ok(entry.frame)).await.is_err() {
+                        return;
+                    }
+                }
+                None => {
+                    let due = tokio::time::Instant::now() + delay;
+                    let id = entry.frame.id();
+                    if servo_cmd::ServoRequest::from_frame(id, &entry.frame).is_ok() {
+                        if pending.is_none() {
+                            match tokio::time::timeout(delay.max(SEND_GRACE), sent.next()).await {
+                                Ok(Some(frame)) => pending = Some(frame),
+                                Ok(None) => return,
+                                Err(_) => {}
+                            }
+                        }
+                        if pending.is_some_and(|frame| same_frame(&frame, &entry.frame)) {
+                            pending = None;
+                            continue;
+                        }
+                    }
+                    tokio::time::sleep_until(due).await;
+                    if received.send(Ok(entry.frame)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        tracing::info!("end of replayed log");
+        if let Some(frame) = pending {
+            tracing::warn!(
+                "replay diverged from log: sent {:?} that isn't in the log",
+                frame
+            );
+        }
+        while let Some(frame) = sent.next().await {
+            tracing::warn!(
+                "replay diverged from log: sent {:?} after end of log",
+                frame
+            );
+        }
+    };
+    (can_tx, can_rx, driver)
+}
+
+fn same_frame(a: &socketcan::CanFrame, b: &socketcan::CanFrame) -> bool {
+    a.id() == b.id() && a.data() == b.data()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arm, servo_cmd::ServoRequest, servo_cmd::ServoResponse, Axis};
+
+    #[test]
+    fn entries_round_trip() {
+        for line in [
+            "(1436509052.249713) can0 001#31AA30",
+            "(1436509052.000001) can0 001#3132 T",
+            "(1436509052.250000) can0 006#F301FA R",
+            "(0.000000) vcan0 12345678# T",
+            "(1436509052.249713) can0 1FFFFFFF#0102030405060708",
+        ] {
+            let entry = parse_entry(line).unwrap();
+            let ifname = line.split_whitespace().nth(1).unwrap();
+            assert_eq!(format_entry(&entry, ifname), line);
+        }
+        let entry = parse_entry("(1436509052.249713) can0 001#3132 T").unwrap();
+        assert_eq!(entry.direction, Some(bus::Direction::Sent));
+        assert_eq!(
+            entry.timestamp,
+            time::Duration::from_micros(1436509052249713)
+        );
+        assert_eq!(entry.frame.data(), [0x31, 0x32]);
+        let entry = parse_entry("(1436509052.249713) can0 12345678#").unwrap();
+        assert_eq!(entry.direction, None);
+        assert!(entry.frame.is_extended());
+    }
+
+    #[test]
+    fn malformed_entries() {
+        for line in [
+            "",
+            "1436509052.249713 can0 001#3132",
+            "(1436509052.249713) can0 0013132",
+            "(1436509052.249713) can0 001#313",
+            "(1436509052.249713) can0 001#R",
+            "(1436509052.249713) can0 001##03132",
+            "(1436509052.249713) can0 001#3132 X",
+            "(1436509052.249713) can0 800#3132",
+        ] {
+            assert!(parse_entry(line).is_none(), "{line:?}");
+        }
+        let log = "(1.000000) can0 001#3132\n\n(2.000000) can0 001#313\n";
+        assert!(matches!(
+            read(log.as_bytes()),
+            Err(Error::Malformed { line: 3, .. })
+        ));
+    }
+
+    /// A log of `candump -l`, without directions, of enabling the X axis and reading its position.
+    /// The response to `Enable` is the same frame as the request.
+    const LOG: &str = "\
+(1436509052.249713) can0 001#F301F5
+(1436509052.250912) can0 001#F301F5
+(1436509052.262008) can0 001#3132
+(1436509052.263150) can0 001#3100000000400072
+";
+
+    #[tokio::test]
+    async fn replay_log_without_directions() {
+        let entries = read(LOG.as_bytes()).unwrap();
+        assert!(entries.iter().all(|entry| entry.direction.is_none()));
+        let (arm, pump) = arm::Arm::replay(entries);
+        let pump = tokio::spawn(pump);
+
+        let axis = arm.without_state_checks();
+        let axis = axis.axis(Axis::X);
+        assert_eq!(
+            axis.request(ServoRequest::Enable { enabled: true })
+                .await
+                .unwrap(),
+            ServoResponse::Enable { success: true }
+        );
+        assert_eq!(
+            axis.request(ServoRequest::ReadEncoderValueAddition)
+                .await
+                .unwrap(),
+            ServoResponse::ReadEncoderValueAddition { value: 0x4000 }
+        );
+        drop(arm);
+        pump.abort();
+    }
+}
@@ -0,0 +1,437 @@
+//! A long-running daemon that owns the CAN bus, and a client for talking to it.
+//!
+//! The daemon speaks JSON-RPC 2.0 over a Unix socket, with one message per line.  Request methods
+//! and parameters are those of [`crate::Request`], and the result of every call is a list of
+//! per-axis records (the same records that the CLI prints).  Streaming requests such as `watch`
+//! additionally send a `record` notification for every record produced, before the final
+//! response.  Requests without an `id` are notifications, which get neither.
+//!
+//! Requests that command the arm run one at a time, whichever client sent them, while requests
+//! that only read from it run alongside them; see [`crate::Request::commands_arm`].  When a client
+//! hangs up, its request is cut short, and if it was commanding the arm, all axes are stopped.
+use std::collections;
+use std::path;
+
+use tokio::io::{AsyncBufReadExt as _, AsyncWriteExt as _};
+use tokio::net::unix;
+use tokio::sync::mpsc;
+
+use arctos_can_driver::arm::{self, moves};
+use arctos_can_driver::Axis;
+
+use crate::output;
+
+const JSONRPC_VERSION: &str = "2.0";
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    /// A number, string or null, that is echoed back unchanged in the response.  Requests
+    /// without one are notifications, which get no response.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_id",
+        skip_serializing_if = "Option::is_none"
+    )]
+    id: Option<serde_json::Value>,
+    #[serde(flatten)]
+    request: crate::Request,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct RpcResponse {
+    jsonrpc: String,
+    id: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Vec<output::Record>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct RpcNotification {
+    jsonrpc: String,
+    method: String,
+    params: output::Record,
+}
+
+/// Deserializes an id that is present, even if it is null, so that it can be told apart from a
+/// missing one.
+fn deserialize_id<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<serde_json::Value>, D::Error> {
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
+
+/// The socket path used when none is specified, which is specific to the CAN interface.
+pub fn default_socket_path(ifname: &str) -> path::PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(path::PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join(format!("arctos-can-driver-{ifname}.sock"))
+}
+
+/// Serves requests on the socket until interrupted.
+pub async fn serve(arm: &arm::Arm, socket_path: &path::Path) -> anyhow::Result<()> {
+    use anyhow::Context as _;
+    use futures::StreamExt as _;
+
+    if socket_path.exists() {
+        if tokio::net::UnixStream::connect(socket_path).await.is_ok() {
+            anyhow::bail!("a daemon is already listening on {}", socket_path.display());
+        }
+        tracing::debug!("removing stale socket {}", socket_path.display());
+        std::fs::remove_file(socket_path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(socket_path)
+        .with_context(|| format!("failed to listen on {}", socket_path.display()))?;
+    tracing::info!("listening on {}", socket_path.display());
+
+    let commanding = tokio::sync::Mutex::new(());
+    let mut connections = futures::stream::FuturesUnordered::new();
+    let result = loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                match accepted {
+                    Ok((stream, _)) => {
+                        connections.push(handle_connection(arm, &commanding, stream));
+                    }
+                    Err(err) => break Err(err.into()),
+                }
+            }
+            Some(result) = connections.next(), if !connections.is_empty() => {
+                if let Err(err) = result {
+                    tracing::warn!("connection failed: {err:#}");
+                }
+            }
+            _ = tokio::signal::ctrl_c() => {
+                tracing::info!("shutting down");
+                break Ok(());
+            }
+        }
+    };
+    std::fs::remove_file(socket_path)?;
+    result
+}
+
+/// Serves the requests of a client, one after the other.  Requests that command the arm first
+/// wait for `commanding`, so that they don't run at the same time as those of other clients.
+#[tracing::instrument(skip_all)]
+async fn handle_connection(
+    arm: &arm::Arm,
+    commanding: &tokio::sync::Mutex<()>,
+    stream: tokio::net::UnixStream,
+) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = tokio::io::BufReader::new(reader).lines();
+    // Requests that arrive while another request is running are handled afterwards, in order.
+    let mut pending = collections::VecDeque::new();
+
+    loop {
+        let line = match pending.pop_front() {
+            Some(line) => line,
+            None => match lines.next_line().await? {
+                Some(line) => line,
+                None => return Ok(()),
+            },
+        };
+
+        let request = match serde_json::from_str::<serde_json::Value>(&line) {
+            Ok(value) => {
+                let id = value.get("id").cloned().unwrap_or_default();
+                serde_json::from_value::<RpcRequest>(value)
+                    .map_err(|err| (id, INVALID_REQUEST, err.to_string()))
+            }
+            Err(err) => Err((serde_json::Value::Null, PARSE_ERROR, err.to_string())),
+        };
+        let request = match request {
+            Ok(request) => request,
+            Err((id, code, message)) => {
+                write_message(&mut writer, &error_response(id, code, message)).await?;
+                continue;
+            }
+        };
+        tracing::debug!("handling request {:?}", request.request);
+
+        let commands = request.request.commands_arm();
+        let _commanding = if commands {
+            Some(commanding.lock().await)
+        } else {
+            None
+        };
+        // Notifications get no response, so their streamed records are dropped too.
+        let id = request.id;
+        let notify = id.is_some();
+        let (notification_tx, mut notification_rx) = mpsc::unbounded_channel();
+        let result = {
+            let execution = crate::execute(arm, request.request, |record| {
+                if notify {
+                    notification_tx
+                        .send(record)
+                        .map_err(|_| anyhow::format_err!("client went away"))?;
+                }
+                Ok(())
+            });
+            tokio::pin!(execution);
+            loop {
+                tokio::select! {
+                    result = &mut execution => break Some(result),
+                    Some(record) = notification_rx.recv() => {
+                        if let Err(err) = write_message(&mut writer, &notification(record)).await {
+                            tracing::warn!("failed to write to the client: {err:#}");
+                            break None;
+                        }
+                    }
+                    line = lines.next_line() => {
+                        match line {
+                            Ok(Some(line)) => pending.push_back(line),
+                            Ok(None) => break None,
+                            Err(err) => {
+                                tracing::warn!("failed to read from the client: {err}");
+                                break None;
+                            }
+                        }
+                    }
+                }
+            }
+        };
+        let Some(result) = result else {
+            // The client hung up, so there is nobody to report the result to, and nobody to
+            // watch over a motion that would go on unattended.
+            if commands {
+                tracing::warn!("client hung up while commanding the arm, stopping all axes");
+                let axes = Axis::ALL.to_vec();
+                for outcome in moves::par_map(arm, axes, |a| async move { a.stop().await }).await {
+                    if let Err(err) = outcome.result {
+                        tracing::error!("stopping axis {:?} failed: {err}", outcome.axis);
+                    }
+                }
+            }
+            return Ok(());
+        };
+        while let Ok(record) = notification_rx.try_recv() {
+            write_message(&mut writer, &notification(record)).await?;
+        }
+
+        let Some(id) = id else {
+            if let Err(err) = result {
+                tracing::warn!("notification failed: {err:#}");
+            }
+            continue;
+        };
+        let response = match result {
+            Ok(records) => RpcResponse {
+                jsonrpc: JSONRPC_VERSION.to_owned(),
+                id,
+                result: Some(records),
+                error: None,
+            },
+            Err(err) => error_response(id, SERVER_ERROR, format!("{err:#}")),
+        };
+        write_message(&mut writer, &response).await?;
+    }
+}
+
+fn notification(record: output::Record) -> RpcNotification {
+    RpcNotification {
+        jsonrpc: JSONRPC_VERSION.to_owned(),
+        method: "record".to_owned(),
+        params: record,
+    }
+}
+
+fn error_response(id: serde_json::Value, code: i64, message: String) -> RpcResponse {
+    RpcResponse {
+        jsonrpc: JSONRPC_VERSION.to_owned(),
+        id,
+        result: None,
+        error: Some(RpcError { code, message }),
+    }
+}
+
+async fn write_message(
+    writer: &mut unix::OwnedWriteHalf,
+    message: &impl serde::Serialize,
+) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(())
+}
+
+/// A connection to a running daemon.
+pub struct Client {
+    lines: tokio::io::Lines<tokio::io::BufReader<unix::OwnedReadHalf>>,
+    writer: unix::OwnedWriteHalf,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connects to the daemon listening on the socket, or returns `None` if there is none.
+    pub async fn connect(socket_path: &path::Path) -> anyhow::Result<Option<Self>> {
+        use anyhow::Context as _;
+
+        let stream = match tokio::net::UnixStream::connect(socket_path).await {
+            Ok(stream) => stream,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to connect to {}", socket_path.display()))
+            }
+        };
+        tracing::debug!("using daemon at {}", socket_path.display());
+
+        let (reader, writer) = stream.into_split();
+        Ok(Some(Self {
+            lines: tokio::io::BufReader::new(reader).lines(),
+            writer,
+            next_id: 0,
+        }))
+    }
+
+    /// Sends a request to the daemon, calling `on_record` for every streamed record, and returns
+    /// the records of the final response.
+    pub async fn call(
+        &mut self,
+        request: &crate::Request,
+        mut on_record: impl FnMut(output::Record) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Vec<output::Record>> {
+        let id = serde_json::Value::from(self.next_id);
+        self.next_id += 1;
+        write_message(
+            &mut self.writer,
+            &RpcRequest {
+                jsonrpc: JSONRPC_VERSION.to_owned(),
+                id: Some(id.clone()),
+                request: request.clone(),
+            },
+        )
+        .await?;
+
+        while let Some(line) = self.lines.next_line().await? {
+            let message = serde_json::from_str::<serde_json::Value>(&line)?;
+            if message.get("method").is_some() {
+                let notification = serde_json::from_value::<RpcNotification>(message)?;
+                on_record(notification.params)?;
+                continue;
+            }
+
+            let response = serde_json::from_value::<RpcResponse>(message)?;
+            if response.id != id {
+                tracing::warn!("ignoring response with unexpected id {}", response.id);
+                continue;
+            }
+            if let Some(error) = response.error {
+                anyhow::bail!("daemon error {}: {}", error.code, error.message);
+            }
+            return Ok(response.result.unwrap_or_default());
+        }
+        anyhow::bail!("daemon closed the connection")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time;
+
+    use super::*;
+
+    #[test]
+    fn request_ids_are_kept_as_given() {
+        for id in [
+            serde_json::json!(7),
+            serde_json::json!("abc"),
+            serde_json::Value::Null,
+        ] {
+            let message = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "read",
+                "params": {"axes": []},
+            });
+            let request = serde_json::from_value::<RpcRequest>(message).unwrap();
+            assert_eq!(request.id, Some(id));
+            assert!(matches!(request.request, crate::Request::Read { .. }));
+        }
+
+        let message = serde_json::json!({"jsonrpc": "2.0", "method": "state"});
+        let request = serde_json::from_value::<RpcRequest>(message).unwrap();
+        assert_eq!(request.id, None);
+    }
+
+    #[tokio::test]
+    async fn notifications_get_no_response() {
+        let (arm, pump) = arm::Arm::simulated();
+        let pump = tokio::spawn(pump);
+        let commanding = tokio::sync::Mutex::new(());
+        let (client, server) = tokio::net::UnixStream::pair().unwrap();
+        let client = async {
+            let (reader, mut writer) = client.into_split();
+            let mut lines = tokio::io::BufReader::new(reader).lines();
+            let notification = serde_json::json!({"jsonrpc": "2.0", "method": "state"});
+            let request = serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "state"});
+            write_message(&mut writer, &notification).await.unwrap();
+            write_message(&mut writer, &request).await.unwrap();
+            let line = lines.next_line().await.unwrap().unwrap();
+            let response = serde_json::from_str::<RpcResponse>(&line).unwrap();
+            assert_eq!(response.id, 1);
+
+            drop(writer);
+            assert_eq!(lines.next_line().await.unwrap(), None);
+        };
+        let (result, ()) = tokio::join!(handle_connection(&arm, &commanding, server), client);
+        result.unwrap();
+        pump.abort();
+    }
+
+    #[tokio::test]
+    async fn hanging_up_stops_the_axes() {
+        let (arm, pump) = arm::Arm::simulated();
+        let pump = tokio::spawn(pump);
+        let commanding = tokio::sync::Mutex::new(());
+        let (client, server) = tokio::net::UnixStream::pair().unwrap();
+        let client = async {
+            let (_reader, mut writer) = client.into_split();
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "force",
+                "params": {"request": {"method": "move", "params": {
+                    "axes": ["x"],
+                    "position": 100.0,
+                    "speed": 3000,
+                    "accel_raw": null,
+                    "shape": "trapezoid",
+                }}},
+            });
+            write_message(&mut writer, &request).await.unwrap();
+            time::sleep(time::Duration::from_millis(200)).await;
+        };
+        let (result, ()) = tokio::join!(handle_connection(&arm, &commanding, server), client);
+        result.unwrap();
+
+        // The motion was cut short, and the axis doesn't go on turning in speed mode.
+        let axis = arm.axis(Axis::X);
+        let stopped = axis.position().await.unwrap().rotations;
+        time::sleep(time::Duration::from_millis(50)).await;
+        assert!(stopped > 0.0 && stopped < 100.0, "{stopped}");
+        assert_eq!(axis.position().await.unwrap().rotations, stopped);
+        pump.abort();
+    }
+}
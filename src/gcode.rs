@@ -0,0 +1,596 @@
+//! G-code programs, translated to [`Request`]s.
+//!
+//! A whole program is translated before the arm moves, so that mistakes are found up front, and
+//! reported with their line number.  The supported codes are:
+//!
+//! * `G0` and `G1` move the arm, as fast as possible or at the feed rate `F`.  In joint mode, the
+//!   words `X`, `Y`, `Z`, `A`, `B` and `C` are the joint angles of the axes with the same names,
+//!   in degrees, and `F` is in degrees per minute.  In Cartesian mode, `X`, `Y` and `Z` are the
+//!   position of the tool in millimeters and `A`, `B` and `C` its roll, pitch and yaw in degrees,
+//!   and `F` is in millimeters per minute.  `G1` moves the tool along a straight line, like
+//!   `pose linear`.
+//! * `G4` dwells for `P` milliseconds, or `S` seconds.
+//! * `G28` moves the axes to their home position, all of them or those with a word.
+//! * `G21` and `G90` are accepted, since positions are always absolute and in millimeters.
+//! * `M17` enables and `M18` or `M84` disables the axes, all of them or those with a word, so
+//!   they can't share a line with moves.
+//! * `M3` closes the gripper, to `S` percent if given, and `M5` opens it.
+//! * `M100` switches to joint mode, which is the mode that programs start in, and `M101` to
+//!   Cartesian mode.
+//! * `M2` and `M30` end the program.
+//!
+//! Comments in parentheses or after a semicolon, line numbers (`N`) and `%` lines are ignored.
+use std::collections;
+
+use anyhow::Context as _;
+use arctos_can_driver::{kinematics, Axis};
+
+use crate::Request;
+
+/// Number of setpoints per second of straight-line moves, as for `pose linear`.
+const LINEAR_RATE: f64 = 10.0;
+/// Largest deviation from the line of straight-line moves in millimeters, as for `pose linear`.
+const MAX_DEVIATION: f64 = 5.0;
+
+/// A step of a program, with the line it comes from.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Block {
+    /// Line number, starting at 1.
+    pub line: usize,
+    /// The line without comments, or what the block does for programs that aren't G-code.
+    pub code: String,
+    pub action: Action,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Request(Request),
+    /// Waits for a number of seconds.
+    Dwell(f64),
+}
+
+/// Whether positions are joint angles or poses of the tool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    Joint,
+    Cartesian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Motion {
+    Rapid,
+    Linear,
+}
+
+/// The modal state of a program while it is being translated.
+struct Translator<'a> {
+    profile: &'a kinematics::Profile,
+    mode: Mode,
+    motion: Option<Motion>,
+    /// Feed rate per minute.
+    feed: Option<f64>,
+    /// The pose that the last Cartesian move went to, if the tool hasn't moved since.
+    pose: Option<kinematics::Pose>,
+    /// The joint angles that the program has moved to, starting from the origin.
+    joints: kinematics::Joints,
+}
+
+/// Translates a program into blocks, checking that every move stays within the soft limits of
+/// the profile and, in Cartesian mode, can be reached.
+///
+/// Cartesian moves are checked starting from the joint angles that the program has moved to so
+/// far, assuming that the arm starts at its origin.
+pub fn translate(source: &str, profile: &kinematics::Profile) -> anyhow::Result<Vec<Block>> {
+    let mut translator = Translator {
+        profile,
+        mode: Mode::Joint,
+        motion: None,
+        feed: None,
+        pose: None,
+        joints: kinematics::Joints::default(),
+    };
+    let mut blocks = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let code = strip_comments(text);
+        let code = code.trim();
+        if code.is_empty() || code == "%" {
+            continue;
+        }
+        let (actions, end) = parse_words(code)
+            .and_then(|words| translator.block(&words))
+            .with_context(|| format!("line {line}"))?;
+        blocks.extend(actions.into_iter().map(|action| Block {
+            line,
+            code: code.to_owned(),
+            action,
+        }));
+        if end {
+            break;
+        }
+    }
+    Ok(blocks)
+}
+
+impl Translator<'_> {
+    /// Translates the words of a line into actions, and whether the program ends there.
+    fn block(&mut self, words: &[(char, f64)]) -> anyhow::Result<(Vec<Action>, bool)> {
+        let mut g_codes = Vec::new();
+        let mut m_code = None;
+        let mut values = collections::BTreeMap::new();
+        for &(letter, value) in words {
+            match letter {
+                'G' => g_codes.push(code_number(letter, value)?),
+                'M' if m_code.is_some() => anyhow::bail!("more than one M code"),
+                'M' => m_code = Some(code_number(letter, value)?),
+                'N' => {}
+                'X' | 'Y' | 'Z' | 'A' | 'B' | 'C' | 'F' | 'P' | 'S' => {
+                    if values.insert(letter, value).is_some() {
+                        anyhow::bail!("more than one {letter} word");
+                    }
+                }
+                _ => anyhow::bail!("unsupported word {letter}"),
+            }
+        }
+        let positions = Axis::ALL
+            .into_iter()
+            .filter_map(|axis| Some((axis, *values.get(&axis_letter(axis))?)))
+            .collect::<Vec<_>>();
+        // Commands for axes apply to the axes with a word, or to all of them.
+        let axes = if positions.is_empty() {
+            Axis::ALL.to_vec()
+        } else {
+            positions.iter().map(|&(axis, _)| axis).collect()
+        };
+
+        let mut command = None;
+        for g_code in g_codes {
+            match g_code {
+                0 | 1 | 4 | 28 if command.is_some() => {
+                    anyhow::bail!("more than one of G0, G1, G4 and G28")
+                }
+                0 => self.motion = Some(Motion::Rapid),
+                1 => self.motion = Some(Motion::Linear),
+                4 | 28 => {}
+                21 | 90 => continue,
+                20 => anyhow::bail!("inches (G20) are not supported"),
+                91 => anyhow::bail!("relative positioning (G91) is not supported"),
+                _ => anyhow::bail!("unsupported code G{g_code}"),
+            }
+            command = Some(g_code);
+        }
+        // The axis words of M17, M18 and M84 select axes, so they can't also be positions.
+        if let (Some(g_code @ (0 | 1 | 28)), Some(m_code @ (17 | 18 | 84))) = (command, m_code) {
+            anyhow::bail!("G{g_code} can't be on the same line as M{m_code}");
+        }
+        if command == Some(4) && !positions.is_empty() {
+            anyhow::bail!("G4 takes no axis words");
+        }
+        if let Some(&feed) = values.get(&'F') {
+            if !(feed.is_finite() && feed > 0.0) {
+                anyhow::bail!("feed rate must be a positive number, got {feed}");
+            }
+            self.feed = Some(feed);
+        }
+
+        let mut actions = Vec::new();
+        // Switching modes applies to the moves on the same line, so it comes first.
+        match m_code {
+            Some(17) => actions.push(Action::Request(Request::Enable { axes: axes.clone() })),
+            Some(100) => self.mode = Mode::Joint,
+            Some(101) => self.mode = Mode::Cartesian,
+            _ => {}
+        }
+        match command {
+            Some(4) => {
+                let seconds = match (values.get(&'P'), values.get(&'S')) {
+                    (Some(millis), None) => millis / 1000.0,
+                    (None, Some(&seconds)) => seconds,
+                    _ => anyhow::bail!("G4 needs either P (milliseconds) or S (seconds)"),
+                };
+                if !(seconds.is_finite() && seconds >= 0.0) {
+                    anyhow::bail!("dwell time must not be negative, got {seconds} s");
+                }
+                actions.push(Action::Dwell(seconds));
+            }
+            Some(28) => {
+                for &axis in &axes {
+                    self.joints[axis_index(axis)] = 0.0;
+                }
+                self.pose = None;
+                actions.push(Action::Request(Request::Home { axes: axes.clone() }));
+            }
+            // The axis words of these M codes select axes, instead of moving them.
+            _ if matches!(m_code, Some(17 | 18 | 84)) => {}
+            _ if !positions.is_empty() => {
+                let motion = self.motion.context("position without G0 or G1")?;
+                actions.push(Action::Request(match self.mode {
+                    Mode::Joint => self.joint_move(motion, &positions)?,
+                    Mode::Cartesian => self.cartesian_move(motion, &positions)?,
+                }));
+            }
+            _ => {}
+        }
+        match m_code {
+            None | Some(17 | 100 | 101) => {}
+            Some(18 | 84) => actions.push(Action::Request(Request::Disable { axes })),
+            Some(3 | 5) => {
+                let gripper = self
+                    .profile
+                    .gripper
+                    .context("the robot profile has no gripper")?;
+                let closed = match m_code {
+                    Some(3) => values.get(&'S').map_or(100.0, |&percent| percent),
+                    _ => 0.0,
+                };
+                if !(0.0..=100.0).contains(&closed) {
+                    anyhow::bail!("gripper can close from 0 to 100 percent, got {closed}");
+                }
+                actions.push(Action::Request(Request::Gripper {
+                    gripper,
+                    position: gripper.open + (gripper.closed - gripper.open) * closed / 100.0,
+                }));
+            }
+            Some(2 | 30) => return Ok((actions, true)),
+            Some(m_code) => anyhow::bail!("unsupported code M{m_code}"),
+        }
+        Ok((actions, false))
+    }
+
+    fn joint_move(&mut self, motion: Motion, positions: &[(Axis, f64)]) -> anyhow::Result<Request> {
+        for &(axis, angle) in positions {
+            self.profile.check_limit(axis, angle)?;
+            // Fail early for axes without a gear ratio.
+            self.profile.motor_position(axis, angle)?;
+            self.joints[axis_index(axis)] = angle;
+        }
+        self.pose = None;
+        Ok(Request::MoveJoints {
+            profile: self.profile.clone(),
+            joints: positions.iter().copied().collect(),
+            speed: match motion {
+                Motion::Rapid => None,
+                Motion::Linear => Some(self.feed_per_second()?),
+            },
+        })
+    }
+
+    fn cartesian_move(
+        &mut self,
+        motion: Motion,
+        positions: &[(Axis, f64)],
+    ) -> anyhow::Result<Request> {
+        let from = self.pose;
+        let mut pose = match from {
+            Some(pose) => pose,
+            None if positions.len() == Axis::ALL.len() => kinematics::Pose::default(),
+            None => anyhow::bail!(
+                "the first Cartesian move, and the first one after a joint move or homing, needs \
+                 all of X, Y, Z, A, B and C"
+            ),
+        };
+        for &(axis, value) in positions {
+            *match axis {
+                Axis::X => &mut pose.x,
+                Axis::Y => &mut pose.y,
+                Axis::Z => &mut pose.z,
+                Axis::A => &mut pose.roll,
+                Axis::B => &mut pose.pitch,
+                Axis::C => &mut pose.yaw,
+            } = value;
+        }
+        let request = match motion {
+            Motion::Rapid => {
+                self.joints = self.profile.inverse(&pose, &self.joints)?;
+                Request::SetPose {
+                    profile: self.profile.clone(),
+                    pose,
+                }
+            }
+            Motion::Linear => {
+                let speed = self.feed_per_second()?;
+                match from {
+                    Some(from) => {
+                        let step = speed / LINEAR_RATE;
+                        let path = self.profile.linear_path(&from, &pose, &self.joints, step)?;
+                        if let Some(&(_, joints)) = path.last() {
+                            self.joints = joints;
+                        }
+                    }
+                    // Without a known start, only the end of the line can be checked.
+                    None => self.joints = self.profile.inverse(&pose, &self.joints)?,
+                }
+                Request::MoveLinear {
+                    profile: self.profile.clone(),
+                    pose,
+                    speed,
+                    rate: LINEAR_RATE,
+                    max_deviation: MAX_DEVIATION,
+                }
+            }
+        };
+        self.pose = Some(pose);
+        Ok(request)
+    }
+
+    fn feed_per_second(&self) -> anyhow::Result<f64> {
+        let feed = self.feed.context("G1 needs a feed rate (F)")?;
+        Ok(feed / 60.0)
+    }
+}
+
+/// Removes comments in parentheses and after a semicolon.
+fn strip_comments(line: &str) -> String {
+    let line = line.split(';').next().unwrap_or_default();
+    let mut code = String::with_capacity(line.len());
+    let mut depth = 0;
+    for c in line.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ if depth == 0 => code.push(c),
+            _ => {}
+        }
+    }
+    code
+}
+
+/// Splits a line into words, each a letter followed by a number.
+fn parse_words(code: &str) -> anyhow::Result<Vec<(char, f64)>> {
+    let mut words = Vec::new();
+    let mut chars = code.chars().filter(|c| !c.is_whitespace()).peekable();
+    while let Some(letter) = chars.next() {
+        if !letter.is_ascii_alphabetic() {
+            anyhow::bail!("expected a letter, got {letter:?}");
+        }
+        let letter = letter.to_ascii_uppercase();
+        let mut number = String::new();
+        while let Some(&c) = chars.peek() {
+            if !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+')) {
+                break;
+            }
+            number.push(c);
+            chars.next();
+        }
+        let value = number
+            .parse::<f64>()
+            .map_err(|_| anyhow::anyhow!("expected a number after {letter}, got {number:?}"))?;
+        words.push((letter, value));
+    }
+    Ok(words)
+}
+
+/// The number of a G or M code, which must be a whole number.
+fn code_number(letter: char, value: f64) -> anyhow::Result<u32> {
+    if value.fract() != 0.0 || value < 0.0 {
+        anyhow::bail!("unsupported code {letter}{value}");
+    }
+    Ok(value as u32)
+}
+
+fn axis_letter(axis: Axis) -> char {
+    match axis {
+        Axis::X => 'X',
+        Axis::Y => 'Y',
+        Axis::Z => 'Z',
+        Axis::A => 'A',
+        Axis::B => 'B',
+        Axis::C => 'C',
+    }
+}
+
+fn axis_index(axis: Axis) -> usize {
+    Axis::ALL
+        .iter()
+        .position(|&a| a == axis)
+        .expect("all axes are in Axis::ALL")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The shipped profile of the Arctos arm, with a gripper.
+    fn profile() -> kinematics::Profile {
+        let mut profile: kinematics::Profile =
+            toml::from_str(include_str!("../profiles/arctos.toml")).unwrap();
+        profile.gripper = Some(kinematics::Gripper {
+            id: 7,
+            open: 0.0,
+            closed: 2.0,
+        });
+        profile
+    }
+
+    /// A pose that the arm can reach within its limits, as the words of a Cartesian move.
+    fn reachable(profile: &kinematics::Profile, dz: f64) -> String {
+        let pose = profile
+            .forward(&[0.0, -10.0, 20.0, 45.0, 20.0, 0.0])
+            .unwrap();
+        format!(
+            "X{:.6} Y{:.6} Z{:.6} A{:.6} B{:.6} C{:.6}",
+            pose.x,
+            pose.y,
+            pose.z + dz,
+            pose.roll,
+            pose.pitch,
+            pose.yaw
+        )
+    }
+
+    fn axes(axes: &[Axis]) -> String {
+        axes.iter().map(|&axis| axis_letter(axis)).collect()
+    }
+
+    /// A short description of a block, to compare with the expected ones.
+    fn describe(block: &Block) -> String {
+        let action = match &block.action {
+            Action::Dwell(seconds) => format!("dwell {seconds}"),
+            Action::Request(Request::Enable { axes: a }) => format!("enable {}", axes(a)),
+            Action::Request(Request::Disable { axes: a }) => format!("disable {}", axes(a)),
+            Action::Request(Request::Home { axes: a }) => format!("home {}", axes(a)),
+            Action::Request(Request::MoveJoints { joints, speed, .. }) => {
+                let joints = joints
+                    .iter()
+                    .map(|(&axis, angle)| format!("{}{angle}", axis_letter(axis)))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                match speed {
+                    Some(speed) => format!("joints {joints} at {speed}"),
+                    None => format!("joints {joints}"),
+                }
+            }
+            Action::Request(Request::SetPose { pose, .. }) => format!("pose z{:.1}", pose.z),
+            Action::Request(Request::MoveLinear { pose, speed, .. }) => {
+                format!("linear z{:.1} at {speed}", pose.z)
+            }
+            Action::Request(Request::Gripper { position, .. }) => format!("gripper {position}"),
+            Action::Request(request) => panic!("unexpected request {request:?}"),
+        };
+        format!("{}: {action}", block.line)
+    }
+
+    #[test]
+    fn accepted_programs() {
+        let profile = profile();
+        let z = profile
+            .forward(&[0.0, -10.0, 20.0, 45.0, 20.0, 0.0])
+            .unwrap()
+            .z;
+        let cartesian = format!(
+            "M101\nG0 {}\nG1 Z{:.6} F600\nG0 X0 Y-20\n",
+            reachable(&profile, 0.0),
+            z - 10.0
+        );
+        let cases: &[(&str, &str, &[&str])] = &[
+            (
+                "G0 and G1 are modal, and so is the feed rate",
+                "G0 X10 Y-20\nZ30\nG1 X5 F600\nA15\nG0 B10\nG1 C1 F1200\n",
+                &[
+                    "1: joints X10 Y-20",
+                    "2: joints Z30",
+                    "3: joints X5 at 10",
+                    "4: joints A15 at 10",
+                    "5: joints B10",
+                    "6: joints C1 at 20",
+                ],
+            ),
+            (
+                "M17 and M18 select the axes with a word, or all of them",
+                "M17\nM17 X0 Z0\nM18 Y0\nM84\n",
+                &[
+                    "1: enable XYZABC",
+                    "2: enable XZ",
+                    "3: disable Y",
+                    "4: disable XYZABC",
+                ],
+            ),
+            (
+                "G4 dwells for P milliseconds or S seconds",
+                "G4 P1500\nG4 S2\nG4 P0\n",
+                &["1: dwell 1.5", "2: dwell 2", "3: dwell 0"],
+            ),
+            (
+                "G28 homes the axes with a word, or all of them",
+                "G28\nG28 X0 C0\n",
+                &["1: home XYZABC", "2: home XC"],
+            ),
+            (
+                "M2 ends the program, after the rest of its line",
+                "G0 X1\nM2\nG0 X2\nG999\n",
+                &["1: joints X1"],
+            ),
+            (
+                "M30 ends the program too",
+                "G0 X1 M30\nG0 X2\n",
+                &["1: joints X1"],
+            ),
+            (
+                "comments, line numbers and % lines are ignored",
+                "%\n(start)\nN10 G0 X1 (move) Y-2 ; comment G0 X3\n; G0 X4\n%\n",
+                &["3: joints X1 Y-2"],
+            ),
+            (
+                "the gripper closes to S percent",
+                "M3\nM3 S25\nM5\n",
+                &["1: gripper 2", "2: gripper 0.5", "3: gripper 0"],
+            ),
+            (
+                "Cartesian moves start from a pose with all six words",
+                &cartesian,
+                &[
+                    &format!("2: pose z{z:.1}"),
+                    &format!("3: linear z{:.1} at 10", z - 10.0),
+                    &format!("4: pose z{:.1}", z - 10.0),
+                ],
+            ),
+        ];
+        for (name, source, expected) in cases {
+            let blocks =
+                translate(source, &profile).unwrap_or_else(|err| panic!("{name}: {err:#}"));
+            let actual = blocks.iter().map(describe).collect::<Vec<_>>();
+            assert_eq!(actual, *expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn rejected_programs() {
+        let profile = profile();
+        let far = "X2000 Y0 Z0 A0 B0 C0";
+        let cases = [
+            ("G0 X10\nG1 X20\n", 2, "G1 needs a feed rate (F)"),
+            ("G0 X10\n\nY400\n", 3, "outside of its limits"),
+            ("X10\n", 1, "position without G0 or G1"),
+            (
+                "G0 X1\nM101\nG0 X300 Y0\n",
+                3,
+                "needs all of X, Y, Z, A, B and C",
+            ),
+            (
+                &format!(
+                    "M101\nG0 {}\nG28 X0\nG1 Z300 F600\n",
+                    reachable(&profile, 0.0)
+                ),
+                4,
+                "needs all of X, Y, Z, A, B and C",
+            ),
+            (&format!("M101\nG0 {far}\n"), 2, "can't reach the pose"),
+            ("G4\n", 1, "G4 needs either P (milliseconds) or S (seconds)"),
+            (
+                "G4 P100 S1\n",
+                1,
+                "G4 needs either P (milliseconds) or S (seconds)",
+            ),
+            ("G4 S-1\n", 1, "dwell time must not be negative"),
+            ("G4 P100 X10\n", 1, "G4 takes no axis words"),
+            ("M17 A1 G0 X10\n", 1, "G0 can't be on the same line as M17"),
+            ("G28 X0 M84\n", 1, "G28 can't be on the same line as M84"),
+            ("G0 G1 X1\n", 1, "more than one of G0, G1, G4 and G28"),
+            ("G0 X1 X2\n", 1, "more than one X word"),
+            ("M17 M18\n", 1, "more than one M code"),
+            ("G20\n", 1, "inches (G20) are not supported"),
+            ("G91\n", 1, "relative positioning (G91) is not supported"),
+            ("G0 X1\nM999\n", 2, "unsupported code M999"),
+            ("G1.5\n", 1, "unsupported code G1.5"),
+            ("G0 X1 F0\n", 1, "feed rate must be a positive number"),
+            ("G0 Q1\n", 1, "unsupported word Q"),
+            ("G0 X\n", 1, "expected a number after X"),
+            ("M3 S150\n", 1, "gripper can close from 0 to 100 percent"),
+        ];
+        for (source, line, message) in cases {
+            let err = translate(source, &profile).expect_err(source);
+            assert_eq!(err.to_string(), format!("line {line}"), "{source:?}");
+            let err = format!("{err:#}");
+            assert!(err.contains(message), "{source:?}: {err}");
+        }
+
+        let mut profile = profile;
+        profile.gripper = None;
+        let err = translate("G0 X1\nM5\n", &profile).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "line 2: the robot profile has no gripper"
+        );
+    }
+}
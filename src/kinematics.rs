@@ -0,0 +1,742 @@
+//! Kinematics of the arm, converting between joint angles and poses of the tool.
+//!
+//! The geometry of the arm is described by a [`Profile`], with the Denavit-Hartenberg parameters
+//! of every joint.  Lengths are in millimeters, and angles in degrees.
+//!
+//! Inverse kinematics are solved numerically, so that any geometry can be described by a profile.
+//! The solver starts from the current joint angles, and from variations of them with the base,
+//! elbow or wrist flipped, to find the solutions that are closest to the current state.
+use std::collections;
+use std::fmt;
+
+use crate::Axis;
+
+/// Joint angles in degrees, in the order of [`Axis::ALL`].
+pub type Joints = [f64; 6];
+
+/// A homogeneous transform.
+type Matrix = [[f64; 4]; 4];
+
+/// Maximum number of solver iterations from each starting point.
+const MAX_ITERATIONS: usize = 200;
+/// Largest change of a joint angle in a single solver iteration, in degrees.
+const MAX_STEP: f64 = 10.0;
+/// Damping of the solver steps, to keep them small close to singularities.
+const DAMPING: f64 = 0.01;
+/// Millimeters of position error that count as much as one radian of orientation error.
+const ORIENTATION_WEIGHT: f64 = 100.0;
+/// Largest position error of a solution, in millimeters.
+const POSITION_TOLERANCE: f64 = 1e-3;
+/// Largest orientation error of a solution, in radians.
+const ORIENTATION_TOLERANCE: f64 = 1e-5;
+/// Largest change of orientation between two samples of a straight line, in degrees.
+const ROTATION_STEP: f64 = 1.0;
+/// Largest change of a joint angle between two samples of a straight line, in degrees.  Larger
+/// changes happen close to singularities, where the joints can't keep up with the tool.
+const MAX_JOINT_STEP: f64 = 15.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The profile has no parameters for an axis.
+    MissingJoint { axis: Axis },
+    /// Neither the profile nor the axis defaults know the gear ratio of an axis.
+    MissingGearRatio { axis: Axis },
+    /// No joint angles put the tool at the requested pose.
+    Unreachable,
+    /// A straight line passes too close to a singularity, where a joint would have to turn
+    /// suddenly.
+    Singular { axis: Axis, pose: Pose },
+    /// The pose can only be reached by moving a joint beyond its soft limits.
+    OutOfLimits {
+        axis: Axis,
+        angle: f64,
+        min: f64,
+        max: f64,
+    },
+}
+
+/// The geometry of an arm.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct Profile {
+    pub joints: collections::BTreeMap<Axis, Joint>,
+    /// The gripper at the end of the arm, if it has one.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub gripper: Option<Gripper>,
+    /// Joint angles in degrees that `arm shutdown` moves to before disabling the axes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub park: Option<collections::BTreeMap<Axis, f64>>,
+    /// The order in which the axes are brought up by `arm startup`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub startup: Vec<StartupStep>,
+}
+
+/// The Denavit-Hartenberg parameters of a joint, and how it is driven by its motor.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct Joint {
+    /// Offset along the previous z axis to the common normal.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub d: f64,
+    /// Length of the common normal.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub a: f64,
+    /// Angle about the common normal, from the previous z axis to the new z axis.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub alpha: f64,
+    /// Angle about the previous z axis when the motor is at its origin.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub theta_offset: f64,
+    /// Motor rotations per joint rotation, or [`Axis::gearing_factor`] if not set.  A negative
+    /// ratio means that the joint turns the other way than the motor.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub gear_ratio: Option<f64>,
+    /// Soft limits of the joint angle, or [`Axis::actuation_range`] if not set.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub limits: Option<(f64, f64)>,
+}
+
+/// A gripper driven by a servo of its own, on the same CAN network as the axes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct Gripper {
+    /// CAN id of the servo.
+    pub id: u16,
+    /// Position of the servo when the gripper is open, in number of servo rotations from origin.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub open: f64,
+    /// Position of the servo when the gripper is fully closed.
+    pub closed: f64,
+}
+
+/// A step of bringing up the arm: its axes are initialized, enabled, homed and verified together,
+/// after the axes of the previous steps.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct StartupStep {
+    pub axes: Vec<Axis>,
+    /// Whether to home the axes, using the homing settings of their servos.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub home: bool,
+}
+
+/// A position and orientation of the tool, relative to the base of the arm.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pose {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    /// Rotation about the x axis, applied first.
+    pub roll: f64,
+    /// Rotation about the y axis, applied after `roll`.
+    pub pitch: f64,
+    /// Rotation about the z axis, applied last.
+    pub yaw: f64,
+}
+
+impl Profile {
+    /// The parameters of the joint driven by an axis.
+    pub fn joint(&self, axis: Axis) -> Result<&Joint, Error> {
+        self.joints.get(&axis).ok_or(Error::MissingJoint { axis })
+    }
+
+    /// The number of motor rotations per joint rotation of an axis.
+    pub fn gear_ratio(&self, axis: Axis) -> Result<f64, Error> {
+        self.joint(axis)?
+            .gear_ratio
+            .or(axis.gearing_factor())
+            .ok_or(Error::MissingGearRatio { axis })
+    }
+
+    /// The soft limits of the joint angle of an axis, as `(min, max)`, if it has any.
+    pub fn limits(&self, axis: Axis) -> Result<Option<(f64, f64)>, Error> {
+        let limits = self.joint(axis)?.limits.or(axis.actuation_range());
+        Ok(limits.map(|(a, b)| (a.min(b), a.max(b))))
+    }
+
+    /// Converts a motor position, in number of servo rotations from origin, to a joint angle.
+    pub fn joint_angle(&self, axis: Axis, rotations: f64) -> Result<f64, Error> {
+        Ok(rotations / self.gear_ratio(axis)? * 360.0)
+    }
+
+    /// Converts a joint angle to a motor position, in number of servo rotations from origin.
+    pub fn motor_position(&self, axis: Axis, angle: f64) -> Result<f64, Error> {
+        Ok(angle / 360.0 * self.gear_ratio(axis)?)
+    }
+
+    /// Computes the pose of the tool for the given joint angles.
+    pub fn forward(&self, joints: &Joints) -> Result<Pose, Error> {
+        Ok(Pose::from_matrix(&self.frames(joints)?[6]))
+    }
+
+    /// Computes joint angles that put the tool at the given pose, picking the solution within the
+    /// soft limits that is closest to the `current` joint angles.
+    pub fn inverse(&self, pose: &Pose, current: &Joints) -> Result<Joints, Error> {
+        let target = pose.matrix();
+        let mut best: Option<(f64, Result<Joints, Error>)> = None;
+        for seed in seeds(current) {
+            let Some(mut solution) = self.solve(&target, seed)? else {
+                continue;
+            };
+            for (angle, current) in solution.iter_mut().zip(current) {
+                // Prefer the turn of the joint that is closest to where it is now.
+                *angle += 360.0 * ((current - *angle) / 360.0).round();
+            }
+            let distance = solution
+                .iter()
+                .zip(current)
+                .map(|(angle, current)| (angle - current).powi(2))
+                .sum::<f64>();
+            let result = self.check_limits(&solution).map(|()| solution);
+            // Solutions within the limits always win, otherwise report the closest violation.
+            let better = match &best {
+                None => true,
+                Some((_, Ok(_))) if result.is_err() => false,
+                Some((_, Err(_))) if result.is_ok() => true,
+                Some((best_distance, _)) => distance < *best_distance,
+            };
+            if better {
+                best = Some((distance, result));
+            }
+        }
+        best.map_or(Err(Error::Unreachable), |(_, result)| result)
+    }
+
+    /// Samples the straight line from the pose `from` to the pose `to`, and solves the joint
+    /// angles of every sample, starting from the `current` joint angles.
+    ///
+    /// Samples are at most `max_step` millimeters apart, and one degree of orientation.  The
+    /// returned samples include `to`, but not `from`.
+    pub fn linear_path(
+        &self,
+        from: &Pose,
+        to: &Pose,
+        current: &Joints,
+        max_step: f64,
+    ) -> Result<Vec<(Pose, Joints)>, Error> {
+        let (start, end) = (from.matrix(), to.matrix());
+        let rotation = rotation_error(&end, &start);
+        let distance = norm(&[0, 1, 2].map(|i| end[i][3] - start[i][3]));
+        let steps = (distance / max_step)
+            .max(norm(&rotation).to_degrees() / ROTATION_STEP)
+            .ceil()
+            .max(1.0) as usize;
+
+        let mut path = Vec::with_capacity(steps);
+        let mut previous = *current;
+        for step in 1..=steps {
+            let t = step as f64 / steps as f64;
+            let turn = rotation_matrix(&rotation.map(|r| r * t));
+            let mut sample = IDENTITY;
+            for i in 0..3 {
+                for j in 0..3 {
+                    sample[i][j] = (0..3).map(|k| turn[i][k] * start[k][j]).sum();
+                }
+                sample[i][3] = start[i][3] + (end[i][3] - start[i][3]) * t;
+            }
+            let pose = Pose::from_matrix(&sample);
+            let joints = self.inverse(&pose, &previous)?;
+            for ((axis, angle), previous) in Axis::ALL.into_iter().zip(joints).zip(previous) {
+                if (angle - previous).abs() > MAX_JOINT_STEP {
+                    return Err(Error::Singular { axis, pose });
+                }
+            }
+            path.push((pose, joints));
+            previous = joints;
+        }
+        Ok(path)
+    }
+
+    /// Checks that a joint angle is within the soft limits of the joint.
+    pub fn check_limit(&self, axis: Axis, angle: f64) -> Result<(), Error> {
+        if let Some((min, max)) = self.limits(axis)? {
+            if !(min..=max).contains(&angle) {
+                return Err(Error::OutOfLimits {
+                    axis,
+                    angle,
+                    min,
+                    max,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_limits(&self, joints: &Joints) -> Result<(), Error> {
+        for (axis, &angle) in Axis::ALL.into_iter().zip(joints) {
+            self.check_limit(axis, angle)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the damped least squares solver from `seed`, returning the joint angles if they
+    /// converge on the target.
+    fn solve(&self, target: &Matrix, seed: Joints) -> Result<Option<Joints>, Error> {
+        let mut joints = seed;
+        for _ in 0..MAX_ITERATIONS {
+            let frames = self.frames(&joints)?;
+            let tool = &frames[6];
+            let position_error = [0, 1, 2].map(|i| target[i][3] - tool[i][3]);
+            let orientation_error = rotation_error(target, tool);
+            if norm(&position_error) < POSITION_TOLERANCE
+                && norm(&orientation_error) < ORIENTATION_TOLERANCE
+            {
+                return Ok(Some(joints));
+            }
+            let error: [f64; 6] = [
+                position_error[0],
+                position_error[1],
+                position_error[2],
+                orientation_error[0] * ORIENTATION_WEIGHT,
+                orientation_error[1] * ORIENTATION_WEIGHT,
+                orientation_error[2] * ORIENTATION_WEIGHT,
+            ];
+
+            let jacobian = jacobian(&frames);
+            // Solve `(JᵀJ + λ²I) Δ = Jᵀe` for the change of joint angles `Δ`.
+            let mut lhs = [[0.0; 6]; 6];
+            let mut rhs = [0.0; 6];
+            for i in 0..6 {
+                for j in 0..6 {
+                    lhs[i][j] = (0..6).map(|k| jacobian[k][i] * jacobian[k][j]).sum();
+                }
+                lhs[i][i] += DAMPING * DAMPING;
+                rhs[i] = (0..6).map(|k| jacobian[k][i] * error[k]).sum();
+            }
+            let Some(step) = solve_linear(lhs, rhs) else {
+                return Ok(None);
+            };
+            let scale = step
+                .iter()
+                .fold(1.0, |scale: f64, s| scale.max(s.abs() / MAX_STEP));
+            for (angle, s) in joints.iter_mut().zip(step) {
+                *angle += s / scale;
+            }
+        }
+        Ok(None)
+    }
+
+    /// The transforms from the base to the frame of every joint, starting with the base itself
+    /// and ending with the tool.
+    fn frames(&self, joints: &Joints) -> Result<[Matrix; 7], Error> {
+        let mut frames = [IDENTITY; 7];
+        for (i, (axis, angle)) in Axis::ALL.into_iter().zip(joints).enumerate() {
+            frames[i + 1] = multiply(&frames[i], &self.joint(axis)?.transform(*angle));
+        }
+        Ok(frames)
+    }
+}
+
+impl Joint {
+    /// The transform from the frame of the previous joint to the frame of this joint.
+    fn transform(&self, angle: f64) -> Matrix {
+        let (st, ct) = (angle + self.theta_offset).to_radians().sin_cos();
+        let (sa, ca) = self.alpha.to_radians().sin_cos();
+        [
+            [ct, -st * ca, st * sa, self.a * ct],
+            [st, ct * ca, -ct * sa, self.a * st],
+            [0.0, sa, ca, self.d],
+            [0.0, 0.0, 0.0, 1.0],
+        ]
+    }
+}
+
+impl Pose {
+    fn matrix(&self) -> Matrix {
+        let (sr, cr) = self.roll.to_radians().sin_cos();
+        let (sp, cp) = self.pitch.to_radians().sin_cos();
+        let (sy, cy) = self.yaw.to_radians().sin_cos();
+        [
+            [
+                cy * cp,
+                cy * sp * sr - sy * cr,
+                cy * sp * cr + sy * sr,
+                self.x,
+            ],
+            [
+                sy * cp,
+                sy * sp * sr + cy * cr,
+                sy * sp * cr - cy * sr,
+                self.y,
+            ],
+            [-sp, cp * sr, cp * cr, self.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ]
+    }
+
+    fn from_matrix(m: &Matrix) -> Self {
+        let pitch = (-m[2][0]).atan2(m[0][0].hypot(m[1][0]));
+        let (roll, yaw) = if m[0][0].hypot(m[1][0]) < 1e-9 {
+            // Gimbal lock: only the sum or difference of roll and yaw is known.
+            (0.0, (-m[0][1]).atan2(m[1][1]))
+        } else {
+            (m[2][1].atan2(m[2][2]), m[1][0].atan2(m[0][0]))
+        };
+        Self {
+            x: m[0][3],
+            y: m[1][3],
+            z: m[2][3],
+            roll: roll.to_degrees(),
+            pitch: pitch.to_degrees(),
+            yaw: yaw.to_degrees(),
+        }
+    }
+}
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Starting points for the solver: the current joint angles, and variations with the base turned
+/// around, the elbow bent the other way, and the wrist flipped.
+fn seeds(current: &Joints) -> Vec<Joints> {
+    let mut seeds = Vec::new();
+    for base in [0.0, 180.0] {
+        for elbow in [0.0, 180.0] {
+            for wrist in [false, true] {
+                let mut seed = *current;
+                seed[0] += base;
+                seed[2] += elbow;
+                if wrist {
+                    seed[3] += 180.0;
+                    seed[4] = -seed[4];
+                    seed[5] += 180.0;
+                }
+                seeds.push(seed);
+            }
+        }
+    }
+    seeds
+}
+
+/// The geometric Jacobian, with the change of the tool position (in millimeters) and orientation
+/// (in weighted radians) per degree of every joint in the columns.
+fn jacobian(frames: &[Matrix; 7]) -> [[f64; 6]; 6] {
+    let tool = [0, 1, 2].map(|i| frames[6][i][3]);
+    let mut jacobian = [[0.0; 6]; 6];
+    for (j, frame) in frames[..6].iter().enumerate() {
+        // Every joint turns about the z axis of the frame before it.
+        let axis = [0, 1, 2].map(|i| frame[i][2]);
+        let arm = [0, 1, 2].map(|i| tool[i] - frame[i][3]);
+        let velocity = cross(&axis, &arm);
+        for i in 0..3 {
+            jacobian[i][j] = velocity[i].to_radians();
+            jacobian[i + 3][j] = axis[i].to_radians() * ORIENTATION_WEIGHT;
+        }
+    }
+    jacobian
+}
+
+/// The rotation that turns the orientation of `actual` into that of `target`, as a rotation
+/// vector in radians.
+fn rotation_error(target: &Matrix, actual: &Matrix) -> [f64; 3] {
+    // The rotation from actual to target, in base coordinates: `R_target * R_actualᵀ`.
+    let mut r = [[0.0; 3]; 3];
+    for (i, row) in r.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = (0..3).map(|k| target[i][k] * actual[j][k]).sum();
+        }
+    }
+    let skew = [r[2][1] - r[1][2], r[0][2] - r[2][0], r[1][0] - r[0][1]];
+    let cos = ((r[0][0] + r[1][1] + r[2][2] - 1.0) / 2.0).clamp(-1.0, 1.0);
+    let angle = cos.acos();
+    let sin = angle.sin();
+    if sin > 1e-6 {
+        skew.map(|s| s * angle / (2.0 * sin))
+    } else if cos > 0.0 {
+        skew.map(|s| s / 2.0)
+    } else {
+        // Half a turn: the axis is the longest column of `R + I`.
+        let column = (0..3)
+            .map(|j| [0, 1, 2].map(|i| r[i][j] + if i == j { 1.0 } else { 0.0 }))
+            .max_by(|a, b| norm(a).total_cmp(&norm(b)))
+            .unwrap();
+        let length = norm(&column);
+        column.map(|c| c / length * angle)
+    }
+}
+
+/// The rotation about the axis of a rotation vector, by its length in radians.
+fn rotation_matrix(vector: &[f64; 3]) -> [[f64; 3]; 3] {
+    let angle = norm(vector);
+    if angle < 1e-12 {
+        return [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    }
+    let [x, y, z] = vector.map(|v| v / angle);
+    let (s, c) = angle.sin_cos();
+    let t = 1.0 - c;
+    [
+        [t * x * x + c, t * x * y - s * z, t * x * z + s * y],
+        [t * x * y + s * z, t * y * y + c, t * y * z - s * x],
+        [t * x * z - s * y, t * y * z + s * x, t * z * z + c],
+    ]
+}
+
+/// Solves `a x = b` by Gaussian elimination, or returns `None` if `a` is singular.
+fn solve_linear(mut a: [[f64; 6]; 6], mut b: [f64; 6]) -> Option<[f64; 6]> {
+    for col in 0..6 {
+        let pivot = (col..6).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..6 {
+            let pivot_row = a[col];
+            let factor = a[row][col] / pivot_row[col];
+            for (cell, pivot) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *cell -= factor * pivot;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = [0.0; 6];
+    for row in (0..6).rev() {
+        let rest = (row + 1..6).map(|k| a[row][k] * x[k]).sum::<f64>();
+        x[row] = (b[row] - rest) / a[row][row];
+    }
+    Some(x)
+}
+
+fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn norm(v: &[f64; 3]) -> f64 {
+    v.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.0; 4]; 4];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingJoint { axis } => {
+                write!(f, "robot profile has no joint parameters for axis {axis:?}")
+            }
+            Error::MissingGearRatio { axis } => {
+                write!(f, "robot profile has no gear ratio for axis {axis:?}")
+            }
+            Error::Unreachable => write!(f, "the arm can't reach the pose"),
+            Error::Singular { axis, pose } => write!(
+                f,
+                "the path passes too close to a singularity at ({:.1}, {:.1}, {:.1}), where \
+                 axis {axis:?} would have to turn suddenly",
+                pose.x, pose.y, pose.z
+            ),
+            Error::OutOfLimits {
+                axis,
+                angle,
+                min,
+                max,
+            } => write!(
+                f,
+                "reaching the pose needs axis {axis:?} at {angle:.1}°, outside of its limits \
+                 {min}° to {max}°"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The geometry of `profiles/arctos.toml`, with limits of a full turn either way.
+    fn arctos() -> Profile {
+        let joint = |d, a, alpha, theta_offset, gear_ratio| Joint {
+            d,
+            a,
+            alpha,
+            theta_offset,
+            gear_ratio: Some(gear_ratio),
+            limits: Some((-360.0, 360.0)),
+        };
+        Profile {
+            joints: [
+                (Axis::X, joint(287.87, 20.174, -90.0, 0.0, 13.6)),
+                (Axis::Y, joint(0.0, 260.986, 0.0, -90.0, 150.0)),
+                (Axis::Z, joint(0.0, 19.219, -90.0, 0.0, 150.0)),
+                (Axis::A, joint(260.753, 0.0, 90.0, 0.0, 5.1)),
+                (Axis::B, joint(0.0, 0.0, -90.0, 0.0, 67.82)),
+                (Axis::C, joint(74.745, 0.0, 0.0, 0.0, 67.82)),
+            ]
+            .into(),
+            ..Profile::default()
+        }
+    }
+
+    #[test]
+    fn forward() {
+        let profile = arctos();
+        // At zero, the upper arm points up, and the forearm and the tool point forward along x.
+        let pose = profile.forward(&[0.0; 6]).unwrap();
+        let x = 20.174 + 260.753 + 74.745;
+        let (y, z) = (0.0, 287.87 + 260.986 + 19.219);
+        assert!((pose.x - x).abs() < 1e-9, "{pose:?}");
+        assert!((pose.y - y).abs() < 1e-9, "{pose:?}");
+        assert!((pose.z - z).abs() < 1e-9, "{pose:?}");
+
+        // Turning the base turns the whole pose about the z axis.
+        let joints = [0.0, -10.0, 20.0, 45.0, 20.0, 0.0];
+        let pose = profile.forward(&joints).unwrap();
+        let turned = profile
+            .forward(&[90.0, -10.0, 20.0, 45.0, 20.0, 0.0])
+            .unwrap();
+        assert!((turned.x + pose.y).abs() < 1e-9);
+        assert!((turned.y - pose.x).abs() < 1e-9);
+        assert!((turned.z - pose.z).abs() < 1e-9);
+        assert!((turned.yaw - pose.yaw - 90.0).abs() < 1e-9);
+
+        let mut profile = profile;
+        profile.joints.remove(&Axis::C);
+        assert_eq!(
+            profile.forward(&joints),
+            Err(Error::MissingJoint { axis: Axis::C })
+        );
+    }
+
+    /// A pose with the tool pointing down, at the height of the elbow.
+    fn down(x: f64, y: f64) -> Pose {
+        Pose {
+            x,
+            y,
+            z: 300.0,
+            roll: 180.0,
+            ..Pose::default()
+        }
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64], tolerance: f64) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < tolerance, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn inverse_of_forward() {
+        let profile = arctos();
+        for joints in [
+            [0.0, -10.0, 20.0, 45.0, 20.0, 0.0],
+            [10.0, -20.0, 15.0, 5.0, 30.0, -10.0],
+            [-30.0, 10.0, 40.0, -20.0, 60.0, 90.0],
+        ] {
+            let pose = profile.forward(&joints).unwrap();
+            let solution = profile.inverse(&pose, &[0.0; 6]).unwrap();
+            assert_close(&solution, &joints, 0.01);
+            let solved = profile.forward(&solution).unwrap();
+            assert_close(
+                &[solved.x, solved.y, solved.z],
+                &[pose.x, pose.y, pose.z],
+                POSITION_TOLERANCE,
+            );
+        }
+    }
+
+    #[test]
+    fn unreachable_pose() {
+        let profile = arctos();
+        assert_eq!(
+            profile.inverse(&down(2000.0, 0.0), &[0.0; 6]),
+            Err(Error::Unreachable)
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let mut profile = arctos();
+        profile.joints.get_mut(&Axis::Y).unwrap().limits = Some((30.0, -60.0));
+        profile.joints.get_mut(&Axis::Z).unwrap().limits = None;
+        assert_eq!(profile.check_limit(Axis::Y, -60.0), Ok(()));
+        assert_eq!(profile.check_limit(Axis::Y, 30.0), Ok(()));
+        assert_eq!(
+            profile.check_limit(Axis::Y, 30.5),
+            Err(Error::OutOfLimits {
+                axis: Axis::Y,
+                angle: 30.5,
+                min: -60.0,
+                max: 30.0
+            })
+        );
+        // Without limits in the profile, the actuation range of the axis applies.
+        assert_eq!(profile.check_limit(Axis::Z, 25.0), Ok(()));
+        assert!(profile.check_limit(Axis::Z, -1.0).is_err());
+        profile.joints.remove(&Axis::X);
+        assert_eq!(
+            profile.check_limit(Axis::X, 0.0),
+            Err(Error::MissingJoint { axis: Axis::X })
+        );
+
+        // A pose whose only solutions are beyond the limits of the shoulder or the base is refused.
+        let profile = arctos();
+        let pose = profile
+            .forward(&[0.0, -10.0, 20.0, 45.0, 20.0, 0.0])
+            .unwrap();
+        let mut limited = profile.clone();
+        limited.joints.get_mut(&Axis::X).unwrap().limits = Some((-90.0, 90.0));
+        limited.joints.get_mut(&Axis::Y).unwrap().limits = Some((0.0, 30.0));
+        assert!(matches!(
+            limited.inverse(&pose, &[0.0; 6]),
+            Err(Error::OutOfLimits { axis: Axis::Y, .. })
+        ));
+    }
+
+    #[test]
+    fn linear_path() {
+        let profile = arctos();
+        let (from, to) = (down(300.0, -50.0), down(300.0, 50.0));
+        let current = profile.inverse(&from, &[0.0; 6]).unwrap();
+        let path = profile.linear_path(&from, &to, &current, 5.0).unwrap();
+        assert_eq!(path.len(), 20);
+        let mut previous = current;
+        for (pose, joints) in &path {
+            assert_close(&[pose.x, pose.z], &[300.0, 300.0], 1e-9);
+            let solved = profile.forward(joints).unwrap();
+            assert_close(
+                &[solved.x, solved.y, solved.z],
+                &[pose.x, pose.y, pose.z],
+                1e-3,
+            );
+            assert_close(joints, &previous, MAX_JOINT_STEP);
+            previous = *joints;
+        }
+        let (last, _) = path.last().unwrap();
+        assert_close(&[last.y], &[50.0], 1e-9);
+    }
+
+    #[test]
+    fn linear_path_through_singularity() {
+        // Passing over the base, the base would have to turn half a turn at once.
+        let profile = arctos();
+        let (from, to) = (down(150.0, 5.0), down(-150.0, 5.0));
+        let current = profile.inverse(&from, &[0.0; 6]).unwrap();
+        let Err(Error::Singular { axis, pose }) = profile.linear_path(&from, &to, &current, 5.0)
+        else {
+            panic!("the path should pass through a singularity");
+        };
+        assert_eq!(axis, Axis::X);
+        assert!(pose.x.abs() < 10.0, "{pose:?}");
+    }
+}
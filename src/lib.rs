@@ -0,0 +1,20 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "socketcan")]
+pub mod arm;
+mod axis;
+#[cfg(feature = "socketcan")]
+pub mod bus;
+#[cfg(feature = "socketcan")]
+pub mod candump;
+#[cfg(feature = "std")]
+pub mod kinematics;
+#[cfg(feature = "std")]
+pub mod motion;
+#[cfg(feature = "socketcan")]
+pub mod pcapng;
+pub mod servo_cmd;
+#[cfg(feature = "socketcan")]
+pub mod sim;
+
+pub use axis::Axis;
@@ -1,22 +1,76 @@
-use futures::{future, sink, stream};
-use tokio::sync::{broadcast, mpsc, oneshot};
+use std::path;
+
+use arctos_can_driver::arm::moves;
+use arctos_can_driver::{arm, bus, candump, kinematics, motion, pcapng, servo_cmd, Axis};
+use futures::future;
 use tokio::time;
 
-mod servo_cmd;
+mod daemon;
+mod gcode;
+mod monitor;
+mod output;
+mod poses;
+mod request;
+mod teach;
+
+use request::{execute, Request};
 
 /// A simple controller for an Arctos robot arm using canbus.
 #[derive(clap::Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(flatten)]
+    bus: BusOptions,
+    /// Format to use when printing results to stdout.
+    #[arg(short, long, value_enum, default_value_t)]
+    output: output::OutputFormat,
+    /// Path of the Unix socket of the daemon started by `serve`.  When a daemon is listening on
+    /// the socket, commands are sent to it instead of using the CAN network directly.
+    #[arg(long, env = "ARCTOS_SOCKET")]
+    socket: Option<path::PathBuf>,
+    /// Path of the robot profile, a TOML file with the geometry of the arm.  Needed for commands
+    /// that work with Cartesian poses.
+    #[arg(long, env = "ARCTOS_PROFILE")]
+    profile: Option<path::PathBuf>,
+    /// Path of the file with the named poses of `pose save` and `goto`.  Defaults to
+    /// `arctos-can-driver/poses.toml` in the state directory of the user.
+    #[arg(long, env = "ARCTOS_POSES")]
+    poses: Option<path::PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Args, Debug)]
+struct BusOptions {
     /// Interface name for the CAN network to use.
     #[arg(short, long, default_value = "can0")]
     ifname: String,
-    #[command(subcommand)]
-    command: Command,
+    /// Record every frame sent and received to a log file, in the format of `candump -l`.
+    #[arg(long)]
+    record: Option<path::PathBuf>,
+    /// Capture every frame sent and received to a pcapng file, that can be opened in Wireshark.
+    #[arg(long)]
+    capture: Option<path::PathBuf>,
+    /// Replay a log file recorded with `--record` (or `candump -l`) instead of using the CAN
+    /// network.
+    #[arg(long, conflicts_with = "dry_run")]
+    replay: Option<path::PathBuf>,
+    /// Don't use the CAN network, but print every frame that would be sent, and simulate
+    /// successful responses from the servos.
+    #[arg(long)]
+    dry_run: bool,
+    /// Send requests even if the states of the axes don't allow them, for example to move an axis
+    /// that hasn't been homed.  Without the daemon started by `serve`, which keeps track of the
+    /// states from one command to the next, they are read from the servos, which are taken to have
+    /// been homed.
+    #[arg(long)]
+    force: bool,
 }
 
 #[derive(Debug, clap::Subcommand)]
 enum Command {
+    /// Keep the CAN network open, and serve requests from other invocations over a Unix socket.
+    Serve,
     Axes {
         #[arg(short, long)]
         all: bool,
@@ -25,6 +79,271 @@ enum Command {
         #[command(subcommand)]
         axes_command: AxesCommand,
     },
+    /// Cartesian poses of the tool and named poses of the arm, using the geometry from the robot
+    /// profile.
+    Pose {
+        #[command(subcommand)]
+        pose_command: PoseCommand,
+    },
+    /// Send raw requests to servos, bypassing the higher level commands.
+    Servo {
+        #[arg(short, long)]
+        all: bool,
+        #[arg(long, value_enum)]
+        axes: Vec<Axis>,
+        #[command(subcommand)]
+        servo_command: ServoCommand,
+    },
+    /// Low-level access to the CAN network.
+    Bus {
+        #[command(subcommand)]
+        bus_command: BusCommand,
+    },
+    /// Move all axes to a named pose saved with `pose save`, so that they all start and finish
+    /// together.
+    Goto {
+        /// Name of the pose.
+        name: String,
+        /// Speed of the joint with the most travel in degrees per second, instead of the default
+        /// speeds of the axes.
+        #[arg(short, long)]
+        speed: Option<f64>,
+    },
+    /// Bring the whole arm up or down, following the sequences of the robot profile.
+    Arm {
+        #[command(subcommand)]
+        arm_command: ArmCommand,
+    },
+    /// Teach the arm by hand, and replay what it was taught.
+    Teach {
+        #[command(subcommand)]
+        teach_command: TeachCommand,
+    },
+    /// Run a G-code program, printing every line as it completes.
+    ///
+    /// `G0` and `G1` move the joints (after `M100`, the default) or the tool (after `M101`), with
+    /// `F` in degrees or millimeters per minute.  `G4` dwells, `G28` homes the axes, `M17` and
+    /// `M18` enable and disable them, and `M3` and `M5` close and open the gripper of the robot
+    /// profile.  The whole program is checked before anything moves.
+    RunGcode {
+        /// Path of the program.
+        file: path::PathBuf,
+        /// Only check the program, and print what every line translates to, without moving.
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum BusCommand {
+    /// Passively decode all servo traffic on the CAN network, until interrupted.
+    ///
+    /// Frames that can't be decoded, for example because of a CRC mismatch or an unknown opcode,
+    /// are shown with an error.
+    Monitor {
+        /// Only show frames to or from these axes.
+        #[arg(long, value_enum)]
+        axes: Vec<Axis>,
+        /// Only show frames with these opcodes.
+        #[arg(long, value_enum)]
+        opcodes: Vec<servo_cmd::ServoOpcode>,
+    },
+    /// Send a raw frame to the CAN network, and show the frames received from the same id in
+    /// response.
+    ///
+    /// The checksum byte is appended to the data, so `bus send --id 0x01 82 05` sends `82 05 88`.
+    /// Responses with known opcodes are decoded.
+    Send {
+        /// CAN id to send the frame to, in decimal or hexadecimal with a `0x` prefix.
+        #[arg(long, value_parser = parse_id)]
+        id: socketcan::StandardId,
+        /// Bytes of the frame in hexadecimal, starting with the opcode and without the checksum.
+        #[arg(required = true, value_parser = parse_byte)]
+        data: Vec<u8>,
+        /// How long to wait for responses, in milliseconds.
+        #[arg(long, default_value_t = 500)]
+        window: u64,
+    },
+}
+
+fn parse_id(s: &str) -> anyhow::Result<socketcan::StandardId> {
+    let id = match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16)?,
+        None => s.parse()?,
+    };
+    socketcan::StandardId::new(id).ok_or_else(|| anyhow::anyhow!("not a standard CAN id: {s}"))
+}
+
+fn parse_byte(s: &str) -> anyhow::Result<u8> {
+    Ok(u8::from_str_radix(s.strip_prefix("0x").unwrap_or(s), 16)?)
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum PoseCommand {
+    /// Read the positions of all axes, and print the pose of the tool.
+    Get,
+    /// Move the tool to a pose, with the joint angles closest to the current ones that reach it.
+    ///
+    /// All axes start and finish together, but the tool doesn't follow a straight line.
+    Set {
+        #[command(flatten)]
+        pose: PoseArgs,
+    },
+    /// Move the tool to a pose along a straight line, by streaming setpoints to the axes.
+    ///
+    /// The line is sampled at a fixed rate, and after every sample the tool position is read back
+    /// and compared with the line.  If it strays too far, the axes are stopped where they are.
+    Linear {
+        #[command(flatten)]
+        pose: PoseArgs,
+        /// Speed of the tool in millimeters per second.
+        #[arg(short, long, default_value_t = 20.0)]
+        speed: f64,
+        /// Number of setpoints to send per second, for each axis.
+        #[arg(short, long, default_value_t = 10.0)]
+        rate: f64,
+        /// Largest distance from the line that the tool may stray, in millimeters.
+        #[arg(long, default_value_t = 5.0)]
+        max_deviation: f64,
+    },
+    /// Read the joint angles of all axes, and save them as a named pose for `goto`.
+    Save {
+        /// Name of the pose.
+        name: String,
+        /// Replace the pose if there already is one with the same name.
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Print the named poses, with their joint angles.
+    List,
+    /// Delete a named pose.
+    Delete {
+        /// Name of the pose.
+        name: String,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+struct PoseArgs {
+    /// Position of the tool in millimeters.
+    #[arg(allow_negative_numbers = true)]
+    x: f64,
+    #[arg(allow_negative_numbers = true)]
+    y: f64,
+    #[arg(allow_negative_numbers = true)]
+    z: f64,
+    /// Orientation of the tool in degrees, as rotations about the x, y and z axes, in that
+    /// order.
+    #[arg(allow_negative_numbers = true)]
+    roll: f64,
+    #[arg(allow_negative_numbers = true)]
+    pitch: f64,
+    #[arg(allow_negative_numbers = true)]
+    yaw: f64,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum ArmCommand {
+    /// Initialize, enable, home and verify the axes, step by step in the order of the
+    /// `[[startup]]` sequence of the robot profile.
+    ///
+    /// The axes of a step are brought up together, after those of the previous steps.  They are
+    /// verified by checking that their motors are at rest, and that their joint angles are within
+    /// the soft limits.  The sequence stops at the first failure, and leaves the axes that were
+    /// already brought up enabled.  A summary of every axis is printed at the end.
+    Startup,
+    /// Move all axes to the park pose and wait for them to get there, and then disable them one
+    /// at a time, from the wrist to the base, so that nothing drops.
+    ///
+    /// The park pose is the `[park]` section of the robot profile, unless `--pose` is given.
+    /// Shutting down stops at the first failure, and the final state of every axis is printed.
+    Shutdown {
+        /// Park at a named pose saved with `pose save` instead.
+        #[arg(long)]
+        pose: Option<String>,
+    },
+    /// Print the state of every axis and of the whole arm, as tracked by the daemon.
+    ///
+    /// Axes go from `unknown` to `configured` (initialized), `enabled`, `homed` and `ready` (homed
+    /// and at rest), as the servos acknowledge requests and report their status.  Any failed
+    /// request leaves the axis `faulted`, until it is initialized again.  Enabling needs the axis
+    /// to be configured, homing needs it to be enabled, and moving needs it to be homed;
+    /// anything else is refused unless `--force` is given.
+    Status,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum TeachCommand {
+    /// Disable axis motors so that the arm can be moved by hand, and capture waypoints.
+    ///
+    /// Type a name for a waypoint (or nothing, for a numbered one) and press Enter to capture the
+    /// positions of all axes, and press Ctrl-D when done.  Waypoints are saved to the file after
+    /// every capture, after the waypoints that it already has.  The axes stay disabled afterwards.
+    Record {
+        /// Path of the waypoint file.
+        file: path::PathBuf,
+        /// Disable all axes, instead of those given with `--axes`.
+        #[arg(short, long)]
+        all: bool,
+        /// Axes to disable.
+        #[arg(long, value_enum, required_unless_present = "all")]
+        axes: Vec<Axis>,
+    },
+    /// Enable all axis motors, and visit the waypoints of a file in order, with coordinated moves.
+    Replay {
+        /// Path of the waypoint file.
+        file: path::PathBuf,
+    },
+    /// Disable axis motors so that the arm can be moved by hand, and record the positions of all
+    /// axes continuously, until interrupted.
+    Demonstrate {
+        /// Path of the demonstration file, a CSV file that is overwritten.
+        file: path::PathBuf,
+        /// Number of samples to take per second.
+        #[arg(short, long, default_value_t = 50.0)]
+        rate: f64,
+        /// Disable all axes, instead of those given with `--axes`.
+        #[arg(short, long)]
+        all: bool,
+        /// Axes to disable.
+        #[arg(long, value_enum, required_unless_present = "all")]
+        axes: Vec<Axis>,
+    },
+    /// Play back a recorded demonstration along a smooth path, by streaming setpoints to all axes.
+    ///
+    /// The recording is smoothed with a moving average, and a spline is fitted through knots
+    /// taken at a fixed interval.  The axes first move to the start of the path.
+    Play {
+        /// Path of the demonstration file.
+        file: path::PathBuf,
+        /// Playback speed, relative to the timing of the path.
+        #[arg(short, long, default_value_t = 1.0)]
+        speed: f64,
+        /// Whether to keep the timing of the recording, or to move at an even pace.
+        #[arg(long, value_enum, default_value_t)]
+        timing: motion::Timing,
+        /// Width of the moving average in seconds, that filters out jitter.
+        #[arg(long, default_value_t = 0.2)]
+        smoothing: f64,
+        /// Time between the knots of the spline in seconds, before any change of speed.
+        #[arg(long, default_value_t = 0.1)]
+        interval: f64,
+        /// Number of setpoints to send per second, for each axis.
+        #[arg(short, long, default_value_t = 20.0)]
+        rate: f64,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum ServoCommand {
+    /// Send a single request to the servos, and print their (first) responses.
+    ///
+    /// Requests are sent as-is, so this can also change settings that the other commands rely
+    /// on, such as the CAN id or the work mode of a servo.
+    Cmd {
+        #[command(subcommand)]
+        request: servo_cmd::ServoRequest,
+    },
 }
 
 #[derive(Debug, clap::Subcommand)]
@@ -33,16 +352,23 @@ enum AxesCommand {
     Init,
     /// Enable (power on) axis motors.
     Enable,
+    /// Disable (power off) axis motors, so that they no longer hold their positions.
+    Disable,
+    /// Stop axis motors right away, whatever they are doing, so that they hold their positions.
+    Stop,
     /// Set the origin of the specified axes to whatever the current position of the robot is.
     SetOrigin,
     /// Get the current axis positions, from the point of view of the motor(s).
     GetMotorPos,
+    /// Move axis motors to their home position, using the homing settings of the motors.
+    Home,
     /// Set the axis positions, from the point of view of the motor(s).
     SetMotorPos {
         /// The raw position in number of servo rotations from origin.
         position: f64,
-        /// The acceleration of the motor, in RPM/s².
-        #[arg(long)]
+        /// The acceleration of the motor for a profile generated on the host, in RPM/s², or the
+        /// acceleration given by `accel_raw` if not set.
+        #[arg(long, requires = "shape")]
         accel: Option<f64>,
         /// The acceleration of the motor, with more control compared to the `accel` flag.
         /// Determines the rate at which `speed` is ramped up, according to the formula:
@@ -69,363 +395,123 @@ enum AxesCommand {
         /// The speed of the motor in RPM.
         #[arg(short, long)]
         speed: Option<f64>,
+        /// Slow down the axes with less travel, so that all axes start and finish together.
+        ///
+        /// The speed and acceleration of every axis then become the fastest that it may use.
+        #[arg(long)]
+        sync: bool,
+        /// Generate a speed profile of this shape on the host, and stream it to the motors in
+        /// speed mode, instead of relying on the built-in ramp of the motors.
+        ///
+        /// The measured position is fed back into the speed, and the motors finish the move with
+        /// a short position move to make up for any remaining difference.
+        #[arg(long, value_enum, conflicts_with = "sync")]
+        shape: Option<motion::Shape>,
+        /// The jerk of the motor for S-curve profiles, in RPM/s³, or four times the acceleration
+        /// if not set.
+        #[arg(long, requires = "shape")]
+        jerk: Option<f64>,
+    },
+    /// Continuously poll telemetry (position, speed, error, status and IO ports) from axis motors.
+    Watch {
+        /// Number of samples to take per second, for each axis.
+        #[arg(short, long, default_value_t = 10.0)]
+        rate: f64,
+        /// Stop after taking this many samples per axis, instead of running until interrupted.
+        #[arg(short = 'n', long)]
+        count: Option<u64>,
     },
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
-pub enum Axis {
-    X,
-    Y,
-    Z,
-    A,
-    B,
-    C,
-}
-
-impl Axis {
-    pub fn id(&self) -> socketcan::Id {
-        match *self {
-            Axis::X => socketcan::Id::Standard(socketcan::StandardId::new(1).unwrap()),
-            Axis::Y => socketcan::Id::Standard(socketcan::StandardId::new(2).unwrap()),
-            Axis::Z => socketcan::Id::Standard(socketcan::StandardId::new(3).unwrap()),
-            Axis::A => socketcan::Id::Standard(socketcan::StandardId::new(4).unwrap()),
-            Axis::B => socketcan::Id::Standard(socketcan::StandardId::new(5).unwrap()),
-            Axis::C => socketcan::Id::Standard(socketcan::StandardId::new(6).unwrap()),
-        }
-    }
-
-    fn default_speed(&self) -> u16 {
-        match *self {
-            Axis::X => 300,
-            Axis::Y => 300,
-            Axis::Z => 300,
-            Axis::A => 500,
-            Axis::B => 500,
-            Axis::C => 500,
-        }
-    }
-
-    fn default_accel(&self) -> u8 {
-        match *self {
-            Axis::X => 176,
-            Axis::Y => 176,
-            Axis::Z => 176,
-            Axis::A => 216,
-            Axis::B => 236,
-            Axis::C => 236,
-        }
-    }
-
-    fn gearing_factor(&self) -> Option<f64> {
-        match *self {
-            Axis::X => Some(13.6),
-            Axis::Y => None,
-            Axis::Z => None,
-            Axis::A => Some(5.1),
-            Axis::B => None,
-            Axis::C => None,
+impl PoseArgs {
+    fn pose(&self) -> kinematics::Pose {
+        kinematics::Pose {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+            roll: self.roll,
+            pitch: self.pitch,
+            yaw: self.yaw,
         }
     }
+}
 
-    fn actuation_range(&self) -> Option<(f64, f64)> {
-        match *self {
-            Axis::X => None,
-            Axis::Y => Some((-60.0, 30.0)),
-            Axis::Z => Some((50.0, 0.0)),
-            Axis::A => None,
-            Axis::B => None,
-            Axis::C => None,
-        }
+impl BusOptions {
+    /// Whether commands must use the bus themselves, instead of sending them to a daemon.
+    fn needs_own_bus(&self) -> bool {
+        self.record.is_some() || self.capture.is_some() || self.replay.is_some() || self.dry_run
     }
-}
 
-type CanFrameTx = sink::SinkErrInto<
-    tokio_util::sync::PollSender<socketcan::CanFrame>,
-    socketcan::CanFrame,
-    anyhow::Error,
->;
-type CanFrameRx =
-    stream::ErrInto<tokio_stream::wrappers::BroadcastStream<socketcan::CanFrame>, anyhow::Error>;
-
-async fn par_map_canbus<A, I, Tx, Rx, F, R, RV>(
-    values: I,
-    mut can_tx: Tx,
-    mut can_rx: Rx,
-    ref action: F,
-) -> anyhow::Result<Vec<RV>>
-where
-    I: IntoIterator<Item = A>,
-    Tx: sink::Sink<socketcan::CanFrame, Error = anyhow::Error> + Unpin,
-    Rx: stream::Stream<Item = anyhow::Result<socketcan::CanFrame>> + Unpin,
-    F: Fn(A, CanFrameTx, CanFrameRx) -> R,
-    R: future::Future<Output = anyhow::Result<RV>>,
-{
-    use future::FutureExt as _;
-    use futures_util::stream::TryStreamExt as _;
-    use sink::SinkExt as _;
-    use stream::StreamExt as _;
-
-    let (ref can_broadcast_tx, _) = broadcast::channel(16);
-    let (done_tx_send, mut done_tx_recv) = oneshot::channel();
-    let (done_rx_send, mut done_rx_recv) = oneshot::channel();
-    let (can_collect_tx, mut can_collect_rx) = mpsc::channel(1);
-    let rx_task = async move {
-        loop {
-            tokio::select! {
-                item = can_rx.next() => {
-                    if let Some(item) = item.transpose()? {
-                        can_broadcast_tx.send(item)?;
+    /// Opens the bus, returning the arm and the future that drives the bus.
+    ///
+    /// A `passive` user only listens to the bus, so a replayed log is played back in full
+    /// instead of waiting for the frames that were sent in the log to be sent again.
+    fn open(
+        &self,
+        passive: bool,
+    ) -> anyhow::Result<(arm::Arm, future::BoxFuture<'static, Result<(), arm::Error>>)> {
+        use anyhow::Context as _;
+        use future::FutureExt as _;
+
+        let (arm, pump) = match &self.replay {
+            Some(replay) => {
+                let file = std::fs::File::open(replay)
+                    .with_context(|| format!("failed to open {}", replay.display()))?;
+                let mut entries = candump::read(std::io::BufReader::new(file))?;
+                if passive {
+                    for entry in &mut entries {
+                        entry.direction = Some(bus::Direction::Received);
                     }
                 }
-                _ = &mut done_rx_recv => {
-                    break;
-                }
+                let (arm, pump) = arm::Arm::replay(entries);
+                (arm, pump.boxed())
             }
-        }
-        anyhow::Ok(())
-    };
-    let tx_task = async move {
-        loop {
-            tokio::select! {
-                item = can_collect_rx.recv() => {
-                    if let Some(item) = item {
-                        can_tx.send(item).await?;
-                    }
-                }
-                _ = &mut done_tx_recv => {
-                    break;
-                }
+            None if self.dry_run => {
+                let (arm, pump) = arm::Arm::simulated();
+                arm.bus().tap(monitor::Printer);
+                (arm, pump.boxed())
             }
-        }
-        anyhow::Ok(())
-    };
-    let workers_task = future::try_join_all(values.into_iter().map(|value| {
-        let can_tx = tokio_util::sync::PollSender::new(can_collect_tx.clone()).sink_err_into();
-        let can_rx =
-            tokio_stream::wrappers::BroadcastStream::new(can_broadcast_tx.subscribe()).err_into();
-        action(value, can_tx, can_rx)
-    }))
-    .inspect(|_| done_tx_send.send(()).unwrap())
-    .inspect(|_| done_rx_send.send(()).unwrap());
-    let (_, _, results) = future::try_join3(rx_task, tx_task, workers_task).await?;
-    anyhow::Ok(results)
-}
-
-#[tracing::instrument(skip(can_tx, can_rx))]
-async fn init_axis(
-    axis: Axis,
-    mut can_tx: impl sink::Sink<socketcan::CanFrame, Error = anyhow::Error> + Unpin,
-    mut can_rx: impl stream::Stream<Item = anyhow::Result<socketcan::CanFrame>> + Unpin,
-) -> anyhow::Result<()> {
-    use sink::SinkExt as _;
-
-    let set_work_mode = can_tx.send(
-        servo_cmd::ServoRequest::SetWorkMode {
-            work_mode: servo_cmd::WorkMode::SrVFoc,
-        }
-        .to_frame(axis.id())?,
-    );
-    let await_work_mode = await_axis_response(&mut can_rx, axis, |response| async move {
-        if let servo_cmd::ServoResponse::SetWorkMode { success } = response {
-            let status = if success { "success" } else { "fail" };
-            tracing::info!("set SR_vFOC work mode: {status}");
-            if success {
-                Ok(Some(()))
-            } else {
-                anyhow::bail!("failed to set work mode for axis {axis:?}")
+            None => {
+                let (arm, pump) = arm::Arm::open(&self.ifname)?;
+                (arm, pump.boxed())
             }
-        } else {
-            Ok(None)
+        };
+        if let Some(record) = &self.record {
+            let file = std::fs::File::create(record)
+                .with_context(|| format!("failed to create {}", record.display()))?;
+            arm.bus().tap(candump::Writer::new(
+                std::io::BufWriter::new(file),
+                &self.ifname,
+            ));
         }
-    });
-
-    futures::try_join!(set_work_mode, await_work_mode)?;
-
-    let set_display_off =
-        can_tx.send(servo_cmd::ServoRequest::SetAutoSSD { enable: true }.to_frame(axis.id())?);
-    let await_display_off = await_axis_response(&mut can_rx, axis, |response| async move {
-        if let servo_cmd::ServoResponse::SetAutoSSD { success } = response {
-            let status = if success { "success" } else { "fail" };
-            tracing::info!("turn off display: {status}");
-            if success {
-                Ok(Some(()))
-            } else {
-                anyhow::bail!("failed to turn off display for axis {axis:?}")
-            }
-        } else {
-            Ok(None)
+        if let Some(capture) = &self.capture {
+            let file = std::fs::File::create(capture)
+                .with_context(|| format!("failed to create {}", capture.display()))?;
+            arm.bus().tap(pcapng::Writer::new(
+                std::io::BufWriter::new(file),
+                &self.ifname,
+            )?);
         }
-    });
-
-    futures::try_join!(set_display_off, await_display_off)?;
-
-    Ok(())
-}
-
-#[tracing::instrument(skip(can_tx, can_rx))]
-async fn enable_axis(
-    axis: Axis,
-    mut can_tx: impl sink::Sink<socketcan::CanFrame, Error = anyhow::Error> + Unpin,
-    can_rx: impl stream::Stream<Item = anyhow::Result<socketcan::CanFrame>> + Unpin,
-) -> anyhow::Result<()> {
-    use futures_util::SinkExt as _;
-
-    let set_enabled =
-        can_tx.send(servo_cmd::ServoRequest::Enable { enabled: true }.to_frame(axis.id())?);
-    let await_enabled = await_axis_response(can_rx, axis, |response| async move {
-        if let servo_cmd::ServoResponse::Enable { success } = response {
-            let status = if success { "success" } else { "fail" };
-            tracing::info!("enable: {status}");
-            if success {
-                Ok(Some(()))
-            } else {
-                anyhow::bail!("failed to enable axis {axis:?}")
-            }
-        } else {
-            Ok(None)
-        }
-    });
-
-    futures::try_join!(set_enabled, await_enabled)?;
-
-    Ok(())
-}
-
-#[tracing::instrument(skip(can_tx, can_rx))]
-async fn set_origin(
-    axis: Axis,
-    mut can_tx: impl sink::Sink<socketcan::CanFrame, Error = anyhow::Error> + Unpin,
-    can_rx: impl stream::Stream<Item = anyhow::Result<socketcan::CanFrame>> + Unpin,
-) -> anyhow::Result<()> {
-    use futures_util::SinkExt as _;
-
-    let set_axis_zero = can_tx.send(servo_cmd::ServoRequest::SetAxisZero.to_frame(axis.id())?);
-    let await_axis_zero = await_axis_response(can_rx, axis, |response| async move {
-        if let servo_cmd::ServoResponse::SetAxisZero { success } = response {
-            let status = if success { "success" } else { "fail" };
-            tracing::info!("set origin: {status}");
-            if success {
-                Ok(Some(()))
-            } else {
-                anyhow::bail!("failed to set origin for axis {axis:?}")
-            }
-        } else {
-            Ok(None)
-        }
-    });
-
-    futures::try_join!(set_axis_zero, await_axis_zero)?;
-
-    Ok(())
-}
-
-#[tracing::instrument(skip(can_tx, can_rx))]
-async fn get_axis_pos_raw(
-    axis: Axis,
-    mut can_tx: impl sink::Sink<socketcan::CanFrame, Error = anyhow::Error> + Unpin,
-    can_rx: impl stream::Stream<Item = anyhow::Result<socketcan::CanFrame>> + Unpin,
-) -> anyhow::Result<Option<i64>> {
-    use futures_util::SinkExt as _;
-
-    let read_encoder_value =
-        can_tx.send(servo_cmd::ServoRequest::ReadEncoderValueAddition.to_frame(axis.id())?);
-    let await_encoder_value = await_axis_response(can_rx, axis, |response| async move {
-        if let servo_cmd::ServoResponse::ReadEncoderValueAddition { value } = response {
-            tracing::info!("read encoder value: {value}");
-            Ok(Some(value))
-        } else {
-            Ok(None)
-        }
-    });
-
-    let value = futures::try_join!(read_encoder_value, await_encoder_value)?.1;
-
-    Ok(value)
-}
-
-#[tracing::instrument(skip(can_tx, can_rx))]
-async fn set_axis_pos_raw(
-    axis: Axis,
-    position: f64,
-    speed: u16,
-    accel: u8,
-    mut can_tx: impl sink::Sink<socketcan::CanFrame, Error = anyhow::Error> + Unpin,
-    can_rx: impl stream::Stream<Item = anyhow::Result<socketcan::CanFrame>> + Unpin,
-) -> anyhow::Result<()> {
-    use futures_util::SinkExt as _;
-
-    let request = servo_cmd::ServoRequest::RunPositionAbsoluteMotionMode {
-        speed,
-        accel,
-        abs_axis: (position * 0x4000 as f64) as i32,
+        Ok((arm, pump))
     }
-    .to_frame(axis.id())?;
-    can_tx.send(request).await?;
-
-    await_axis_response(can_rx, axis, |response| async move {
-        if let servo_cmd::ServoResponse::RunPositionAbsoluteMotionMode { status } = response {
-            tracing::info!("set axis pos: {status:?}");
-            match status {
-                servo_cmd::MotionStatus::Fail => {
-                    anyhow::bail!("failed to set origin for axis {axis:?}")
-                }
-                servo_cmd::MotionStatus::Busy => {
-                    Ok(None)
-                }
-                servo_cmd::MotionStatus::Success => {
-                    Ok(Some(()))
-                }
-                servo_cmd::MotionStatus::LimitReached => {
-                    tracing::warn!("endstop triggered when trying to set axis position to {position} for axis {axis:?}");
-                    Ok(Some(()))
-                }
-            }
-        } else {
-            Ok(None)
-        }
-    })
-    .await?;
-
-    Ok(())
 }
 
-async fn await_axis_response<Rx, H, F, A>(
-    mut can_rx: Rx,
-    axis: Axis,
-    mut response_handler: H,
-) -> anyhow::Result<Option<A>>
-where
-    Rx: stream::Stream<Item = anyhow::Result<socketcan::CanFrame>> + Unpin,
-    H: FnMut(servo_cmd::ServoResponse) -> F,
-    F: future::Future<Output = anyhow::Result<Option<A>>>,
-{
+/// Reads the robot profile given with `--profile`.
+fn load_profile(path: Option<&path::Path>) -> anyhow::Result<kinematics::Profile> {
     use anyhow::Context as _;
-    use futures_util::StreamExt as _;
-    use socketcan::EmbeddedFrame as _;
-
-    let await_response = async {
-        while let Some(frame) = can_rx.next().await {
-            let frame = frame?;
-            if frame.id() == axis.id() {
-                let response = servo_cmd::ServoResponse::from_frame(axis.id(), frame)?;
-                if let Some(a) = response_handler(response).await? {
-                    return anyhow::Ok(Some(a));
-                }
-            }
-        }
-        anyhow::Ok(None)
-    };
-    let timeout = time::timeout(time::Duration::from_millis(100), await_response);
-    timeout
-        .await
-        .unwrap_or_else(|e| Err(e.into()))
-        .context(format!("didn't get a response for axis {axis:?}"))
+
+    let path = path.context("this command needs a robot profile, given with --profile")?;
+    let profile = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    toml::from_str(&profile).with_context(|| format!("invalid robot profile {}", path.display()))
 }
 
 #[tokio::main]
 async fn main() {
     use clap::Parser as _;
-    tracing_subscriber::fmt::init();
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
 
     let args = Args::parse();
     if let Err(err) = run(args).await {
@@ -436,57 +522,416 @@ async fn main() {
 
 async fn run(args: Args) -> anyhow::Result<()> {
     use clap::ValueEnum as _;
-    use futures::TryStreamExt as _;
-    use futures_util::SinkExt as _;
-    use futures_util::StreamExt as _;
-
-    let socket = socketcan::tokio::CanSocket::open(&args.ifname)?;
-    let (can_tx, can_rx) = socket.split();
-    let can_tx = can_tx.sink_err_into();
-    let can_rx = can_rx.err_into();
+
+    let socket_path = args
+        .socket
+        .unwrap_or_else(|| daemon::default_socket_path(&args.bus.ifname));
+    let poses_path = args.poses.unwrap_or_else(poses::default_path);
     match args.command {
+        Command::Serve => {
+            let (arm, pump) = args.bus.open(false)?;
+            tokio::select! {
+                result = pump => {
+                    result?;
+                    anyhow::bail!("CAN socket closed")
+                }
+                result = daemon::serve(&arm, &socket_path) => result?,
+            }
+        }
         Command::Axes {
             all,
             axes,
             axes_command,
         } => {
-            let axes = if all { Axis::value_variants() } else { &axes }
-                .into_iter()
-                .copied();
-            match axes_command {
-                AxesCommand::Init => {
-                    par_map_canbus(axes, can_tx, can_rx, init_axis).await?;
-                }
-                AxesCommand::Enable => {
-                    par_map_canbus(axes, can_tx, can_rx, enable_axis).await?;
-                }
-                AxesCommand::GetMotorPos => {
-                    par_map_canbus(axes, can_tx, can_rx, get_axis_pos_raw).await?;
-                }
+            let axes = if all {
+                Axis::value_variants().to_vec()
+            } else {
+                axes
+            };
+            let request = match axes_command {
+                AxesCommand::Init => Request::Init { axes },
+                AxesCommand::Enable => Request::Enable { axes },
+                AxesCommand::Disable => Request::Disable { axes },
+                AxesCommand::Stop => Request::Stop { axes },
+                AxesCommand::SetOrigin => Request::SetOrigin { axes },
+                AxesCommand::GetMotorPos => Request::Read { axes },
+                AxesCommand::Home => Request::Home { axes },
                 AxesCommand::SetMotorPos {
                     position,
                     speed,
-                    accel: _, // TODO
+                    accel,
                     accel_raw,
-                } => {
-                    par_map_canbus(axes, can_tx, can_rx, |a, t, r| {
-                        set_axis_pos_raw(
-                            a,
-                            position,
-                            speed.map(|s| s as u16).unwrap_or(a.default_speed()),
-                            accel_raw.unwrap_or(a.default_accel()),
-                            t,
-                            r,
-                        )
+                    sync,
+                    shape,
+                    jerk,
+                } => Request::Move {
+                    axes,
+                    position,
+                    speed: speed.map(|s| s as u16),
+                    accel_raw,
+                    sync,
+                    shape,
+                    accel,
+                    jerk,
+                },
+                AxesCommand::Watch { rate, count } => Request::Watch { axes, rate, count },
+            };
+            call(&args.bus, args.output, &socket_path, request).await?;
+        }
+        Command::Pose { pose_command } => {
+            let request = match pose_command {
+                PoseCommand::Get => Request::GetPose {
+                    profile: load_profile(args.profile.as_deref())?,
+                },
+                PoseCommand::Set { pose } => Request::SetPose {
+                    profile: load_profile(args.profile.as_deref())?,
+                    pose: pose.pose(),
+                },
+                PoseCommand::Linear {
+                    pose,
+                    speed,
+                    rate,
+                    max_deviation,
+                } => Request::MoveLinear {
+                    profile: load_profile(args.profile.as_deref())?,
+                    pose: pose.pose(),
+                    speed,
+                    rate,
+                    max_deviation,
+                },
+                PoseCommand::Save { name, force } => {
+                    if name.trim().is_empty() {
+                        anyhow::bail!("pose names can't be empty");
+                    }
+                    let mut library = poses::load(&poses_path)?;
+                    if library.contains_key(&name) && !force {
+                        anyhow::bail!(
+                            "there already is a pose named {name:?}, use --force to replace it"
+                        );
+                    }
+                    let profile = load_profile(args.profile.as_deref())?;
+                    let joints = poses::capture(&args.bus, &socket_path, profile).await?;
+                    let saved = poses::to_records([(&name, &joints)])?;
+                    library.insert(name, joints);
+                    poses::save(&poses_path, &library)?;
+                    output::report(args.output, &saved)?;
+                    return Ok(());
+                }
+                PoseCommand::List => {
+                    let library = poses::load(&poses_path)?;
+                    output::report(args.output, &poses::to_records(&library)?)?;
+                    return Ok(());
+                }
+                PoseCommand::Delete { name } => {
+                    let mut library = poses::load(&poses_path)?;
+                    poses::get(&library, &name)?;
+                    library.remove(&name);
+                    poses::save(&poses_path, &library)?;
+                    return Ok(());
+                }
+            };
+            call(&args.bus, args.output, &socket_path, request).await?;
+        }
+        Command::Goto { name, speed } => {
+            if let Some(speed) = speed.filter(|speed| !(speed.is_finite() && *speed > 0.0)) {
+                anyhow::bail!("speed must be a positive number, got {speed}");
+            }
+            let joints = poses::get(&poses::load(&poses_path)?, &name)?.clone();
+            let request = Request::MoveJoints {
+                profile: load_profile(args.profile.as_deref())?,
+                joints,
+                speed,
+            };
+            call(&args.bus, args.output, &socket_path, request).await?;
+        }
+        Command::Servo {
+            all,
+            axes,
+            servo_command,
+        } => {
+            let axes = if all {
+                Axis::value_variants().to_vec()
+            } else {
+                axes
+            };
+            let request = match servo_command {
+                ServoCommand::Cmd { request } => Request::Servo { axes, request },
+            };
+            call(&args.bus, args.output, &socket_path, request).await?;
+        }
+        Command::Arm { arm_command } => {
+            let request = match arm_command {
+                ArmCommand::Startup => Request::Startup {
+                    profile: load_profile(args.profile.as_deref())?,
+                },
+                ArmCommand::Shutdown { pose } => {
+                    let profile = load_profile(args.profile.as_deref())?;
+                    let park = match pose {
+                        Some(name) => poses::get(&poses::load(&poses_path)?, &name)?.clone(),
+                        None => profile.park.clone().ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "the robot profile has no park pose, add a [park] section or use \
+                                 --pose"
+                            )
+                        })?,
+                    };
+                    Request::Shutdown { profile, park }
+                }
+                ArmCommand::Status => Request::State,
+            };
+            call(&args.bus, args.output, &socket_path, request).await?;
+        }
+        Command::Teach { teach_command } => match teach_command {
+            TeachCommand::Record { file, all, axes } => {
+                let axes = if all {
+                    Axis::value_variants().to_vec()
+                } else {
+                    axes
+                };
+                teach::record(&args.bus, args.output, &socket_path, &file, axes).await?;
+            }
+            TeachCommand::Replay { file } => {
+                let blocks = teach::replay(&teach::load(&file)?);
+                call(
+                    &args.bus,
+                    args.output,
+                    &socket_path,
+                    Request::Program { blocks },
+                )
+                .await?;
+            }
+            TeachCommand::Demonstrate {
+                file,
+                rate,
+                all,
+                axes,
+            } => {
+                let axes = if all {
+                    Axis::value_variants().to_vec()
+                } else {
+                    axes
+                };
+                teach::demonstrate(&args.bus, args.output, &socket_path, &file, axes, rate).await?;
+            }
+            TeachCommand::Play {
+                file,
+                speed,
+                timing,
+                smoothing,
+                interval,
+                rate,
+            } => {
+                if !(interval.is_finite() && interval > 0.0) {
+                    anyhow::bail!("knot interval must be a positive number, got {interval}");
+                }
+                let path = teach::load_path(&file, smoothing, interval, timing)?;
+                teach::play(&args.bus, args.output, &socket_path, path, speed, rate).await?;
+            }
+        },
+        Command::RunGcode { file, check } => {
+            use anyhow::Context as _;
+
+            let profile = load_profile(args.profile.as_deref())?;
+            let source = std::fs::read_to_string(&file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            let blocks = gcode::translate(&source, &profile)
+                .with_context(|| format!("invalid program {}", file.display()))?;
+            if check {
+                let records = blocks
+                    .iter()
+                    .map(|block| {
+                        let action = match &block.action {
+                            gcode::Action::Request(request) => serde_json::to_value(request)?
+                                .get("method")
+                                .cloned()
+                                .unwrap_or_default(),
+                            gcode::Action::Dwell(seconds) => format!("dwell {seconds} s").into(),
+                        };
+                        let mut record = output::Record::new();
+                        record.insert("line".to_owned(), block.line.into());
+                        record.insert("code".to_owned(), block.code.clone().into());
+                        record.insert("action".to_owned(), action);
+                        anyhow::Ok(record)
                     })
-                    .await?;
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                output::report(args.output, &records)?;
+            } else {
+                call(
+                    &args.bus,
+                    args.output,
+                    &socket_path,
+                    Request::Program { blocks },
+                )
+                .await?;
+            }
+        }
+        Command::Bus { bus_command } => match bus_command {
+            BusCommand::Monitor { axes, opcodes } => {
+                // Frames sent by other processes, including a daemon, are also received by this
+                // socket, so there is no need to go through the daemon.
+                let (arm, pump) = args.bus.open(true)?;
+                let mut stream = output::RecordStream::new(args.output, monitor::columns())?;
+                let monitor = monitor::monitor(arm.bus(), &axes, &opcodes, |r| stream.write(&r));
+                tokio::select! {
+                    result = pump => {
+                        result?;
+                        anyhow::bail!("CAN socket closed")
+                    }
+                    result = monitor => result?,
+                    _ = tokio::signal::ctrl_c() => {}
                 }
-                AxesCommand::SetOrigin => {
-                    par_map_canbus(axes, can_tx, can_rx, set_origin).await?;
+            }
+            BusCommand::Send { id, data, window } => {
+                // Responses are received by this socket even if a daemon is running, so there is
+                // no need to go through the daemon.
+                let (arm, pump) = args.bus.open(false)?;
+                let mut stream = output::RecordStream::new(args.output, monitor::columns())?;
+                let window = time::Duration::from_millis(window);
+                let send = monitor::send(arm.bus(), id, &data, window, |r| stream.write(&r));
+                tokio::select! {
+                    result = pump => {
+                        result?;
+                        anyhow::bail!("CAN socket closed")
+                    }
+                    result = send => result?,
                 }
             }
+        },
+    }
+
+    Ok(())
+}
+
+/// The columns of the records made from a struct.
+fn columns(value: impl serde::Serialize) -> anyhow::Result<Vec<String>> {
+    let serde_json::Value::Object(record) = serde_json::to_value(value)? else {
+        anyhow::bail!("records are made from structs")
+    };
+    Ok(record.into_iter().map(|(column, _)| column).collect())
+}
+
+/// Executes a request, through the daemon if one is listening on `socket_path`, and returns the
+/// records it produces.  Streamed records are passed to `on_record` instead.
+async fn dispatch(
+    bus: &BusOptions,
+    socket_path: &path::Path,
+    request: Request,
+    on_record: impl FnMut(output::Record) -> anyhow::Result<()>,
+) -> anyhow::Result<Vec<output::Record>> {
+    let client = if bus.needs_own_bus() {
+        None
+    } else {
+        daemon::Client::connect(socket_path).await?
+    };
+    let request = if bus.force {
+        Request::Force {
+            request: Box::new(request),
+        }
+    } else {
+        request
+    };
+    if let Some(mut client) = client {
+        client.call(&request, on_record).await
+    } else {
+        // Nothing is known about what earlier commands did, so the states of the axes are taken
+        // from the servos, unless they won't be checked anyway.
+        let (arm, pump) = bus.open(false)?;
+        let execution = async {
+            if !bus.force {
+                arm.seed_states().await;
+            }
+            execute(&arm, request, on_record).await
+        };
+        tokio::select! {
+            result = pump => {
+                result?;
+                anyhow::bail!("CAN socket closed")
+            }
+            result = execution => result,
         }
     }
+}
 
+/// Executes a request, through the daemon if one is listening on `socket_path`, and prints the
+/// records it produces.
+///
+/// Streaming requests run until Ctrl-C is pressed.  Motions that are interrupted stop all axes,
+/// and fail since they didn't complete.
+async fn call(
+    bus: &BusOptions,
+    format: output::OutputFormat,
+    socket_path: &path::Path,
+    request: Request,
+) -> anyhow::Result<()> {
+    use anyhow::Context as _;
+
+    let mut stream = match request {
+        Request::Watch { .. } => {
+            let mut columns = vec!["timestamp".to_owned()];
+            columns.extend(
+                output::to_record(&moves::Outcome {
+                    axis: Axis::X,
+                    result: Ok::<_, arm::Error>(arm::Telemetry::default()),
+                    elapsed: time::Duration::ZERO,
+                })?
+                .into_iter()
+                .map(|(column, _)| column),
+            );
+            columns.push("error".to_owned());
+            Some(output::RecordStream::new(format, columns)?)
+        }
+        Request::SamplePositions { .. } => Some(output::RecordStream::new(
+            format,
+            columns(moves::PositionSample::default())?,
+        )?),
+        Request::PlayPath { .. } => Some(output::RecordStream::new(
+            format,
+            columns(moves::PathProgress::default())?,
+        )?),
+        Request::Program { .. } => Some(output::RecordStream::new(
+            format,
+            columns(request::ProgramProgress::default())?,
+        )?),
+        Request::MoveLinear { .. } => Some(output::RecordStream::new(
+            format,
+            columns(moves::LinearProgress::default())?,
+        )?),
+        _ => None,
+    };
+    let streaming = stream.is_some();
+    let moving = matches!(
+        request,
+        Request::PlayPath { .. } | Request::MoveLinear { .. } | Request::Program { .. }
+    );
+    let on_record = |record: output::Record| match &mut stream {
+        Some(stream) => stream.write(&record),
+        None => anyhow::bail!("unexpected streamed record"),
+    };
+
+    let call = dispatch(bus, socket_path, request, on_record);
+    if streaming {
+        tokio::select! {
+            result = call => { result?; }
+            _ = tokio::signal::ctrl_c() => {
+                // Sampling runs until interrupted, which is not an error, but motions are cut
+                // short while the axes are still heading for the last setpoint they were sent.
+                if moving {
+                    let stop = Request::Stop {
+                        axes: Axis::ALL.to_vec(),
+                    };
+                    let records = dispatch(bus, socket_path, stop, |_| {
+                        anyhow::bail!("unexpected streamed record")
+                    })
+                    .await
+                    .context("interrupted, and failed to stop the axes")?;
+                    output::ensure_success(&records)
+                        .context("interrupted, and failed to stop the axes")?;
+                    anyhow::bail!("interrupted, all axes were stopped");
+                }
+            }
+        }
+    } else {
+        output::report(format, &call.await?)?;
+    }
     Ok(())
 }
@@ -0,0 +1,363 @@
+//! Passive decoding of the servo traffic on the bus.
+//!
+//! Requests from the host and responses from a servo are sent with the same CAN id, and many of
+//! them have the same layout, so frames are told apart by their length where possible, and
+//! otherwise by whether a request with the same opcode is still waiting for a response.  Requests
+//! that get no response, such as when responses are disabled, stop waiting after
+//! [`RESPONSE_WINDOW`], so that the next such request isn't taken for a response.
+//!
+//! Raw frames can also be sent with [`send`], for opcodes that the codec doesn't know about.
+use std::collections;
+use std::io;
+
+use arctos_can_driver::servo_cmd::{self, ProtocolError, ServoOpcode, ServoRequest, ServoResponse};
+use arctos_can_driver::{bus, Axis};
+use socketcan::EmbeddedFrame as _;
+use tokio::time;
+
+use crate::output;
+
+/// A decoded frame.
+#[derive(Debug)]
+pub enum Decoded {
+    Request(ServoRequest),
+    Response(ServoResponse),
+}
+
+/// How long a request waits for its response, like the arm does.
+const RESPONSE_WINDOW: time::Duration = time::Duration::from_millis(100);
+
+/// Decodes frames, keeping track of which requests are waiting for a response.
+#[derive(Debug, Default)]
+pub struct Monitor {
+    /// When each request that is waiting for a response was last seen, by CAN id and opcode.
+    pending: collections::BTreeMap<(u32, ServoOpcode), time::Instant>,
+}
+
+impl Monitor {
+    /// Decodes a frame seen at `now`.
+    pub fn decode(
+        &mut self,
+        frame: &socketcan::CanFrame,
+        now: time::Instant,
+    ) -> Result<Decoded, ProtocolError> {
+        let id = frame.id();
+        self.pending
+            .retain(|_, seen| now.duration_since(*seen) < RESPONSE_WINDOW);
+        let decoded = match (
+            ServoRequest::from_frame(id, frame),
+            ServoResponse::from_frame(id, frame),
+        ) {
+            (Ok(request), Err(_)) => Decoded::Request(request),
+            (Err(_), Ok(response)) => Decoded::Response(response),
+            (Ok(request), Ok(response)) => {
+                if self.pending.contains_key(&(raw_id(id), response.opcode())) {
+                    Decoded::Response(response)
+                } else {
+                    Decoded::Request(request)
+                }
+            }
+            (Err(request_err), Err(response_err)) => {
+                let awaiting_response = self.pending.keys().any(|&(i, _)| i == raw_id(id));
+                return Err(if awaiting_response {
+                    response_err
+                } else {
+                    request_err
+                });
+            }
+        };
+
+        match &decoded {
+            Decoded::Request(request) => {
+                // A request seen again waits anew, in case the first one went unanswered.
+                self.pending.insert((raw_id(id), request.opcode()), now);
+            }
+            Decoded::Response(response) => {
+                self.pending.remove(&(raw_id(id), response.opcode()));
+            }
+        }
+        Ok(decoded)
+    }
+}
+
+/// A bus tap that prints every frame to stderr, with its meaning.
+///
+/// Since the direction of every frame is known, there is no need for the guesswork of
+/// [`Monitor`].
+pub struct Printer;
+
+impl bus::Tap for Printer {
+    fn frame(&mut self, direction: bus::Direction, frame: &socketcan::CanFrame) -> io::Result<()> {
+        use io::Write as _;
+
+        let id = frame.id();
+        let (direction, decoded) = match direction {
+            bus::Direction::Sent => (
+                "sent",
+                ServoRequest::from_frame(id, frame).map(|r| format!("{r:?}")),
+            ),
+            bus::Direction::Received => (
+                "received",
+                ServoResponse::from_frame(id, frame).map(|r| format!("{r:?}")),
+            ),
+        };
+        let (crc, data) = match frame.data().split_last() {
+            Some((crc, data)) => (format!("{crc:02x}"), data),
+            None => (String::new(), frame.data()),
+        };
+        let axis = Axis::from_id(id).map_or_else(|| "-".to_owned(), |a| format!("{a:?}"));
+        writeln!(
+            io::stderr().lock(),
+            "{direction:<8} {:#05x} {axis} [{:<20}] crc {crc:<2}  {}",
+            raw_id(id),
+            hex(data),
+            decoded.unwrap_or_else(|err| format!("error: {err}")),
+        )
+    }
+}
+
+/// The columns of the records produced by [`monitor`].
+pub fn columns() -> Vec<String> {
+    [
+        "timestamp",
+        "id",
+        "axis",
+        "direction",
+        "opcode",
+        "data",
+        "decoded",
+        "error",
+    ]
+    .into_iter()
+    .map(str::to_owned)
+    .collect()
+}
+
+/// Decodes every frame seen on the bus, passing a record for every frame that matches the filters
+/// to `on_record`.  Empty filters match everything.
+pub async fn monitor(
+    bus: &bus::Dispatcher,
+    axes: &[Axis],
+    opcodes: &[ServoOpcode],
+    mut on_record: impl FnMut(output::Record) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut channel = bus.channel();
+    let mut monitor = Monitor::default();
+    loop {
+        let frame = match channel.recv().await {
+            Ok(frame) => frame,
+            Err(arctos_can_driver::arm::Error::Lagged(n)) => {
+                tracing::warn!("missed {n} frames");
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        // Decode every frame, even filtered ones, to keep track of pending requests.
+        let decoded = monitor.decode(&frame, time::Instant::now());
+        if matches(&frame, axes, opcodes) {
+            on_record(to_record(timestamp, &frame, decoded)?)?;
+        }
+    }
+}
+
+/// Whether a frame matches the filters of [`monitor`].
+fn matches(frame: &socketcan::CanFrame, axes: &[Axis], opcodes: &[ServoOpcode]) -> bool {
+    let axis = Axis::from_id(frame.id());
+    let opcode = frame.data().first();
+    (axes.is_empty() || axis.is_some_and(|axis| axes.contains(&axis)))
+        && (opcodes.is_empty() || opcodes.iter().any(|&o| opcode == Some(&(o as u8))))
+}
+
+/// Sends a frame to the bus, appending the checksum, and passes a record for every frame that is
+/// received from the same id within `window` to `on_record`.
+pub async fn send(
+    bus: &bus::Dispatcher,
+    id: socketcan::StandardId,
+    data: &[u8],
+    window: time::Duration,
+    mut on_record: impl FnMut(output::Record) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let id = socketcan::Id::Standard(id);
+    let mut data = data.to_vec();
+    data.push(servo_cmd::compute_crc(id, &data));
+    let frame = socketcan::CanFrame::new(id, &data).ok_or_else(|| {
+        anyhow::anyhow!(
+            "frames hold at most 7 bytes besides the checksum, got {}",
+            data.len() - 1
+        )
+    })?;
+
+    // Keep other operations on the axis from seeing the responses.
+    let _lock = match Axis::from_id(id) {
+        Some(axis) => Some(bus.lock(axis).await),
+        None => None,
+    };
+    let mut channel = bus.channel();
+    channel.send(frame).await?;
+    let deadline = time::Instant::now() + window;
+    loop {
+        let frame = match time::timeout_at(deadline, channel.recv()).await {
+            Ok(Ok(frame)) => frame,
+            Ok(Err(arctos_can_driver::arm::Error::Lagged(n))) => {
+                tracing::warn!("missed {n} frames");
+                continue;
+            }
+            Ok(Err(err)) => return Err(err.into()),
+            Err(_) => return Ok(()),
+        };
+        if frame.id() != id {
+            continue;
+        }
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        let decoded = ServoResponse::from_frame(id, &frame).map(Decoded::Response);
+        on_record(to_record(timestamp, &frame, decoded)?)?;
+    }
+}
+
+/// Describes a frame as a record with the [`columns`].
+fn to_record(
+    timestamp: std::time::Duration,
+    frame: &socketcan::CanFrame,
+    decoded: Result<Decoded, ProtocolError>,
+) -> anyhow::Result<output::Record> {
+    let axis = Axis::from_id(frame.id());
+    let mut record = output::Record::new();
+    record.insert(
+        "timestamp".to_owned(),
+        serde_json::to_value(timestamp.as_secs_f64())?,
+    );
+    record.insert(
+        "id".to_owned(),
+        format!("{:#05x}", raw_id(frame.id())).into(),
+    );
+    record.insert("axis".to_owned(), serde_json::to_value(axis)?);
+    record.insert("data".to_owned(), hex(frame.data()).into());
+    match decoded {
+        Ok(Decoded::Request(request)) => {
+            record.insert("direction".to_owned(), "request".into());
+            record.insert(
+                "opcode".to_owned(),
+                format!("{:?}", request.opcode()).into(),
+            );
+            record.insert("decoded".to_owned(), format!("{request:?}").into());
+        }
+        Ok(Decoded::Response(response)) => {
+            record.insert("direction".to_owned(), "response".into());
+            record.insert(
+                "opcode".to_owned(),
+                format!("{:?}", response.opcode()).into(),
+            );
+            record.insert("decoded".to_owned(), format!("{response:?}").into());
+        }
+        Err(err) => {
+            record.insert("error".to_owned(), err.to_string().into());
+        }
+    }
+    Ok(record)
+}
+
+fn raw_id(id: socketcan::Id) -> u32 {
+    match id {
+        socketcan::Id::Standard(id) => id.as_raw().into(),
+        socketcan::Id::Extended(id) => id.as_raw(),
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame to or from the X axis with the given opcode and arguments, followed by their CRC.
+    fn frame(data: &[u8]) -> socketcan::CanFrame {
+        let id = Axis::X.id();
+        let mut data = data.to_vec();
+        data.push(servo_cmd::compute_crc(id, &data));
+        socketcan::CanFrame::new(id, &data).unwrap()
+    }
+
+    #[test]
+    fn requests_and_responses_with_the_same_bytes() {
+        // Enabling and its successful response are both `f3 01`.
+        let enable = frame(&[0xf3, 0x01]);
+        let mut monitor = Monitor::default();
+        let now = time::Instant::now();
+        assert!(matches!(
+            monitor.decode(&enable, now),
+            Ok(Decoded::Request(ServoRequest::Enable { enabled: true }))
+        ));
+        assert!(matches!(
+            monitor.decode(&enable, now),
+            Ok(Decoded::Response(ServoResponse::Enable { success: true }))
+        ));
+        // The response was seen, so the next one is a request again.
+        assert!(matches!(
+            monitor.decode(&enable, now),
+            Ok(Decoded::Request(_))
+        ));
+    }
+
+    #[test]
+    fn unanswered_requests_expire() {
+        let enable = frame(&[0xf3, 0x01]);
+        let mut monitor = Monitor::default();
+        let now = time::Instant::now();
+        monitor.decode(&enable, now).unwrap();
+        let later = now + RESPONSE_WINDOW;
+        assert!(matches!(
+            monitor.decode(&enable, later),
+            Ok(Decoded::Request(_))
+        ));
+        // The request seen again is the one waiting now.
+        assert!(matches!(
+            monitor.decode(&enable, later + RESPONSE_WINDOW / 2),
+            Ok(Decoded::Response(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut monitor = Monitor::default();
+        assert_eq!(
+            monitor
+                .decode(&frame(&[0x00]), time::Instant::now())
+                .unwrap_err(),
+            ProtocolError::UnknownOpcode { opcode: 0x00 }
+        );
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut monitor = Monitor::default();
+        let corrupt = socketcan::CanFrame::new(Axis::X.id(), &[0x30, 0x00]).unwrap();
+        assert!(matches!(
+            monitor.decode(&corrupt, time::Instant::now()),
+            Err(ProtocolError::CrcMismatch { actual: 0x00, .. })
+        ));
+        // Errors don't leave anything waiting for a response.
+        assert!(monitor.pending.is_empty());
+    }
+
+    #[test]
+    fn filters() {
+        let enable = frame(&[0xf3, 0x01]);
+        assert!(matches(&enable, &[], &[]));
+        assert!(matches(&enable, &[Axis::X, Axis::Y], &[]));
+        assert!(!matches(&enable, &[Axis::Y], &[]));
+        assert!(matches(&enable, &[], &[ServoOpcode::Enable]));
+        assert!(!matches(&enable, &[], &[ServoOpcode::QueryStatus]));
+        assert!(!matches(&enable, &[Axis::X], &[ServoOpcode::QueryStatus]));
+        // Frames from ids other than the axes only match when no axes are given.
+        let other =
+            socketcan::CanFrame::new(socketcan::StandardId::new(0x7).unwrap(), &[0xf3]).unwrap();
+        assert!(matches(&other, &[], &[ServoOpcode::Enable]));
+        assert!(!matches(&other, &[Axis::X], &[]));
+    }
+}
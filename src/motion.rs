@@ -0,0 +1,714 @@
+//! Planning of servo motions.
+//!
+//! The servos ramp their speed up and down by 1 RPM at a time, with `(256 - accel) * 50 µs`
+//! between the steps, so a motion follows a trapezoidal speed profile.  Travel is in number of
+//! servo rotations, like the positions of `ServoAxis::move_to`.
+
+/// The speed and acceleration parameters of a servo motion.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ramp {
+    /// The top speed in RPM.
+    pub speed: u16,
+    /// The raw acceleration, where `0` means that the top speed is reached immediately.
+    pub accel: u8,
+}
+
+/// Time between two speed steps of 1 RPM for an `accel` value of 255, in seconds.
+const STEP_TIME: f64 = 50e-6;
+
+impl Ramp {
+    /// The acceleration in RPM/s.
+    pub fn accel_rate(&self) -> f64 {
+        if self.accel == 0 {
+            f64::INFINITY
+        } else {
+            1.0 / ((256 - u16::from(self.accel)) as f64 * STEP_TIME)
+        }
+    }
+
+    /// How long a motion over `travel` rotations takes, in seconds, including the ramps at both
+    /// ends.
+    pub fn duration(&self, travel: f64) -> f64 {
+        let travel = travel.abs();
+        if travel == 0.0 {
+            return 0.0;
+        }
+        let speed = f64::from(self.speed.max(1));
+        let accel = self.accel_rate();
+        if travel * 60.0 * accel >= speed * speed {
+            // Trapezoid: the top speed is reached and held for a while.
+            60.0 * travel / speed + speed / accel
+        } else {
+            // Triangle: the motion is too short to reach the top speed.
+            2.0 * (60.0 * travel / accel).sqrt()
+        }
+    }
+}
+
+/// Plans motions for several servos so that they all start and finish at the same time, so that
+/// the joints move in a coordinated way.
+///
+/// Every motion is given as its travel and the fastest ramp that the servo may use.  The motion
+/// that takes longest with its fastest ramp keeps it, and all other motions are slowed down to
+/// take just as long, with their acceleration scaled by their travel so that the speed profiles
+/// have the same shape.  Since both speed and acceleration are whole numbers, the durations only
+/// match approximately, especially for short motions.
+pub fn synchronize(motions: &[(f64, Ramp)]) -> Vec<Ramp> {
+    let Some((lead_travel, lead)) = motions
+        .iter()
+        .map(|&(travel, ramp)| (travel.abs(), ramp))
+        .max_by(|(a, ra), (b, rb)| ra.duration(*a).total_cmp(&rb.duration(*b)))
+    else {
+        return Vec::new();
+    };
+    let duration = lead.duration(lead_travel);
+    if duration == 0.0 {
+        return motions.iter().map(|&(_, ramp)| ramp).collect();
+    }
+
+    motions
+        .iter()
+        .map(|&(travel, limit)| {
+            let travel = travel.abs();
+            if travel == 0.0 {
+                return limit;
+            }
+            let accel = scaled_accel(lead.accel_rate() * travel / lead_travel, limit.accel);
+            let ramp = Ramp { speed: 0, accel };
+            let rate = ramp.accel_rate();
+            // Solve `duration = 60 * travel / speed + speed / rate` for the lower speed, which is
+            // the one that reaches it with a trapezoid.
+            let speed = if rate.is_infinite() {
+                60.0 * travel / duration
+            } else {
+                let discriminant = (rate * duration).powi(2) - 240.0 * travel * rate;
+                (rate * duration - discriminant.max(0.0).sqrt()) / 2.0
+            };
+            Ramp {
+                speed: (speed.round() as u16).clamp(1, limit.speed.max(1)),
+                accel,
+            }
+        })
+        .collect()
+}
+
+/// The raw acceleration that comes closest to `rate` RPM/s without going below it, and without
+/// going above the raw acceleration `limit`.
+fn scaled_accel(rate: f64, limit: u8) -> u8 {
+    if limit == 0 {
+        return if rate.is_infinite() { 0 } else { 255 };
+    }
+    if rate.is_infinite() {
+        return limit;
+    }
+    let accel = 256.0 - 1.0 / (rate * STEP_TIME);
+    // Allow for rounding errors, so that an unscaled rate gives back the same raw acceleration.
+    ((accel - 1e-6).ceil().clamp(1.0, 255.0) as u8).min(limit)
+}
+
+/// The shape of a speed profile generated on the host.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Shape {
+    /// Constant acceleration up to the top speed, like the built-in ramp of the servo.
+    Trapezoid,
+    /// Acceleration that ramps up and down with limited jerk, for smoother motions.
+    #[default]
+    SCurve,
+}
+
+/// The limits of a speed profile generated on the host.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Limits {
+    /// The top speed in RPM.
+    pub speed: f64,
+    /// The largest acceleration in RPM/s.
+    pub accel: f64,
+    /// The largest jerk in RPM/s², which only limits S-curves.
+    pub jerk: f64,
+}
+
+/// A speed profile for a motion, to be streamed to a servo in speed mode.
+///
+/// The speed ramps up to its peak, cruises, and ramps down symmetrically.  Trapezoids ramp with
+/// constant acceleration, while S-curves ramp the acceleration itself up and down with constant
+/// jerk.  Short motions don't reach the top speed, or even the largest acceleration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpeedProfile {
+    /// Travel in number of servo rotations, with sign.
+    travel: f64,
+    /// Peak speed in rotations per second.
+    peak: f64,
+    /// Peak acceleration in rotations per second².
+    accel: f64,
+    /// Jerk in rotations per second³, which is infinite for trapezoids.
+    jerk: f64,
+    /// Duration of each ramp, in seconds.
+    ramp: f64,
+    /// Duration of the motion, in seconds.
+    duration: f64,
+}
+
+impl SpeedProfile {
+    /// Plans a profile over `travel` servo rotations.
+    pub fn new(travel: f64, shape: Shape, limits: &Limits) -> Self {
+        let speed = limits.speed.max(1.0) / 60.0;
+        let accel = limits.accel.max(1.0) / 60.0;
+        let jerk = match shape {
+            Shape::Trapezoid => f64::INFINITY,
+            Shape::SCurve => limits.jerk.max(1.0) / 60.0,
+        };
+        let distance = travel.abs();
+        let ramp_time = |peak: f64| {
+            if peak * jerk >= accel * accel {
+                peak / accel + accel / jerk
+            } else {
+                2.0 * (peak / jerk).sqrt()
+            }
+        };
+
+        // Both ramps together cover `peak * ramp_time(peak)`, which grows with the peak speed, so
+        // find the highest peak that fits in the travel.
+        let peak = if speed * ramp_time(speed) <= distance {
+            speed
+        } else {
+            let (mut low, mut high) = (0.0, speed);
+            for _ in 0..64 {
+                let mid = (low + high) / 2.0;
+                if mid * ramp_time(mid) <= distance {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+            low
+        };
+        let ramp = ramp_time(peak);
+        let duration = if peak > 0.0 {
+            ramp + distance / peak
+        } else {
+            0.0
+        };
+        Self {
+            travel,
+            peak,
+            accel: accel.min((peak * jerk).sqrt()),
+            jerk,
+            ramp,
+            duration,
+        }
+    }
+
+    /// How long the motion takes, in seconds.
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// The planned speed in RPM at time `t` seconds, with the sign of the travel.
+    pub fn speed(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, self.duration);
+        let speed = if t < self.ramp {
+            self.ramp_speed(t)
+        } else if t > self.duration - self.ramp {
+            self.ramp_speed(self.duration - t)
+        } else {
+            self.peak
+        };
+        speed * 60.0 * self.travel.signum()
+    }
+
+    /// The planned travel in servo rotations at time `t` seconds, with sign.
+    pub fn position(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, self.duration);
+        let distance = if t < self.ramp {
+            self.ramp_distance(t)
+        } else if t > self.duration - self.ramp {
+            self.travel.abs() - self.ramp_distance(self.duration - t)
+        } else {
+            self.ramp_distance(self.ramp) + self.peak * (t - self.ramp)
+        };
+        distance * self.travel.signum()
+    }
+
+    /// The time spent at constant jerk at either end of a ramp.
+    fn jerk_time(&self) -> f64 {
+        self.accel / self.jerk
+    }
+
+    /// The speed `t` seconds into the ramp up, in rotations per second.
+    fn ramp_speed(&self, t: f64) -> f64 {
+        let tj = self.jerk_time();
+        if t < tj {
+            self.jerk * t * t / 2.0
+        } else if t > self.ramp - tj {
+            let left = self.ramp - t;
+            self.peak - self.jerk * left * left / 2.0
+        } else {
+            // After the jerk phase, at a speed of `accel * tj / 2`, the speed grows at the full
+            // acceleration.
+            self.accel * (t - tj / 2.0)
+        }
+    }
+
+    /// The distance covered `t` seconds into the ramp up, in rotations.
+    fn ramp_distance(&self, t: f64) -> f64 {
+        let tj = self.jerk_time();
+        if t < tj {
+            self.jerk * t.powi(3) / 6.0
+        } else if t > self.ramp - tj {
+            // The ramp covers `peak * ramp / 2` in total, since it is symmetric.
+            let left = self.ramp - t;
+            self.peak * self.ramp / 2.0 - (self.peak * left - self.jerk * left.powi(3) / 6.0)
+        } else {
+            let jerk_distance = self.accel * tj * tj / 6.0;
+            jerk_distance + self.accel * ((t - tj / 2.0).powi(2) - tj * tj / 4.0) / 2.0
+        }
+    }
+}
+
+/// Raw positions of all axes in number of servo rotations from origin, in the order of
+/// [`crate::Axis::ALL`].
+pub type Positions = [f64; 6];
+
+/// How a recorded path is timed when it is played back.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Timing {
+    /// As recorded, including pauses and changes of speed.
+    #[default]
+    Preserved,
+    /// At an even pace over the same total time, without pauses.
+    Normalized,
+}
+
+/// A smooth path through the positions of all axes over time, fitted to a recording.
+///
+/// The path is a cubic Hermite spline through knots, with Catmull-Rom tangents, so that it
+/// passes through every knot with a continuous speed.  It starts and ends at rest.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spline {
+    /// Time of every knot in seconds from the start, increasing.
+    times: Vec<f64>,
+    knots: Vec<Positions>,
+}
+
+impl Spline {
+    /// Fits a path to `(time, positions)` samples, ordered by time.
+    ///
+    /// The samples are smoothed with a moving average over `window` seconds, to filter out the
+    /// jitter of a hand-guided recording, and knots are then placed every `interval` seconds.
+    /// The first and last samples are kept as they are.
+    pub fn fit(samples: &[(f64, Positions)], window: f64, interval: f64, timing: Timing) -> Self {
+        let Some(&(start, _)) = samples.first() else {
+            return Self {
+                times: Vec::new(),
+                knots: Vec::new(),
+            };
+        };
+        let end = samples[samples.len() - 1].0;
+
+        let smoothed = samples
+            .iter()
+            .map(|&(time, _)| {
+                // Shrink the window close to the ends, so that it stays centered on the sample.
+                let half = (window / 2.0).min(time - start).min(end - time).max(0.0);
+                let (mut sum, mut count) = (Positions::default(), 0.0);
+                for (_, positions) in samples
+                    .iter()
+                    .skip_while(|&&(t, _)| t < time - half)
+                    .take_while(|&&(t, _)| t <= time + half)
+                {
+                    for (sum, position) in sum.iter_mut().zip(positions) {
+                        *sum += position;
+                    }
+                    count += 1.0;
+                }
+                (time - start, sum.map(|sum| sum / count))
+            })
+            .collect::<Vec<_>>();
+
+        let duration = end - start;
+        let count = (duration / interval.max(f64::EPSILON)).ceil().max(1.0) as usize;
+        let mut times = Vec::with_capacity(count + 1);
+        let mut knots = Vec::with_capacity(count + 1);
+        for i in 0..=count {
+            let time = (i as f64 * interval).min(duration);
+            times.push(time);
+            knots.push(interpolate(&smoothed, time));
+            if time >= duration {
+                break;
+            }
+        }
+
+        if timing == Timing::Normalized {
+            // Time every segment by how far the axis with the most travel moves.
+            let mut distances = vec![0.0];
+            for pair in knots.windows(2) {
+                let travel = pair[0]
+                    .iter()
+                    .zip(&pair[1])
+                    .map(|(a, b)| (b - a).abs())
+                    .fold(0.0, f64::max);
+                distances.push(distances[distances.len() - 1] + travel);
+            }
+            let total = distances[distances.len() - 1];
+            if total > 0.0 {
+                let (mut retimed, mut kept) = (Vec::new(), Vec::new());
+                for ((distance, knot), i) in distances.iter().zip(&knots).zip(0..) {
+                    // Pauses would leave several knots at the same time.
+                    if i > 0 && distance - distances[i - 1] <= 0.0 {
+                        continue;
+                    }
+                    retimed.push(distance / total * duration);
+                    kept.push(*knot);
+                }
+                times = retimed;
+                knots = kept;
+            }
+        }
+        Self { times, knots }
+    }
+
+    /// How long the path takes, in seconds.
+    pub fn duration(&self) -> f64 {
+        self.times.last().copied().unwrap_or_default()
+    }
+
+    /// The positions at time `t` seconds from the start.
+    pub fn position(&self, t: f64) -> Positions {
+        let Some(&last) = self.knots.last() else {
+            return Positions::default();
+        };
+        let t = t.clamp(0.0, self.duration());
+        let i = self.times.partition_point(|&time| time <= t);
+        if i == 0 || i >= self.knots.len() {
+            return if i == 0 { self.knots[0] } else { last };
+        }
+        let (t0, t1) = (self.times[i - 1], self.times[i]);
+        let h = t1 - t0;
+        let s = (t - t0) / h;
+        let (m0, m1) = (self.tangent(i - 1), self.tangent(i));
+        let mut positions = Positions::default();
+        for (axis, position) in positions.iter_mut().enumerate() {
+            let (p0, p1) = (self.knots[i - 1][axis], self.knots[i][axis]);
+            *position = (2.0 * s.powi(3) - 3.0 * s.powi(2) + 1.0) * p0
+                + (s.powi(3) - 2.0 * s.powi(2) + s) * h * m0[axis]
+                + (-2.0 * s.powi(3) + 3.0 * s.powi(2)) * p1
+                + (s.powi(3) - s.powi(2)) * h * m1[axis];
+        }
+        positions
+    }
+
+    /// The speed at a knot in rotations per second, which is zero at both ends.
+    fn tangent(&self, i: usize) -> Positions {
+        if i == 0 || i + 1 >= self.knots.len() {
+            return Positions::default();
+        }
+        let dt = self.times[i + 1] - self.times[i - 1];
+        let mut tangent = Positions::default();
+        for (axis, tangent) in tangent.iter_mut().enumerate() {
+            *tangent = (self.knots[i + 1][axis] - self.knots[i - 1][axis]) / dt;
+        }
+        tangent
+    }
+}
+
+/// Linearly interpolates between samples ordered by time.
+fn interpolate(samples: &[(f64, Positions)], time: f64) -> Positions {
+    let i = samples.partition_point(|&(t, _)| t <= time);
+    if i == 0 {
+        return samples[0].1;
+    }
+    if i >= samples.len() {
+        return samples[samples.len() - 1].1;
+    }
+    let ((t0, p0), (t1, p1)) = (samples[i - 1], samples[i]);
+    let s = if t1 > t0 {
+        (time - t0) / (t1 - t0)
+    } else {
+        0.0
+    };
+    let mut positions = Positions::default();
+    for ((position, a), b) in positions.iter_mut().zip(p0).zip(p1) {
+        *position = a + (b - a) * s;
+    }
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} != {expected}"
+        );
+    }
+
+    #[test]
+    fn accel_rate() {
+        assert_eq!(Ramp { speed: 1, accel: 0 }.accel_rate(), f64::INFINITY);
+        assert_close(
+            Ramp {
+                speed: 1,
+                accel: 255,
+            }
+            .accel_rate(),
+            20000.0,
+            1e-9,
+        );
+        assert_close(
+            Ramp {
+                speed: 1,
+                accel: 56,
+            }
+            .accel_rate(),
+            100.0,
+            1e-9,
+        );
+    }
+
+    #[test]
+    fn ramp_duration() {
+        // 100 RPM/s, so that 100 RPM is reached after one second, and 5/3 rotations.
+        let ramp = Ramp {
+            speed: 100,
+            accel: 56,
+        };
+        let boundary = 100.0 * 100.0 / (60.0 * 100.0);
+        assert_eq!(ramp.duration(0.0), 0.0);
+        assert_close(ramp.duration(boundary), 2.0, 1e-9);
+        assert_close(ramp.duration(-boundary), 2.0, 1e-9);
+        // Just below the boundary the motion is a triangle, and just above a trapezoid, which
+        // take as long at the boundary itself.
+        assert_close(ramp.duration(boundary - 1e-9), 2.0, 1e-6);
+        assert_close(ramp.duration(boundary + 1e-9), 2.0, 1e-6);
+        assert_close(ramp.duration(boundary / 4.0), 1.0, 1e-9);
+        assert_close(ramp.duration(boundary * 2.0), 3.0, 1e-9);
+        // Without a ramp, the top speed is held for the whole motion.
+        let ramp = Ramp {
+            speed: 100,
+            accel: 0,
+        };
+        assert_close(ramp.duration(boundary), 1.0, 1e-9);
+    }
+
+    #[test]
+    fn synchronized_durations() {
+        let limit = Ramp {
+            speed: 1000,
+            accel: 200,
+        };
+        let motions = [(100.0, limit), (25.0, limit), (-60.0, limit), (0.0, limit)];
+        let ramps = synchronize(&motions);
+        assert_eq!(ramps.len(), motions.len());
+        // The longest motion keeps its ramp, and motions without travel are left alone.
+        assert_eq!(ramps[0], limit);
+        assert_eq!(ramps[3], limit);
+        let duration = limit.duration(100.0);
+        for ((travel, limit), ramp) in motions.iter().zip(&ramps).take(3) {
+            assert!(ramp.speed <= limit.speed && ramp.accel <= limit.accel);
+            assert_close(ramp.duration(*travel), duration, duration * 0.01);
+        }
+
+        // Short motions are triangles, which are synchronized too.
+        let limit = Ramp {
+            speed: 3000,
+            accel: 100,
+        };
+        let motions = [(0.5, limit), (0.1, limit)];
+        let ramps = synchronize(&motions);
+        let duration = limit.duration(0.5);
+        assert_close(ramps[1].duration(0.1), duration, duration * 0.05);
+
+        assert!(synchronize(&[]).is_empty());
+    }
+
+    /// 600 RPM, 1200 RPM/s and 6000 RPM/s², or 10 rotations/s, 20 rotations/s² and 100
+    /// rotations/s³.
+    const LIMITS: Limits = Limits {
+        speed: 600.0,
+        accel: 1200.0,
+        jerk: 6000.0,
+    };
+
+    /// Checks that a profile starts and ends at rest, is symmetric, and covers its travel.
+    fn assert_consistent(profile: &SpeedProfile, travel: f64) {
+        let duration = profile.duration();
+        assert_eq!(profile.speed(0.0), 0.0);
+        assert_close(profile.speed(duration), 0.0, 1e-9);
+        assert_eq!(profile.position(0.0), 0.0);
+        assert_close(profile.position(duration), travel, 1e-9);
+        assert_close(profile.position(duration / 2.0), travel / 2.0, 1e-9);
+        for i in 0..=100 {
+            let t = duration * f64::from(i) / 100.0;
+            assert_close(profile.speed(t), profile.speed(duration - t), 1e-6);
+            assert!(profile.speed(t).abs() <= LIMITS.speed + 1e-9);
+        }
+    }
+
+    #[test]
+    fn trapezoid() {
+        // The top speed is reached after 0.5 s, over 2.5 rotations.
+        let profile = SpeedProfile::new(100.0, Shape::Trapezoid, &LIMITS);
+        assert_close(profile.duration(), 0.5 + 100.0 / 10.0, 1e-9);
+        assert_close(profile.speed(0.25), 300.0, 1e-9);
+        assert_close(profile.speed(0.5), 600.0, 1e-9);
+        assert_close(profile.position(0.5), 2.5, 1e-9);
+        assert_consistent(&profile, 100.0);
+
+        // Too short to reach the top speed: the peak is `sqrt(travel * accel)` at half time.
+        let profile = SpeedProfile::new(-1.0, Shape::Trapezoid, &LIMITS);
+        let peak = 20.0f64.sqrt();
+        assert_close(profile.duration(), 2.0 * peak / 20.0, 1e-9);
+        assert_close(profile.speed(peak / 20.0), -peak * 60.0, 1e-6);
+        assert_consistent(&profile, -1.0);
+
+        // Exactly the travel of both ramps: a triangle that just reaches the top speed.
+        let profile = SpeedProfile::new(5.0, Shape::Trapezoid, &LIMITS);
+        assert_close(profile.duration(), 1.0, 1e-9);
+        assert_close(profile.speed(0.5), 600.0, 1e-9);
+    }
+
+    #[test]
+    fn s_curve() {
+        // The acceleration ramps up for 0.2 s, and the ramp takes `peak / accel + accel / jerk`.
+        let profile = SpeedProfile::new(100.0, Shape::SCurve, &LIMITS);
+        assert_close(profile.duration(), 0.5 + 0.2 + 100.0 / 10.0, 1e-9);
+        assert_close(profile.speed(0.2), 0.5 * 100.0 * 0.2 * 0.2 * 60.0, 1e-9);
+        assert_close(profile.speed(0.7), 600.0, 1e-9);
+        assert_consistent(&profile, 100.0);
+
+        // With little jerk, the largest acceleration isn't reached before the top speed, and the
+        // ramp takes `2 * sqrt(peak / jerk)`.
+        let limits = Limits {
+            jerk: 600.0,
+            ..LIMITS
+        };
+        let profile = SpeedProfile::new(100.0, Shape::SCurve, &limits);
+        assert_close(profile.duration(), 2.0 + 100.0 / 10.0, 1e-9);
+        assert_close(profile.speed(1.0), 300.0, 1e-9);
+        assert_consistent(&profile, 100.0);
+
+        // Infinite jerk is a trapezoid.
+        let limits = Limits {
+            jerk: f64::INFINITY,
+            ..LIMITS
+        };
+        let profile = SpeedProfile::new(100.0, Shape::SCurve, &limits);
+        assert_eq!(profile, SpeedProfile::new(100.0, Shape::Trapezoid, &LIMITS));
+
+        // Too short to reach the top speed, but still within the largest acceleration.
+        let profile = SpeedProfile::new(1.0, Shape::SCurve, &LIMITS);
+        assert!(profile.duration() > SpeedProfile::new(1.0, Shape::Trapezoid, &LIMITS).duration());
+        assert_consistent(&profile, 1.0);
+    }
+
+    /// A demonstration of 4 s, moving the first two axes for 1.5 s, pausing for 1 s, and moving
+    /// them back.  The other axes stay where they are.
+    fn demonstration() -> Vec<(f64, Positions)> {
+        (0..=40)
+            .map(|i| {
+                let t = f64::from(i) * 0.1;
+                let x = if t < 1.5 {
+                    t * 2.0
+                } else if t < 2.5 {
+                    3.0
+                } else {
+                    3.0 - (t - 2.5) * 2.0
+                };
+                // Recordings start some time after the epoch of their clock.
+                (10.0 + t, [x, -x, 0.0, 1.0, 2.0, 3.0])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spline_passes_through_knots() {
+        let samples = demonstration();
+        let spline = Spline::fit(&samples, 0.0, 0.1, Timing::Preserved);
+        assert_close(spline.duration(), 4.0, 1e-9);
+        assert_eq!(spline.knots.len(), samples.len());
+        // Without smoothing, and with a knot at every sample, the knots are the samples.
+        for ((time, knot), (sample_time, sample)) in
+            spline.times.iter().zip(&spline.knots).zip(&samples)
+        {
+            assert_close(*time, sample_time - 10.0, 1e-9);
+            for (position, sample) in spline.position(*time).iter().zip(sample) {
+                assert_close(*position, *sample, 1e-9);
+            }
+            assert_eq!(knot[3..], [1.0, 2.0, 3.0]);
+        }
+        // Beyond the ends, the path stays at the first and last knot.
+        assert_eq!(spline.position(-1.0), spline.knots[0]);
+        assert_eq!(spline.position(5.0), spline.knots[40]);
+    }
+
+    #[test]
+    fn spline_tangents() {
+        let spline = Spline::fit(&demonstration(), 0.0, 0.1, Timing::Preserved);
+        // At rest at both ends, and Catmull-Rom in between.
+        assert_eq!(spline.tangent(0), Positions::default());
+        assert_eq!(spline.tangent(40), Positions::default());
+        assert_close(spline.tangent(5)[0], 2.0, 1e-9);
+        assert_close(spline.tangent(5)[1], -2.0, 1e-9);
+        assert_close(spline.tangent(20)[0], 0.0, 1e-9);
+        // The speed is continuous through a knot.
+        let dt = 1e-6;
+        let speed = |t: f64| (spline.position(t + dt)[0] - spline.position(t - dt)[0]) / (2.0 * dt);
+        assert_close(speed(0.5 - 1e-4), speed(0.5 + 1e-4), 1e-2);
+        assert_close(speed(0.5), 2.0, 1e-3);
+    }
+
+    #[test]
+    fn spline_smoothing() {
+        let mut samples = demonstration();
+        for (i, (_, positions)) in samples.iter_mut().enumerate() {
+            positions[0] += if i % 2 == 0 { 0.05 } else { -0.05 };
+        }
+        let spline = Spline::fit(&samples, 0.3, 0.2, Timing::Preserved);
+        assert_eq!(spline.times.len(), 21);
+        // The ends are kept, while the jitter in between is averaged out.
+        assert_eq!(spline.position(0.0), samples[0].1);
+        assert_eq!(spline.position(4.0), samples[40].1);
+        assert_close(spline.position(1.0)[0], 2.0, 0.02);
+        assert_close(spline.position(2.0)[0], 3.0, 0.02);
+    }
+
+    #[test]
+    fn normalized_timing() {
+        let samples = demonstration();
+        let spline = Spline::fit(&samples, 0.0, 0.1, Timing::Normalized);
+        assert_close(spline.duration(), 4.0, 1e-9);
+        assert!(
+            spline.times.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:?}",
+            spline.times
+        );
+        // The pause is left out, and the pace is even: half the travel takes half the time.
+        assert_eq!(spline.knots.len(), samples.len() - 10);
+        assert_close(spline.position(1.0)[0], 1.5, 1e-9);
+        assert_close(spline.position(2.0)[0], 3.0, 1e-9);
+        assert_eq!(spline.position(4.0), samples[40].1);
+
+        // Without any motion, the timing is kept.
+        let still = [(0.0, [1.0; 6]), (1.0, [1.0; 6]), (2.0, [1.0; 6])];
+        let spline = Spline::fit(&still, 0.0, 1.0, Timing::Normalized);
+        assert_eq!(spline.times, [0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn empty_spline() {
+        let spline = Spline::fit(&[], 0.2, 0.1, Timing::Preserved);
+        assert_eq!(spline.duration(), 0.0);
+        assert_eq!(spline.position(1.0), Positions::default());
+    }
+}
@@ -0,0 +1,390 @@
+use std::io;
+
+use arctos_can_driver::arm::moves;
+
+/// Format used when printing command results to stdout.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// A human readable table with aligned columns.
+    #[default]
+    Table,
+    /// One JSON object per line.
+    Json,
+    /// Comma-separated values, with a header row.
+    Csv,
+}
+
+pub type Record = serde_json::Map<String, serde_json::Value>;
+
+/// Flattens an outcome into a single record; fields of the value (if it is a struct) become
+/// columns of their own.
+pub fn to_record<T, E>(outcome: &moves::Outcome<T, E>) -> anyhow::Result<Record>
+where
+    T: serde::Serialize,
+    E: std::error::Error + 'static,
+{
+    use serde_json::Value;
+
+    let mut record = Record::new();
+    record.insert("axis".to_owned(), serde_json::to_value(outcome.axis)?);
+    record.insert("success".to_owned(), Value::Bool(outcome.result.is_ok()));
+    record.insert(
+        "elapsed_ms".to_owned(),
+        serde_json::to_value(outcome.elapsed.as_secs_f64() * 1000.0)?,
+    );
+    match &outcome.result {
+        Ok(value) => match serde_json::to_value(value)? {
+            Value::Null => {}
+            Value::Object(fields) => record.extend(fields),
+            other => {
+                record.insert("value".to_owned(), other);
+            }
+        },
+        Err(err) => {
+            record.insert("error".to_owned(), Value::String(moves::chain(err)));
+        }
+    }
+    Ok(record)
+}
+
+/// Converts outcomes into records, one per axis.
+pub fn to_records<T, E>(outcomes: &[moves::Outcome<T, E>]) -> anyhow::Result<Vec<Record>>
+where
+    T: serde::Serialize,
+    E: std::error::Error + 'static,
+{
+    outcomes.iter().map(to_record).collect()
+}
+
+/// Fails with the error of the first record that describes a failure, if any.
+pub fn ensure_success(records: &[Record]) -> anyhow::Result<()> {
+    if let Some(failed) = records
+        .iter()
+        .find(|r| r.get("success") == Some(&serde_json::Value::Bool(false)))
+    {
+        let axis = failed.get("axis").cloned().unwrap_or_default();
+        let error = failed.get("error").cloned().unwrap_or_default();
+        anyhow::bail!("axis {axis} failed: {error}");
+    }
+    Ok(())
+}
+
+/// Prints the records to stdout, and fails if any of the records describes a failure.
+pub fn report(format: OutputFormat, records: &[Record]) -> anyhow::Result<()> {
+    write_records(format, io::stdout().lock(), records)?;
+
+    let failed = records
+        .iter()
+        .filter(|r| r.get("success") == Some(&serde_json::Value::Bool(false)))
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} axes failed", records.len());
+    }
+    Ok(())
+}
+
+fn write_records(
+    format: OutputFormat,
+    mut out: impl io::Write,
+    records: &[Record],
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => {
+            for record in records {
+                serde_json::to_writer(&mut out, record)?;
+                writeln!(out)?;
+            }
+        }
+        OutputFormat::Csv => {
+            let columns = columns(records);
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(&columns)?;
+            for record in records {
+                writer.write_record(columns.iter().map(|c| cell(record.get(c))))?;
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Table => {
+            let columns = columns(records);
+            let rows = records
+                .iter()
+                .map(|r| columns.iter().map(|c| cell(r.get(c))).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            let widths = columns
+                .iter()
+                .enumerate()
+                .map(|(i, c)| rows.iter().map(|r| r[i].len()).fold(c.len(), usize::max))
+                .collect::<Vec<_>>();
+
+            let header = columns.iter().map(|c| c.to_uppercase()).collect::<Vec<_>>();
+            write_row(&mut out, &widths, &header)?;
+            for row in &rows {
+                write_row(&mut out, &widths, row)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes records to stdout as they are produced, for commands that run until interrupted.
+///
+/// Since the set of records isn't known up front, the columns have to be specified when
+/// creating the stream.  Table columns grow as wider values are seen.
+pub struct RecordStream<W = io::Stdout> {
+    format: OutputFormat,
+    columns: Vec<String>,
+    widths: Vec<usize>,
+    out: W,
+}
+
+impl RecordStream {
+    pub fn new(format: OutputFormat, columns: Vec<String>) -> anyhow::Result<Self> {
+        Self::with_writer(format, columns, io::stdout())
+    }
+}
+
+impl<W: io::Write> RecordStream<W> {
+    /// Writes the records to `out` instead of stdout.
+    pub fn with_writer(format: OutputFormat, columns: Vec<String>, out: W) -> anyhow::Result<Self> {
+        let widths = columns.iter().map(String::len).collect::<Vec<_>>();
+        let mut stream = Self {
+            format,
+            columns,
+            widths,
+            out,
+        };
+        match format {
+            OutputFormat::Json => {}
+            OutputFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(&mut stream.out);
+                writer.write_record(&stream.columns)?;
+                writer.flush()?;
+            }
+            OutputFormat::Table => {
+                let header = stream
+                    .columns
+                    .iter()
+                    .map(|c| c.to_uppercase())
+                    .collect::<Vec<_>>();
+                write_row(&mut stream.out, &stream.widths, &header)?;
+            }
+        }
+        stream.out.flush()?;
+        Ok(stream)
+    }
+
+    pub fn write(&mut self, record: &Record) -> anyhow::Result<()> {
+        let cells = || self.columns.iter().map(|c| cell(record.get(c)));
+        match self.format {
+            OutputFormat::Json => {
+                serde_json::to_writer(&mut self.out, record)?;
+                writeln!(self.out)?;
+            }
+            OutputFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(&mut self.out);
+                writer.write_record(cells())?;
+                writer.flush()?;
+            }
+            OutputFormat::Table => {
+                let row = cells().collect::<Vec<_>>();
+                for (width, cell) in self.widths.iter_mut().zip(&row) {
+                    *width = (*width).max(cell.len());
+                }
+                write_row(&mut self.out, &self.widths, &row)?;
+            }
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+fn write_row(mut out: impl io::Write, widths: &[usize], cells: &[String]) -> io::Result<()> {
+    let line = cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, &width)| format!("{cell:width$}"))
+        .collect::<Vec<_>>()
+        .join("  ");
+    writeln!(out, "{}", line.trim_end())
+}
+
+/// All columns present in any of the records, in order of first appearance.
+fn columns(records: &[Record]) -> Vec<String> {
+    let mut columns = Vec::<String>::new();
+    for key in records.iter().flat_map(|r| r.keys()) {
+        if !columns.contains(key) {
+            columns.push(key.clone());
+        }
+    }
+    columns
+}
+
+fn cell(value: Option<&serde_json::Value>) -> String {
+    match value {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arctos_can_driver::{arm, Axis};
+    use std::time;
+
+    #[derive(serde::Serialize)]
+    struct Reading {
+        position: f64,
+        speed: i16,
+    }
+
+    fn outcome<T>(axis: Axis, result: Result<T, moves::Error>) -> moves::Outcome<T, moves::Error> {
+        moves::Outcome {
+            axis,
+            result,
+            elapsed: time::Duration::from_millis(2),
+        }
+    }
+
+    /// A successful reading of X and a failed one of Y, as records.
+    fn records() -> Vec<Record> {
+        let failure = moves::Error::Axis {
+            axis: Axis::Y,
+            source: arm::Error::Closed,
+        };
+        let outcomes = [
+            outcome(
+                Axis::X,
+                Ok(Reading {
+                    position: 1.5,
+                    speed: -3,
+                }),
+            ),
+            outcome(Axis::Y, Err(failure)),
+        ];
+        to_records(&outcomes).unwrap()
+    }
+
+    fn write(format: OutputFormat, records: &[Record]) -> String {
+        let mut out = Vec::new();
+        write_records(format, &mut out, records).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn records_are_flattened() {
+        let records = records();
+        assert_eq!(
+            serde_json::Value::from(records[0].clone()),
+            serde_json::json!({
+                "axis": "x",
+                "success": true,
+                "elapsed_ms": 2.0,
+                "position": 1.5,
+                "speed": -3,
+            })
+        );
+        // Errors are shown with their whole chain of causes.
+        assert_eq!(
+            serde_json::Value::from(records[1].clone()),
+            serde_json::json!({
+                "axis": "y",
+                "success": false,
+                "elapsed_ms": 2.0,
+                "error": "axis Y failed: CAN bus closed",
+            })
+        );
+        // Values that aren't structs get a column of their own, and nothing at all gets none.
+        let record = to_record(&outcome(Axis::Z, Ok(7))).unwrap();
+        assert_eq!(record["value"], 7);
+        let record = to_record(&outcome(Axis::Z, Ok(()))).unwrap();
+        assert_eq!(
+            record.keys().collect::<Vec<_>>(),
+            ["axis", "success", "elapsed_ms"]
+        );
+    }
+
+    #[test]
+    fn table() {
+        // Columns missing from a record are left empty.
+        assert_eq!(
+            write(OutputFormat::Table, &records()),
+            "\
+AXIS  SUCCESS  ELAPSED_MS  POSITION  SPEED  ERROR
+x     true     2.0         1.5       -3
+y     false    2.0                          axis Y failed: CAN bus closed
+"
+        );
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(
+            write(OutputFormat::Csv, &records()),
+            "\
+axis,success,elapsed_ms,position,speed,error
+x,true,2.0,1.5,-3,
+y,false,2.0,,,axis Y failed: CAN bus closed
+"
+        );
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            write(OutputFormat::Json, &records()),
+            r#"{"axis":"x","success":true,"elapsed_ms":2.0,"position":1.5,"speed":-3}
+{"axis":"y","success":false,"elapsed_ms":2.0,"error":"axis Y failed: CAN bus closed"}
+"#
+        );
+    }
+
+    #[test]
+    fn failures_are_reported() {
+        let error = report(OutputFormat::Json, &records()).unwrap_err();
+        assert_eq!(error.to_string(), "1 of 2 axes failed");
+        report(OutputFormat::Json, &records()[..1]).unwrap();
+    }
+
+    /// Streams the records with the columns of a reading, and returns what was written.
+    fn stream(format: OutputFormat) -> String {
+        let columns = ["axis", "position", "speed"].map(str::to_owned).to_vec();
+        let mut out = Vec::new();
+        let mut stream = RecordStream::with_writer(format, columns, &mut out).unwrap();
+        for record in records() {
+            stream.write(&record).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn streamed_table() {
+        // Only the given columns are shown, and they grow with the values.
+        assert_eq!(
+            stream(OutputFormat::Table),
+            "\
+AXIS  POSITION  SPEED
+x     1.5       -3
+y
+"
+        );
+    }
+
+    #[test]
+    fn streamed_csv() {
+        assert_eq!(
+            stream(OutputFormat::Csv),
+            "axis,position,speed\nx,1.5,-3\ny,,\n"
+        );
+    }
+
+    #[test]
+    fn streamed_json() {
+        // Records are written whole, whatever the columns.
+        assert_eq!(
+            stream(OutputFormat::Json),
+            write(OutputFormat::Json, &records())
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Capturing bus traffic in the pcapng format, so that it can be opened in Wireshark.
+//!
+//! Frames are written with the `LINKTYPE_CAN_SOCKETCAN` link type, and every packet has a comment
+//! with the decoded servo request or response.
+use std::io;
+use std::time;
+
+use socketcan::EmbeddedFrame as _;
+
+use crate::bus;
+use crate::servo_cmd::{ServoRequest, ServoResponse};
+
+const SECTION_HEADER_BLOCK: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const LINKTYPE_CAN_SOCKETCAN: u16 = 227;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_NAME: u16 = 2;
+const EPB_FLAGS: u16 = 2;
+const EPB_FLAGS_INBOUND: u32 = 0b01;
+const EPB_FLAGS_OUTBOUND: u32 = 0b10;
+
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+const CAN_RTR_FLAG: u32 = 0x4000_0000;
+
+/// A bus tap that writes every frame to a pcapng capture.
+pub struct Writer<W> {
+    out: W,
+}
+
+impl<W> Writer<W>
+where
+    W: io::Write,
+{
+    /// Starts a capture of the named CAN interface, by writing the pcapng headers.
+    pub fn new(mut out: W, ifname: &str) -> io::Result<Self> {
+        let mut section = Vec::new();
+        section.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend(1u16.to_le_bytes());
+        section.extend(0u16.to_le_bytes());
+        // The length of the section is not known up front.
+        section.extend((-1i64).to_le_bytes());
+        write_block(&mut out, SECTION_HEADER_BLOCK, &section)?;
+
+        let mut interface = Vec::new();
+        interface.extend(LINKTYPE_CAN_SOCKETCAN.to_le_bytes());
+        interface.extend(0u16.to_le_bytes());
+        // No limit on the snapshot length.
+        interface.extend(0u32.to_le_bytes());
+        push_option(&mut interface, IF_NAME, ifname.as_bytes());
+        push_option(&mut interface, OPT_END_OF_OPT, &[]);
+        write_block(&mut out, INTERFACE_DESCRIPTION_BLOCK, &interface)?;
+
+        out.flush()?;
+        Ok(Self { out })
+    }
+}
+
+impl<W> bus::Tap for Writer<W>
+where
+    W: io::Write + Send,
+{
+    fn frame(&mut self, direction: bus::Direction, frame: &socketcan::CanFrame) -> io::Result<()> {
+        let timestamp = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let packet = socketcan_packet(frame);
+        let (flags, comment) = match direction {
+            bus::Direction::Sent => (
+                EPB_FLAGS_OUTBOUND,
+                match ServoRequest::from_frame(frame.id(), frame) {
+                    Ok(request) => format!("{request:?}"),
+                    Err(err) => format!("undecodable request: {err}"),
+                },
+            ),
+            bus::Direction::Received => (
+                EPB_FLAGS_INBOUND,
+                match ServoResponse::from_frame(frame.id(), frame) {
+                    Ok(response) => format!("{response:?}"),
+                    Err(err) => format!("undecodable response: {err}"),
+                },
+            ),
+        };
+
+        let mut block = Vec::new();
+        // Interface id
+        block.extend(0u32.to_le_bytes());
+        block.extend(((timestamp >> 32) as u32).to_le_bytes());
+        block.extend((timestamp as u32).to_le_bytes());
+        // Captured and original packet length
+        block.extend((packet.len() as u32).to_le_bytes());
+        block.extend((packet.len() as u32).to_le_bytes());
+        block.extend(packet);
+        pad(&mut block);
+        push_option(&mut block, EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut block, OPT_COMMENT, comment.as_bytes());
+        push_option(&mut block, OPT_END_OF_OPT, &[]);
+        write_block(&mut self.out, ENHANCED_PACKET_BLOCK, &block)?;
+        // Flush every frame, so that nothing is lost if the process is killed.
+        self.out.flush()
+    }
+}
+
+/// Encodes a frame like the `can_frame` struct of SocketCAN, but with the id in network byte order.
+fn socketcan_packet(frame: &socketcan::CanFrame) -> Vec<u8> {
+    let mut can_id = match frame.id() {
+        socketcan::Id::Standard(id) => u32::from(id.as_raw()),
+        socketcan::Id::Extended(id) => id.as_raw() | CAN_EFF_FLAG,
+    };
+    if frame.is_remote_frame() {
+        can_id |= CAN_RTR_FLAG;
+    }
+
+    let mut data = [0; 8];
+    data[..frame.data().len()].copy_from_slice(frame.data());
+    let mut packet = Vec::with_capacity(16);
+    packet.extend(can_id.to_be_bytes());
+    packet.extend([frame.dlc() as u8, 0, 0, 0]);
+    packet.extend(data);
+    packet
+}
+
+fn write_block(out: &mut impl io::Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    // The block type and the total length come before the body, and the total length again after.
+    let total_length = (body.len() + 12) as u32;
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&total_length.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&total_length.to_le_bytes())
+}
+
+fn push_option(block: &mut Vec<u8>, code: u16, value: &[u8]) {
+    block.extend(code.to_le_bytes());
+    block.extend((value.len() as u16).to_le_bytes());
+    block.extend(value);
+    pad(block);
+}
+
+/// Pads to a multiple of 32 bits, as required for all fields of pcapng blocks.
+fn pad(block: &mut Vec<u8>) {
+    block.resize(block.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Tap as _;
+
+    /// Splits a capture into the types and bodies of its blocks, checking their lengths.
+    fn blocks(mut capture: &[u8]) -> Vec<(u32, &[u8])> {
+        let u32_at =
+            |bytes: &[u8], i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let mut blocks = Vec::new();
+        while !capture.is_empty() {
+            let total_length = u32_at(capture, 4) as usize;
+            assert_eq!(total_length % 4, 0);
+            assert_eq!(u32_at(capture, total_length - 4) as usize, total_length);
+            blocks.push((u32_at(capture, 0), &capture[8..total_length - 4]));
+            capture = &capture[total_length..];
+        }
+        blocks
+    }
+
+    #[test]
+    fn block_layout() {
+        let mut writer = Writer::new(Vec::new(), "vcan0").unwrap();
+        let id = socketcan::StandardId::new(0x001).unwrap();
+        let standard = socketcan::CanFrame::new(id, &[0xf3, 0x01, 0xf5]).unwrap();
+        writer.frame(bus::Direction::Sent, &standard).unwrap();
+        let id = socketcan::ExtendedId::new(0x1234567).unwrap();
+        let extended = socketcan::CanFrame::new(id, &[0x01, 0x02, 0x03, 0x04, 0x05]).unwrap();
+        writer.frame(bus::Direction::Received, &extended).unwrap();
+
+        let blocks = blocks(&writer.out);
+        assert_eq!(blocks.len(), 4);
+
+        let (block_type, body) = blocks[0];
+        assert_eq!(block_type, SECTION_HEADER_BLOCK);
+        assert_eq!(
+            body,
+            [0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+
+        let (block_type, body) = blocks[1];
+        assert_eq!(block_type, INTERFACE_DESCRIPTION_BLOCK);
+        assert_eq!(
+            body,
+            [
+                227, 0, 0, 0, 0, 0, 0, 0, // link type, reserved and snapshot length
+                2, 0, 5, 0, b'v', b'c', b'a', b'n', b'0', 0, 0, 0, // if_name, padded
+                0, 0, 0, 0, // opt_endofopt
+            ]
+        );
+
+        let (block_type, body) = blocks[2];
+        assert_eq!(block_type, ENHANCED_PACKET_BLOCK);
+        // Interface id, then the timestamp, then the captured and original length.
+        assert_eq!(body[..4], [0, 0, 0, 0]);
+        assert_eq!(body[12..20], [16, 0, 0, 0, 16, 0, 0, 0]);
+        assert_eq!(
+            body[20..36],
+            [0x00, 0x00, 0x00, 0x01, 3, 0, 0, 0, 0xf3, 0x01, 0xf5, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(body[36..44], [2, 0, 4, 0, 0b10, 0, 0, 0]);
+        let comment = b"Enable { enabled: true }";
+        assert_eq!(body[44..48], [1, 0, comment.len() as u8, 0]);
+        assert_eq!(body[48..48 + comment.len()], comment[..]);
+        assert_eq!(body[48 + comment.len()..], [0, 0, 0, 0]);
+
+        let (block_type, body) = blocks[3];
+        assert_eq!(block_type, ENHANCED_PACKET_BLOCK);
+        assert_eq!(
+            body[20..36],
+            [0x81, 0x23, 0x45, 0x67, 5, 0, 0, 0, 0x01, 0x02, 0x03, 0x04, 0x05, 0, 0, 0]
+        );
+        assert_eq!(body[36..44], [2, 0, 4, 0, 0b01, 0, 0, 0]);
+        // The comment is padded to 32 bits, before the end of the options.
+        let comment_length = usize::from(u16::from_le_bytes([body[46], body[47]]));
+        let padded = comment_length.next_multiple_of(4);
+        assert!(padded > comment_length);
+        assert!(body[48 + comment_length..48 + padded]
+            .iter()
+            .all(|&b| b == 0));
+        assert_eq!(body[48 + padded..], [0, 0, 0, 0]);
+    }
+}
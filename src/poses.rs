@@ -0,0 +1,103 @@
+//! Named joint positions of the arm, such as "home" or "park", kept in a state file so that they
+//! can be visited by name.
+//!
+//! The file is TOML, with the joint angles in degrees of every pose:
+//!
+//! ```toml
+//! [park]
+//! x = 0.0
+//! y = -45.0
+//! z = 90.0
+//! a = 0.0
+//! b = 45.0
+//! c = 0.0
+//! ```
+use std::collections;
+use std::path;
+
+use anyhow::Context as _;
+use arctos_can_driver::{kinematics, Axis};
+
+use crate::{output, BusOptions, Request};
+
+/// Joint angles in degrees.
+pub type Joints = collections::BTreeMap<Axis, f64>;
+
+/// Named poses, by name.
+pub type Library = collections::BTreeMap<String, Joints>;
+
+/// Where poses are kept by default: `arctos-can-driver/poses.toml` in the state directory of the
+/// user, as given by `XDG_STATE_HOME`.
+pub fn default_path() -> path::PathBuf {
+    std::env::var_os("XDG_STATE_HOME")
+        .map(path::PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| path::Path::new(&home).join(".local/state"))
+        })
+        .unwrap_or_else(std::env::temp_dir)
+        .join("arctos-can-driver")
+        .join("poses.toml")
+}
+
+/// Reads the poses of a file, or none if it doesn't exist yet.
+pub fn load(path: &path::Path) -> anyhow::Result<Library> {
+    if !path.exists() {
+        return Ok(Library::new());
+    }
+    let file = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    toml::from_str(&file).with_context(|| format!("invalid pose file {}", path.display()))
+}
+
+/// Writes the poses to a file, creating its directory if needed.
+pub fn save(path: &path::Path, library: &Library) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    std::fs::write(path, toml::to_string(library)?)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Looks up a pose by name.
+pub fn get<'a>(library: &'a Library, name: &str) -> anyhow::Result<&'a Joints> {
+    library
+        .get(name)
+        .with_context(|| format!("there is no pose named {name:?}, see `pose list`"))
+}
+
+/// Reads the joint angles of all axes.
+pub async fn capture(
+    bus: &BusOptions,
+    socket_path: &path::Path,
+    profile: kinematics::Profile,
+) -> anyhow::Result<Joints> {
+    let records = crate::dispatch(bus, socket_path, Request::GetJoints { profile }, |_| {
+        anyhow::bail!("unexpected streamed record")
+    })
+    .await?;
+    output::ensure_success(&records)?;
+    let Some(mut record) = records.into_iter().next() else {
+        anyhow::bail!("no joint angles were read");
+    };
+    record.remove("success");
+    Ok(serde_json::from_value(record.into())?)
+}
+
+/// One record per pose, with its name and joint angles, for printing.
+pub fn to_records<'a>(
+    poses: impl IntoIterator<Item = (&'a String, &'a Joints)>,
+) -> anyhow::Result<Vec<output::Record>> {
+    poses
+        .into_iter()
+        .map(|(name, joints)| {
+            let mut record = output::Record::new();
+            record.insert("name".to_owned(), name.clone().into());
+            let serde_json::Value::Object(joints) = serde_json::to_value(joints)? else {
+                unreachable!("joints serialize as maps")
+            };
+            record.extend(joints);
+            Ok(record)
+        })
+        .collect()
+}
@@ -0,0 +1,438 @@
+//! The operations that commands run on the arm, which can either be executed directly or sent to
+//! a daemon.
+use std::collections;
+
+use arctos_can_driver::arm::{self, moves};
+use arctos_can_driver::{kinematics, motion, servo_cmd, Axis};
+use tokio::time;
+
+use crate::{gcode, output};
+
+/// An operation on a set of axes, that can either be executed directly or sent to a daemon.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Request {
+    Init {
+        axes: Vec<Axis>,
+    },
+    Enable {
+        axes: Vec<Axis>,
+    },
+    Disable {
+        axes: Vec<Axis>,
+    },
+    /// Stops the axes right away, in any state.
+    Stop {
+        axes: Vec<Axis>,
+    },
+    SetOrigin {
+        axes: Vec<Axis>,
+    },
+    Read {
+        axes: Vec<Axis>,
+    },
+    Home {
+        axes: Vec<Axis>,
+    },
+    Move {
+        axes: Vec<Axis>,
+        /// The raw position in number of servo rotations from origin.
+        position: f64,
+        /// The speed of the motor in RPM, or the default speed of the axis if not set.
+        speed: Option<u16>,
+        /// The raw acceleration of the motor, or the default acceleration of the axis if not set.
+        accel_raw: Option<u8>,
+        /// Whether all axes should start and finish together.
+        #[serde(default)]
+        sync: bool,
+        /// The shape of a speed profile to generate on the host, instead of using the built-in
+        /// ramp of the motor.
+        #[serde(default)]
+        shape: Option<motion::Shape>,
+        /// The acceleration of a generated profile in RPM/s, or the rate of `accel_raw` if not set.
+        #[serde(default)]
+        accel: Option<f64>,
+        /// The jerk of a generated S-curve profile in RPM/s², or four times the acceleration if
+        /// not set.
+        #[serde(default)]
+        jerk: Option<f64>,
+    },
+    /// Samples telemetry from the axes until `count` samples have been taken, or forever.
+    Watch {
+        axes: Vec<Axis>,
+        rate: f64,
+        count: Option<u64>,
+    },
+    /// Samples the positions of all axes at a fixed rate until interrupted, streaming a record
+    /// with the time and positions for every sample.
+    SamplePositions {
+        rate: f64,
+    },
+    /// Moves all axes to the start of a path, and then along it by streaming setpoints,
+    /// streaming a record for every setpoint.
+    PlayPath {
+        path: motion::Spline,
+        /// Playback speed, relative to the timing of the path.
+        speed: f64,
+        /// Number of setpoints per second.
+        rate: f64,
+    },
+    /// Reads the positions of all axes, returning the joint angles in degrees.
+    GetJoints {
+        profile: kinematics::Profile,
+    },
+    /// Reads the positions of all axes, returning the pose of the tool.
+    GetPose {
+        profile: kinematics::Profile,
+    },
+    /// Moves all axes so that the tool reaches a pose.
+    SetPose {
+        profile: kinematics::Profile,
+        pose: kinematics::Pose,
+    },
+    /// Moves axes to raw positions in number of servo rotations from origin, so that they all
+    /// start and finish together.
+    MovePositions {
+        positions: collections::BTreeMap<Axis, f64>,
+    },
+    /// Moves axes to joint angles in degrees, so that they all start and finish together.
+    MoveJoints {
+        profile: kinematics::Profile,
+        joints: collections::BTreeMap<Axis, f64>,
+        /// Speed of the joint with the most travel in degrees per second, or the default speeds
+        /// of the axes if not set.
+        speed: Option<f64>,
+    },
+    /// Moves the tool to a pose along a straight line, streaming a record with the deviation from
+    /// the line for every setpoint.
+    MoveLinear {
+        profile: kinematics::Profile,
+        pose: kinematics::Pose,
+        /// Speed of the tool in millimeters per second.
+        speed: f64,
+        /// Number of setpoints per second.
+        rate: f64,
+        /// Largest deviation from the line in millimeters, before the move is aborted.
+        max_deviation: f64,
+    },
+    /// Sends a raw request to the axes, returning their responses.
+    Servo {
+        axes: Vec<Axis>,
+        request: servo_cmd::ServoRequest,
+    },
+    /// Moves the servo of a gripper to a position, in number of servo rotations from origin.
+    Gripper {
+        gripper: kinematics::Gripper,
+        position: f64,
+    },
+    /// Runs the blocks of a program one after the other, such as a G-code program or the replay
+    /// of taught waypoints, streaming a record for every block as it completes.
+    Program {
+        blocks: Vec<gcode::Block>,
+    },
+    /// Returns the state of every axis, and of the whole arm.
+    State,
+    /// Executes a request regardless of the states of the axes.
+    Force {
+        request: Box<Request>,
+    },
+    /// Brings up the axes in the order of the startup sequence of a profile, returning one record
+    /// per axis with how far it got.
+    Startup {
+        profile: kinematics::Profile,
+    },
+    /// Moves all axes to joint angles in degrees, and then disables them one at a time, from the
+    /// wrist to the base, returning one record per axis with its final state.
+    Shutdown {
+        profile: kinematics::Profile,
+        park: collections::BTreeMap<Axis, f64>,
+    },
+}
+
+impl Request {
+    /// Whether the request commands the arm, rather than only reading from it.  Commanding
+    /// requests of different clients of the daemon must not run at the same time, so that their
+    /// motions don't mix.
+    ///
+    /// Stopping doesn't count, since it has to get through while a motion is running.
+    pub fn commands_arm(&self) -> bool {
+        use servo_cmd::ServoRequest;
+
+        match self {
+            Request::State
+            | Request::Stop { .. }
+            | Request::Read { .. }
+            | Request::Watch { .. }
+            | Request::SamplePositions { .. }
+            | Request::GetJoints { .. }
+            | Request::GetPose { .. } => false,
+            Request::Servo { request, .. } => !matches!(
+                request,
+                ServoRequest::ReadEncoderValueCarry
+                    | ServoRequest::ReadEncoderValueAddition
+                    | ServoRequest::ReadSpeed
+                    | ServoRequest::ReadPulses
+                    | ServoRequest::ReadIOPorts
+                    | ServoRequest::ReadError
+                    | ServoRequest::ReadEnPin
+                    | ServoRequest::ReadGoBackToZeroOnPowerOnStatus
+                    | ServoRequest::ReadMotorShaftLockedRotor
+                    | ServoRequest::QueryStatus
+            ),
+            Request::Force { request } => request.commands_arm(),
+            _ => true,
+        }
+    }
+}
+
+/// Progress of a program, after a block has completed.
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct ProgramProgress {
+    line: usize,
+    code: String,
+    elapsed_ms: f64,
+}
+
+/// Executes a request using the bus, returning one record per axis.
+///
+/// Requests that stream records, such as [`Request::Watch`], pass every record to `on_record` as
+/// it is produced instead.
+pub async fn execute(
+    arm: &arm::Arm,
+    request: Request,
+    mut on_record: impl FnMut(output::Record) -> anyhow::Result<()>,
+) -> anyhow::Result<Vec<output::Record>> {
+    use anyhow::Context as _;
+
+    match request {
+        Request::State => {
+            let mut records = Vec::new();
+            for axis in Axis::ALL {
+                let mut record = output::Record::new();
+                record.insert("axis".to_owned(), serde_json::to_value(axis)?);
+                record.insert(
+                    "state".to_owned(),
+                    serde_json::to_value(arm.axis(axis).state())?,
+                );
+                records.push(record);
+            }
+            let mut record = output::Record::new();
+            record.insert("axis".to_owned(), "arm".into());
+            record.insert("state".to_owned(), serde_json::to_value(arm.state())?);
+            records.push(record);
+            Ok(records)
+        }
+        Request::Force { request } => {
+            Box::pin(execute(&arm.without_state_checks(), *request, on_record)).await
+        }
+        Request::Init { axes } => {
+            output::to_records(&moves::par_map(arm, axes, |a| async move { a.init().await }).await)
+        }
+        Request::Enable { axes } => output::to_records(
+            &moves::par_map(arm, axes, |a| async move { a.enable().await }).await,
+        ),
+        Request::Disable { axes } => output::to_records(
+            &moves::par_map(arm, axes, |a| async move { a.disable().await }).await,
+        ),
+        Request::Stop { axes } => {
+            output::to_records(&moves::par_map(arm, axes, |a| async move { a.stop().await }).await)
+        }
+        Request::SetOrigin { axes } => output::to_records(
+            &moves::par_map(arm, axes, |a| async move { a.set_origin().await }).await,
+        ),
+        Request::Read { axes } => output::to_records(
+            &moves::par_map(arm, axes, |a| async move { a.position().await }).await,
+        ),
+        Request::Home { axes } => output::to_records(
+            &moves::par_map(arm, axes, |a| async move { a.go_home().await }).await,
+        ),
+        Request::Move {
+            axes,
+            position,
+            speed,
+            accel_raw,
+            sync,
+            shape,
+            accel,
+            jerk,
+        } => {
+            let limit = |axis: Axis| motion::Ramp {
+                speed: speed.unwrap_or(axis.default_speed()),
+                accel: accel_raw.unwrap_or(axis.default_accel()),
+            };
+            if let Some(shape) = shape {
+                let limits = |axis| {
+                    let ramp = limit(axis);
+                    // A raw acceleration of 0 has an infinite rate, so cap it at the rate of the
+                    // fastest finite one.
+                    let accel = accel.unwrap_or(ramp.accel_rate()).clamp(1.0, 20_000.0);
+                    motion::Limits {
+                        speed: f64::from(ramp.speed),
+                        accel,
+                        jerk: jerk.unwrap_or(4.0 * accel).max(1.0),
+                    }
+                };
+                let outcomes = moves::move_profiled(arm, axes, position, shape, limits).await;
+                return output::to_records(&outcomes);
+            }
+            let targets = axes
+                .iter()
+                .map(|&axis| (axis, position))
+                .collect::<Vec<_>>();
+            output::to_records(&moves::move_axes(arm, &targets, limit, sync).await?)
+        }
+        Request::MovePositions { positions } => {
+            let targets = positions.into_iter().collect::<Vec<_>>();
+            output::to_records(&moves::move_axes(arm, &targets, moves::default_ramp, true).await?)
+        }
+        Request::Watch { axes, rate, count } => {
+            if !(rate.is_finite() && rate > 0.0) {
+                anyhow::bail!("sampling rate must be a positive number, got {rate}");
+            }
+            let mut interval = time::interval(time::Duration::from_secs_f64(1.0 / rate));
+            interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+
+            let mut taken = 0;
+            while count.is_none() || count > Some(taken) {
+                interval.tick().await;
+                let timestamp =
+                    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+                let outcomes =
+                    moves::par_map(arm, axes.clone(), |a| async move { a.telemetry().await }).await;
+                for record in output::to_records(&outcomes)? {
+                    let mut timestamped = output::Record::new();
+                    timestamped.insert(
+                        "timestamp".to_owned(),
+                        serde_json::to_value(timestamp.as_secs_f64())?,
+                    );
+                    timestamped.extend(record);
+                    on_record(timestamped)?;
+                }
+                taken += 1;
+            }
+            Ok(Vec::new())
+        }
+        Request::SamplePositions { rate } => {
+            moves::sample_positions(arm, rate, |sample| on_record(to_record(sample)?)).await?;
+            Ok(Vec::new())
+        }
+        Request::PlayPath { path, speed, rate } => {
+            moves::play_path(arm, &path, speed, rate, |progress| {
+                on_record(to_record(progress)?)
+            })
+            .await?;
+            Ok(Vec::new())
+        }
+        Request::GetJoints { profile } => {
+            let joints = moves::read_joints(arm, &profile).await?;
+            let joints = Axis::ALL
+                .into_iter()
+                .zip(joints)
+                .collect::<collections::BTreeMap<_, _>>();
+            let mut record = output::Record::new();
+            record.insert("success".to_owned(), true.into());
+            record.extend(to_record(joints)?);
+            Ok(vec![record])
+        }
+        Request::GetPose { profile } => {
+            let joints = moves::read_joints(arm, &profile).await?;
+            let mut record = output::Record::new();
+            record.insert("success".to_owned(), true.into());
+            record.extend(to_record(profile.forward(&joints)?)?);
+            Ok(vec![record])
+        }
+        Request::SetPose { profile, pose } => {
+            let current = moves::read_joints(arm, &profile).await?;
+            let joints = profile.inverse(&pose, &current)?;
+            let joints = Axis::ALL.into_iter().zip(joints).collect::<Vec<_>>();
+            let outcomes = moves::move_joints(arm, &profile, &joints, moves::default_ramp).await?;
+            output::to_records(&outcomes)
+        }
+        Request::MoveJoints {
+            profile,
+            joints,
+            speed,
+        } => {
+            let ramps = joints
+                .keys()
+                .map(|&axis| Ok((axis, moves::joint_ramp(&profile, axis, speed)?)))
+                .collect::<Result<collections::BTreeMap<_, _>, kinematics::Error>>()?;
+            let joints = joints.into_iter().collect::<Vec<_>>();
+            let outcomes = moves::move_joints(arm, &profile, &joints, |axis| ramps[&axis]).await?;
+            output::to_records(&outcomes)
+        }
+        Request::MoveLinear {
+            profile,
+            pose,
+            speed,
+            rate,
+            max_deviation,
+        } => {
+            moves::move_linear(
+                arm,
+                &profile,
+                &pose,
+                speed,
+                rate,
+                max_deviation,
+                |progress| on_record(to_record(progress)?),
+            )
+            .await?;
+            Ok(Vec::new())
+        }
+        Request::Servo { axes, request } => output::to_records(
+            &moves::par_map(arm, axes, |a| async move { a.request(request).await }).await,
+        ),
+        Request::Gripper { gripper, position } => {
+            moves::move_gripper(arm, &gripper, position).await?;
+            let mut record = output::Record::new();
+            record.insert("success".to_owned(), true.into());
+            record.insert("position".to_owned(), position.into());
+            Ok(vec![record])
+        }
+        Request::Startup { profile } => moves::startup(arm, &profile)
+            .await?
+            .into_iter()
+            .map(to_record)
+            .collect(),
+        Request::Shutdown { profile, park } => moves::shutdown(arm, &profile, &park)
+            .await?
+            .into_iter()
+            .map(to_record)
+            .collect(),
+        Request::Program { blocks } => {
+            for block in blocks {
+                let start = time::Instant::now();
+                let line = block.line;
+                match block.action {
+                    gcode::Action::Dwell(seconds) => {
+                        time::sleep(time::Duration::from_secs_f64(seconds)).await;
+                    }
+                    gcode::Action::Request(request) => {
+                        // Records streamed by the request don't fit the columns of the program.
+                        let discard: &mut (dyn FnMut(output::Record) -> anyhow::Result<()> + Send) =
+                            &mut |_| Ok(());
+                        let records = Box::pin(execute(arm, request, discard))
+                            .await
+                            .with_context(|| format!("line {line}"))?;
+                        output::ensure_success(&records).with_context(|| format!("line {line}"))?;
+                    }
+                }
+                on_record(to_record(ProgramProgress {
+                    line,
+                    code: block.code,
+                    elapsed_ms: start.elapsed().as_secs_f64() * 1000.0,
+                })?)?;
+            }
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// A record with the fields of a struct as columns.
+fn to_record(value: impl serde::Serialize) -> anyhow::Result<output::Record> {
+    let serde_json::Value::Object(record) = serde_json::to_value(value)? else {
+        anyhow::bail!("records are made from structs")
+    };
+    Ok(record)
+}
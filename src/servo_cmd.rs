@@ -1,4 +1,76 @@
+use core::fmt;
+
+/// A frame that could not be encoded or decoded.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ProtocolError {
+    /// The frame has no data at all, not even a CRC byte.
+    EmptyFrame,
+    /// The frame has a CRC byte but no opcode.
+    MissingOpcode,
+    /// The CRC byte of the frame doesn't match its id and data.
+    CrcMismatch { expected: u8, actual: u8 },
+    /// The opcode isn't one that the servos know about.
+    UnknownOpcode { opcode: u8 },
+    /// The frame data (without opcode and CRC) is too short for the opcode.
+    TooShort { opcode: ServoOpcode, len: usize },
+    /// A field of a received frame has a value that is invalid for that field.
+    InvalidValue {
+        opcode: ServoOpcode,
+        field: &'static str,
+        value: u8,
+    },
+    /// A request parameter doesn't fit in its field.
+    OutOfRange {
+        opcode: ServoOpcode,
+        field: &'static str,
+        value: i64,
+        min: i64,
+        max: i64,
+    },
+    /// The frame type didn't accept the id or data.
+    MalformedFrame,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ProtocolError::EmptyFrame => write!(f, "empty frame"),
+            ProtocolError::MissingOpcode => write!(f, "frame has no opcode"),
+            ProtocolError::CrcMismatch { expected, actual } => {
+                write!(f, "CRC mismatch; expected {expected} but got {actual}")
+            }
+            ProtocolError::UnknownOpcode { opcode } => {
+                write!(f, "unrecognized opcode: {opcode:#x}")
+            }
+            ProtocolError::TooShort { opcode, len } => {
+                write!(f, "data too short; opcode={opcode:?}, len={len}")
+            }
+            ProtocolError::InvalidValue {
+                opcode,
+                field,
+                value,
+            } => write!(f, "invalid value for {field} of {opcode:?}: {value}"),
+            ProtocolError::OutOfRange {
+                opcode,
+                field,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "{field} of {opcode:?} must be in the range {min}..={max}, got {value}"
+            ),
+            ProtocolError::MalformedFrame => write!(f, "malformed frame"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ProtocolError {}
+
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, num_derive::FromPrimitive)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum WorkMode {
     CrOpen = 0,
@@ -10,6 +82,8 @@ pub enum WorkMode {
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, num_derive::FromPrimitive)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum EnPinActiveMode {
     Low = 0,
@@ -18,6 +92,8 @@ pub enum EnPinActiveMode {
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, num_derive::FromPrimitive)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum CanBitRate {
     B125K = 0,
@@ -27,6 +103,8 @@ pub enum CanBitRate {
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, num_derive::FromPrimitive)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum ZeroMode {
     Disable = 0,
@@ -35,6 +113,8 @@ pub enum ZeroMode {
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, num_derive::FromPrimitive)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum ZeroModeSpeed {
     Speed0 = 0,
@@ -44,6 +124,8 @@ pub enum ZeroModeSpeed {
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, num_derive::FromPrimitive)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum HomeTrig {
     Low = 0,
@@ -51,6 +133,8 @@ pub enum HomeTrig {
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, num_derive::FromPrimitive)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum Direction {
     CW = 0,
@@ -58,6 +142,8 @@ pub enum Direction {
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, num_derive::FromPrimitive)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum SaveState {
     Save = 0xc8,
@@ -65,6 +151,7 @@ pub enum SaveState {
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, num_derive::FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum ProgressStatus {
     Busy = 0,
@@ -73,6 +160,7 @@ pub enum ProgressStatus {
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, num_derive::FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum MotionStatus {
     Fail = 0,
@@ -82,15 +170,18 @@ pub enum MotionStatus {
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, num_derive::FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
 pub enum MotorStatus {
-    MotorStopped,
-    MotorSpeedingUp,
-    MotorSpeedingDown,
-    MotorFullSpeed,
-    MotorHoming,
+    MotorStopped = 1,
+    MotorSpeedingUp = 2,
+    MotorSpeedingDown = 3,
+    MotorFullSpeed = 4,
+    MotorHoming = 5,
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, num_derive::FromPrimitive)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
 #[repr(u8)]
 pub enum ServoOpcode {
     ReadEncoderValueCarry = 0x30,
@@ -133,6 +224,9 @@ pub enum ServoOpcode {
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "cli", derive(clap::Subcommand))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "opcode"))]
 pub enum ServoRequest {
     ReadEncoderValueCarry,
     ReadEncoderValueAddition,
@@ -146,91 +240,129 @@ pub enum ServoRequest {
     ReadMotorShaftLockedRotor,
     Calibrate,
     SetWorkMode {
+        #[cfg_attr(feature = "cli", arg(long, value_enum))]
         work_mode: WorkMode,
     },
     SetCurrent {
         /// Current in mA
+        #[cfg_attr(feature = "cli", arg(long))]
         current: u16,
     },
     SetSubdivision {
+        #[cfg_attr(feature = "cli", arg(long))]
         microsteps: u8,
     },
     SetEnPinActiveMode {
+        #[cfg_attr(feature = "cli", arg(long, value_enum))]
         active: EnPinActiveMode,
     },
     SetDir {
+        #[cfg_attr(feature = "cli", arg(long, value_enum))]
         dir: Direction,
     },
     SetAutoSSD {
+        #[cfg_attr(feature = "cli", arg(long, action = clap::ArgAction::Set))]
         enable: bool,
     },
     SetMotorShaftLockedRotor {
+        #[cfg_attr(feature = "cli", arg(long, action = clap::ArgAction::Set))]
         enable: bool,
     },
     SetSubdivisionInterpolation {
+        #[cfg_attr(feature = "cli", arg(long, action = clap::ArgAction::Set))]
         enable: bool,
     },
     SetCanBitRate {
+        #[cfg_attr(feature = "cli", arg(long, value_enum))]
         bit_rate: CanBitRate,
     },
     SetCanId {
+        #[cfg_attr(feature = "cli", arg(long))]
         id: u16,
     },
     SetCanEnableResponses {
+        #[cfg_attr(feature = "cli", arg(long, action = clap::ArgAction::Set))]
         enable: bool,
     },
     SetKeyLocked {
+        #[cfg_attr(feature = "cli", arg(long, action = clap::ArgAction::Set))]
         enable: bool,
     },
     SetGroupId {
+        #[cfg_attr(feature = "cli", arg(long))]
         id: u16,
     },
     SetHome {
+        #[cfg_attr(feature = "cli", arg(long, value_enum))]
         home_trig: HomeTrig,
+        #[cfg_attr(feature = "cli", arg(long, value_enum))]
         home_dir: Direction,
+        #[cfg_attr(feature = "cli", arg(long))]
         home_speed: u16,
+        #[cfg_attr(feature = "cli", arg(long, action = clap::ArgAction::Set))]
         end_limit: bool,
     },
     GoHome,
     SetAxisZero,
     SetZeroOnPoweronMode {
+        #[cfg_attr(feature = "cli", arg(long, value_enum))]
         zero_mode: ZeroMode,
+        #[cfg_attr(feature = "cli", arg(long, action = clap::ArgAction::Set))]
         enable: bool,
+        #[cfg_attr(feature = "cli", arg(long, value_enum))]
         speed: ZeroModeSpeed,
+        #[cfg_attr(feature = "cli", arg(long, value_enum))]
         dir: Direction,
     },
     RestoreDefaults,
     QueryStatus,
     Enable {
+        #[cfg_attr(feature = "cli", arg(long, action = clap::ArgAction::Set))]
         enabled: bool,
     },
     RunSpeedMode {
+        #[cfg_attr(feature = "cli", arg(long, value_enum))]
         dir: Direction,
+        #[cfg_attr(feature = "cli", arg(long))]
         speed: u16,
+        #[cfg_attr(feature = "cli", arg(long))]
         acc: u8,
     },
     SaveRunModeParams {
+        #[cfg_attr(feature = "cli", arg(long, value_enum))]
         save_state: SaveState,
     },
     RunPositionRelativePulsesMode {
+        #[cfg_attr(feature = "cli", arg(long, value_enum))]
         dir: Direction,
+        #[cfg_attr(feature = "cli", arg(long))]
         speed: u16,
+        #[cfg_attr(feature = "cli", arg(long))]
         acc: u8,
+        #[cfg_attr(feature = "cli", arg(long))]
         pulses: u16,
     },
     RunPositionRelativeMotionMode {
+        #[cfg_attr(feature = "cli", arg(long))]
         speed: u16,
+        #[cfg_attr(feature = "cli", arg(long))]
         acc: u8,
+        #[cfg_attr(feature = "cli", arg(long, allow_negative_numbers = true))]
         rel_axis: i32,
     },
     RunPositionAbsoluteMotionMode {
+        #[cfg_attr(feature = "cli", arg(long))]
         speed: u16,
+        #[cfg_attr(feature = "cli", arg(long))]
         accel: u8,
+        #[cfg_attr(feature = "cli", arg(long, allow_negative_numbers = true))]
         abs_axis: i32,
     },
 }
 
 #[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "opcode"))]
 pub enum ServoResponse {
     ReadEncoderValueCarry {
         /// Number of turns the encoder has completed (CCW positive, CW negative).
@@ -358,59 +490,112 @@ pub enum ServoResponse {
 }
 
 impl ServoRequest {
-    pub fn to_frame(&self, id: socketcan::Id) -> anyhow::Result<socketcan::CanFrame> {
-        // Ensure there's always a zero byte for CRC at the end of every slice passed to Self::add_crc
+    /// The opcode identifying this request on the wire.
+    pub fn opcode(&self) -> ServoOpcode {
+        match *self {
+            ServoRequest::ReadEncoderValueCarry => ServoOpcode::ReadEncoderValueCarry,
+            ServoRequest::ReadEncoderValueAddition => ServoOpcode::ReadEncoderValueAddition,
+            ServoRequest::ReadSpeed => ServoOpcode::ReadSpeed,
+            ServoRequest::ReadPulses => ServoOpcode::ReadPulses,
+            ServoRequest::ReadIOPorts => ServoOpcode::ReadIOPorts,
+            ServoRequest::ReadError => ServoOpcode::ReadError,
+            ServoRequest::ReadEnPin => ServoOpcode::ReadEnPin,
+            ServoRequest::ReadGoBackToZeroOnPowerOnStatus => {
+                ServoOpcode::ReadGoBackToZeroOnPowerOnStatus
+            }
+            ServoRequest::ReleaseMotorShaft => ServoOpcode::ReleaseMotorShaft,
+            ServoRequest::ReadMotorShaftLockedRotor => ServoOpcode::ReadMotorShaftLockedRotor,
+            ServoRequest::Calibrate => ServoOpcode::Calibrate,
+            ServoRequest::SetWorkMode { .. } => ServoOpcode::SetWorkMode,
+            ServoRequest::SetCurrent { .. } => ServoOpcode::SetCurrent,
+            ServoRequest::SetSubdivision { .. } => ServoOpcode::SetSubdivision,
+            ServoRequest::SetEnPinActiveMode { .. } => ServoOpcode::SetEnPinActiveMode,
+            ServoRequest::SetDir { .. } => ServoOpcode::SetDir,
+            ServoRequest::SetAutoSSD { .. } => ServoOpcode::SetAutoSSD,
+            ServoRequest::SetMotorShaftLockedRotor { .. } => ServoOpcode::SetMotorShaftLockedRotor,
+            ServoRequest::SetSubdivisionInterpolation { .. } => {
+                ServoOpcode::SetSubdivisionInterpolation
+            }
+            ServoRequest::SetCanBitRate { .. } => ServoOpcode::SetCanBitRate,
+            ServoRequest::SetCanId { .. } => ServoOpcode::SetCanId,
+            ServoRequest::SetCanEnableResponses { .. } => ServoOpcode::SetCanEnableResponses,
+            ServoRequest::SetKeyLocked { .. } => ServoOpcode::SetKeyLocked,
+            ServoRequest::SetGroupId { .. } => ServoOpcode::SetGroupId,
+            ServoRequest::SetHome { .. } => ServoOpcode::SetHome,
+            ServoRequest::GoHome => ServoOpcode::GoHome,
+            ServoRequest::SetAxisZero => ServoOpcode::SetAxisZero,
+            ServoRequest::SetZeroOnPoweronMode { .. } => ServoOpcode::SetZeroOnPowerOnMode,
+            ServoRequest::RestoreDefaults => ServoOpcode::RestoreDefaults,
+            ServoRequest::QueryStatus => ServoOpcode::QueryStatus,
+            ServoRequest::Enable { .. } => ServoOpcode::Enable,
+            ServoRequest::RunSpeedMode { .. } => ServoOpcode::RunSpeedMode,
+            ServoRequest::SaveRunModeParams { .. } => ServoOpcode::SaveRunModeParams,
+            ServoRequest::RunPositionRelativePulsesMode { .. } => {
+                ServoOpcode::RunPositionRelativePulsesMode
+            }
+            ServoRequest::RunPositionRelativeMotionMode { .. } => {
+                ServoOpcode::RunPositionRelativeMotionMode
+            }
+            ServoRequest::RunPositionAbsoluteMotionMode { .. } => {
+                ServoOpcode::RunPositionAbsoluteMotionMode
+            }
+        }
+    }
+
+    pub fn to_frame<F: embedded_can::Frame>(
+        &self,
+        id: embedded_can::Id,
+    ) -> Result<F, ProtocolError> {
+        // Ensure there's always a zero byte for CRC at the end of every slice passed to add_crc
         match *self {
             ServoRequest::ReadEncoderValueCarry => {
-                Self::add_crc(id, &mut [ServoOpcode::ReadEncoderValueCarry as u8, 0])
+                add_crc(id, &mut [ServoOpcode::ReadEncoderValueCarry as u8, 0])
             }
             ServoRequest::ReadEncoderValueAddition => {
-                Self::add_crc(id, &mut [ServoOpcode::ReadEncoderValueAddition as u8, 0])
-            }
-            ServoRequest::ReadSpeed => Self::add_crc(id, &mut [ServoOpcode::ReadSpeed as u8, 0]),
-            ServoRequest::ReadPulses => Self::add_crc(id, &mut [ServoOpcode::ReadPulses as u8, 0]),
-            ServoRequest::ReadIOPorts => {
-                Self::add_crc(id, &mut [ServoOpcode::ReadIOPorts as u8, 0])
+                add_crc(id, &mut [ServoOpcode::ReadEncoderValueAddition as u8, 0])
             }
-            ServoRequest::ReadError => Self::add_crc(id, &mut [ServoOpcode::ReadError as u8, 0]),
-            ServoRequest::ReadEnPin => Self::add_crc(id, &mut [ServoOpcode::ReadEnPin as u8, 0]),
-            ServoRequest::ReadGoBackToZeroOnPowerOnStatus => Self::add_crc(
+            ServoRequest::ReadSpeed => add_crc(id, &mut [ServoOpcode::ReadSpeed as u8, 0]),
+            ServoRequest::ReadPulses => add_crc(id, &mut [ServoOpcode::ReadPulses as u8, 0]),
+            ServoRequest::ReadIOPorts => add_crc(id, &mut [ServoOpcode::ReadIOPorts as u8, 0]),
+            ServoRequest::ReadError => add_crc(id, &mut [ServoOpcode::ReadError as u8, 0]),
+            ServoRequest::ReadEnPin => add_crc(id, &mut [ServoOpcode::ReadEnPin as u8, 0]),
+            ServoRequest::ReadGoBackToZeroOnPowerOnStatus => add_crc(
                 id,
                 &mut [ServoOpcode::ReadGoBackToZeroOnPowerOnStatus as u8, 0],
             ),
             ServoRequest::ReleaseMotorShaft => {
-                Self::add_crc(id, &mut [ServoOpcode::ReleaseMotorShaft as u8, 0])
+                add_crc(id, &mut [ServoOpcode::ReleaseMotorShaft as u8, 0])
             }
             ServoRequest::ReadMotorShaftLockedRotor => {
-                Self::add_crc(id, &mut [ServoOpcode::ReadMotorShaftLockedRotor as u8, 0])
+                add_crc(id, &mut [ServoOpcode::ReadMotorShaftLockedRotor as u8, 0])
             }
-            ServoRequest::Calibrate => Self::add_crc(id, &mut [ServoOpcode::Calibrate as u8, 0]),
-            ServoRequest::SetWorkMode { work_mode } => Self::add_crc(
+            ServoRequest::Calibrate => add_crc(id, &mut [ServoOpcode::Calibrate as u8, 0]),
+            ServoRequest::SetWorkMode { work_mode } => add_crc(
                 id,
                 &mut [ServoOpcode::SetWorkMode as u8, work_mode as u8, 0],
             ),
             ServoRequest::SetCurrent { current } => {
                 let [b0, b1] = current.to_be_bytes();
-                Self::add_crc(id, &mut [ServoOpcode::SetCurrent as u8, b0, b1, 0])
+                add_crc(id, &mut [ServoOpcode::SetCurrent as u8, b0, b1, 0])
             }
             ServoRequest::SetSubdivision { microsteps } => {
-                Self::add_crc(id, &mut [ServoOpcode::SetSubdivision as u8, microsteps, 0])
+                add_crc(id, &mut [ServoOpcode::SetSubdivision as u8, microsteps, 0])
             }
-            ServoRequest::SetEnPinActiveMode { active } => Self::add_crc(
+            ServoRequest::SetEnPinActiveMode { active } => add_crc(
                 id,
                 &mut [ServoOpcode::SetEnPinActiveMode as u8, active as u8, 0],
             ),
             ServoRequest::SetDir { dir } => {
-                Self::add_crc(id, &mut [ServoOpcode::SetDir as u8, dir as u8, 0])
+                add_crc(id, &mut [ServoOpcode::SetDir as u8, dir as u8, 0])
             }
             ServoRequest::SetAutoSSD { enable } => {
-                Self::add_crc(id, &mut [ServoOpcode::SetAutoSSD as u8, enable as u8, 0])
+                add_crc(id, &mut [ServoOpcode::SetAutoSSD as u8, enable as u8, 0])
             }
-            ServoRequest::SetMotorShaftLockedRotor { enable } => Self::add_crc(
+            ServoRequest::SetMotorShaftLockedRotor { enable } => add_crc(
                 id,
                 &mut [ServoOpcode::SetMotorShaftLockedRotor as u8, enable as u8, 0],
             ),
-            ServoRequest::SetSubdivisionInterpolation { enable } => Self::add_crc(
+            ServoRequest::SetSubdivisionInterpolation { enable } => add_crc(
                 id,
                 &mut [
                     ServoOpcode::SetSubdivisionInterpolation as u8,
@@ -418,26 +603,26 @@ impl ServoRequest {
                     0,
                 ],
             ),
-            ServoRequest::SetCanBitRate { bit_rate } => Self::add_crc(
+            ServoRequest::SetCanBitRate { bit_rate } => add_crc(
                 id,
                 &mut [ServoOpcode::SetCanBitRate as u8, bit_rate as u8, 0],
             ),
             ServoRequest::SetCanId { id: can_id } => {
-                assert!(can_id <= 0x7ff);
+                check_range(ServoOpcode::SetCanId, "id", can_id.into(), 0, 0x7ff)?;
                 let [b0, b1] = can_id.to_be_bytes();
-                Self::add_crc(id, &mut [ServoOpcode::SetCanId as u8, b0, b1, 0])
+                add_crc(id, &mut [ServoOpcode::SetCanId as u8, b0, b1, 0])
             }
-            ServoRequest::SetCanEnableResponses { enable } => Self::add_crc(
+            ServoRequest::SetCanEnableResponses { enable } => add_crc(
                 id,
                 &mut [ServoOpcode::SetCanEnableResponses as u8, enable as u8, 0],
             ),
             ServoRequest::SetKeyLocked { enable } => {
-                Self::add_crc(id, &mut [ServoOpcode::SetKeyLocked as u8, enable as u8, 0])
+                add_crc(id, &mut [ServoOpcode::SetKeyLocked as u8, enable as u8, 0])
             }
             ServoRequest::SetGroupId { id: group_id } => {
-                assert!(group_id <= 0x7ff);
+                check_range(ServoOpcode::SetGroupId, "id", group_id.into(), 0, 0x7ff)?;
                 let [b0, b1] = group_id.to_be_bytes();
-                Self::add_crc(id, &mut [ServoOpcode::SetGroupId as u8, b0, b1, 0])
+                add_crc(id, &mut [ServoOpcode::SetGroupId as u8, b0, b1, 0])
             }
             ServoRequest::SetHome {
                 home_trig,
@@ -445,9 +630,15 @@ impl ServoRequest {
                 home_speed,
                 end_limit,
             } => {
-                assert!(home_speed <= 3000);
+                check_range(
+                    ServoOpcode::SetHome,
+                    "home_speed",
+                    home_speed.into(),
+                    0,
+                    MAX_SPEED,
+                )?;
                 let [b0, b1] = home_speed.to_be_bytes();
-                Self::add_crc(
+                add_crc(
                     id,
                     &mut [
                         ServoOpcode::SetHome as u8,
@@ -460,16 +651,14 @@ impl ServoRequest {
                     ],
                 )
             }
-            ServoRequest::GoHome => Self::add_crc(id, &mut [ServoOpcode::GoHome as u8, 0]),
-            ServoRequest::SetAxisZero => {
-                Self::add_crc(id, &mut [ServoOpcode::SetAxisZero as u8, 0])
-            }
+            ServoRequest::GoHome => add_crc(id, &mut [ServoOpcode::GoHome as u8, 0]),
+            ServoRequest::SetAxisZero => add_crc(id, &mut [ServoOpcode::SetAxisZero as u8, 0]),
             ServoRequest::SetZeroOnPoweronMode {
                 zero_mode,
                 enable,
                 speed,
                 dir,
-            } => Self::add_crc(
+            } => add_crc(
                 id,
                 &mut [
                     ServoOpcode::SetZeroOnPowerOnMode as u8,
@@ -481,25 +670,24 @@ impl ServoRequest {
                 ],
             ),
             ServoRequest::RestoreDefaults => {
-                Self::add_crc(id, &mut [ServoOpcode::RestoreDefaults as u8, 0])
-            }
-            ServoRequest::QueryStatus => {
-                Self::add_crc(id, &mut [ServoOpcode::QueryStatus as u8, 0])
+                add_crc(id, &mut [ServoOpcode::RestoreDefaults as u8, 0])
             }
+            ServoRequest::QueryStatus => add_crc(id, &mut [ServoOpcode::QueryStatus as u8, 0]),
             ServoRequest::Enable { enabled } => {
-                Self::add_crc(id, &mut [ServoOpcode::Enable as u8, enabled as u8, 0])
+                add_crc(id, &mut [ServoOpcode::Enable as u8, enabled as u8, 0])
             }
-            ServoRequest::RunSpeedMode { dir, speed, acc } => Self::add_crc(
-                id,
-                &mut [
-                    ServoOpcode::RunSpeedMode as u8,
-                    ((dir as u8) << 7) | ((speed >> 8) as u8),
-                    speed as u8,
-                    acc,
+            ServoRequest::RunSpeedMode { dir, speed, acc } => {
+                check_range(
+                    ServoOpcode::RunSpeedMode,
+                    "speed",
+                    speed.into(),
                     0,
-                ],
-            ),
-            ServoRequest::SaveRunModeParams { save_state } => Self::add_crc(
+                    MAX_SPEED,
+                )?;
+                let [s0, s1] = dir_speed_bytes(dir, speed);
+                add_crc(id, &mut [ServoOpcode::RunSpeedMode as u8, s0, s1, acc, 0])
+            }
+            ServoRequest::SaveRunModeParams { save_state } => add_crc(
                 id,
                 &mut [ServoOpcode::SaveRunModeParams as u8, save_state as u8, 0],
             ),
@@ -509,13 +697,21 @@ impl ServoRequest {
                 acc,
                 pulses,
             } => {
+                check_range(
+                    ServoOpcode::RunPositionRelativePulsesMode,
+                    "speed",
+                    speed.into(),
+                    0,
+                    MAX_SPEED,
+                )?;
+                let [s0, s1] = dir_speed_bytes(dir, speed);
                 let [b0, b1] = pulses.to_be_bytes();
-                Self::add_crc(
+                add_crc(
                     id,
                     &mut [
                         ServoOpcode::RunPositionRelativePulsesMode as u8,
-                        ((dir as u8) << 7) | ((speed >> 8) as u8),
-                        speed as u8,
+                        s0,
+                        s1,
                         acc,
                         b0,
                         b1,
@@ -528,11 +724,23 @@ impl ServoRequest {
                 acc,
                 rel_axis,
             } => {
-                assert!(rel_axis <= (2i32.pow(23) - 1));
-                assert!(rel_axis >= -(2i32.pow(23)));
+                check_range(
+                    ServoOpcode::RunPositionRelativeMotionMode,
+                    "speed",
+                    speed.into(),
+                    0,
+                    MAX_SPEED,
+                )?;
+                check_range(
+                    ServoOpcode::RunPositionRelativeMotionMode,
+                    "rel_axis",
+                    rel_axis.into(),
+                    I24_MIN,
+                    I24_MAX,
+                )?;
                 let [s0, s1] = speed.to_be_bytes();
                 let [_, b1, b2, b3] = rel_axis.to_be_bytes();
-                Self::add_crc(
+                add_crc(
                     id,
                     &mut [
                         ServoOpcode::RunPositionRelativeMotionMode as u8,
@@ -551,11 +759,23 @@ impl ServoRequest {
                 accel: acc,
                 abs_axis,
             } => {
-                assert!(abs_axis <= (2i32.pow(23) - 1));
-                assert!(abs_axis >= -(2i32.pow(23)));
+                check_range(
+                    ServoOpcode::RunPositionAbsoluteMotionMode,
+                    "speed",
+                    speed.into(),
+                    0,
+                    MAX_SPEED,
+                )?;
+                check_range(
+                    ServoOpcode::RunPositionAbsoluteMotionMode,
+                    "abs_axis",
+                    abs_axis.into(),
+                    I24_MIN,
+                    I24_MAX,
+                )?;
                 let [s0, s1] = speed.to_be_bytes();
                 let [_, b1, b2, b3] = abs_axis.to_be_bytes();
-                Self::add_crc(
+                add_crc(
                     id,
                     &mut [
                         ServoOpcode::RunPositionAbsoluteMotionMode as u8,
@@ -572,36 +792,282 @@ impl ServoRequest {
         }
     }
 
-    fn add_crc(id: socketcan::Id, data: &mut [u8]) -> anyhow::Result<socketcan::CanFrame> {
-        use socketcan::EmbeddedFrame as _;
+    /// Decodes a request sent by the host, for example when simulating or sniffing the bus.
+    pub fn from_frame<F: embedded_can::Frame>(
+        id: embedded_can::Id,
+        frame: &F,
+    ) -> Result<Self, ProtocolError> {
+        use num_traits::FromPrimitive as _;
 
-        let (crc_ref, rest) = data
-            .split_last_mut()
-            .ok_or_else(|| anyhow::format_err!("empty frame"))?;
-        assert_eq!(
-            0, *crc_ref,
-            "must pass in zero CRC byte at the end of the frame data"
-        );
+        let (opcode, data) = split_opcode(id, frame.data())?;
+        let invalid = |field, value| ProtocolError::InvalidValue {
+            opcode,
+            field,
+            value,
+        };
 
-        let crc = compute_crc(id, rest);
+        match opcode {
+            ServoOpcode::ReadEncoderValueCarry => return Ok(ServoRequest::ReadEncoderValueCarry),
+            ServoOpcode::ReadEncoderValueAddition => {
+                return Ok(ServoRequest::ReadEncoderValueAddition)
+            }
+            ServoOpcode::ReadSpeed => return Ok(ServoRequest::ReadSpeed),
+            ServoOpcode::ReadPulses => return Ok(ServoRequest::ReadPulses),
+            ServoOpcode::ReadIOPorts => return Ok(ServoRequest::ReadIOPorts),
+            ServoOpcode::ReadError => return Ok(ServoRequest::ReadError),
+            ServoOpcode::ReadEnPin => return Ok(ServoRequest::ReadEnPin),
+            ServoOpcode::ReadGoBackToZeroOnPowerOnStatus => {
+                return Ok(ServoRequest::ReadGoBackToZeroOnPowerOnStatus)
+            }
+            ServoOpcode::ReleaseMotorShaft => return Ok(ServoRequest::ReleaseMotorShaft),
+            ServoOpcode::ReadMotorShaftLockedRotor => {
+                return Ok(ServoRequest::ReadMotorShaftLockedRotor)
+            }
+            ServoOpcode::Calibrate => return Ok(ServoRequest::Calibrate),
+            ServoOpcode::SetWorkMode => {
+                if let [v0, ..] = *data {
+                    let work_mode =
+                        WorkMode::from_u8(v0).ok_or_else(|| invalid("work_mode", v0))?;
+                    return Ok(ServoRequest::SetWorkMode { work_mode });
+                }
+            }
+            ServoOpcode::SetCurrent => {
+                if let [v0, v1, ..] = *data {
+                    return Ok(ServoRequest::SetCurrent {
+                        current: u16::from_be_bytes([v0, v1]),
+                    });
+                }
+            }
+            ServoOpcode::SetSubdivision => {
+                if let [v0, ..] = *data {
+                    return Ok(ServoRequest::SetSubdivision { microsteps: v0 });
+                }
+            }
+            ServoOpcode::SetEnPinActiveMode => {
+                if let [v0, ..] = *data {
+                    let active =
+                        EnPinActiveMode::from_u8(v0).ok_or_else(|| invalid("active", v0))?;
+                    return Ok(ServoRequest::SetEnPinActiveMode { active });
+                }
+            }
+            ServoOpcode::SetDir => {
+                if let [v0, ..] = *data {
+                    let dir = Direction::from_u8(v0).ok_or_else(|| invalid("dir", v0))?;
+                    return Ok(ServoRequest::SetDir { dir });
+                }
+            }
+            ServoOpcode::SetAutoSSD => {
+                if let [v0, ..] = *data {
+                    return Ok(ServoRequest::SetAutoSSD { enable: v0 != 0 });
+                }
+            }
+            ServoOpcode::SetMotorShaftLockedRotor => {
+                if let [v0, ..] = *data {
+                    return Ok(ServoRequest::SetMotorShaftLockedRotor { enable: v0 != 0 });
+                }
+            }
+            ServoOpcode::SetSubdivisionInterpolation => {
+                if let [v0, ..] = *data {
+                    return Ok(ServoRequest::SetSubdivisionInterpolation { enable: v0 != 0 });
+                }
+            }
+            ServoOpcode::SetCanBitRate => {
+                if let [v0, ..] = *data {
+                    let bit_rate =
+                        CanBitRate::from_u8(v0).ok_or_else(|| invalid("bit_rate", v0))?;
+                    return Ok(ServoRequest::SetCanBitRate { bit_rate });
+                }
+            }
+            ServoOpcode::SetCanId => {
+                if let [v0, v1, ..] = *data {
+                    let id = u16::from_be_bytes([v0, v1]);
+                    check_range(opcode, "id", id.into(), 0, 0x7ff)?;
+                    return Ok(ServoRequest::SetCanId { id });
+                }
+            }
+            ServoOpcode::SetCanEnableResponses => {
+                if let [v0, ..] = *data {
+                    return Ok(ServoRequest::SetCanEnableResponses { enable: v0 != 0 });
+                }
+            }
+            ServoOpcode::SetKeyLocked => {
+                if let [v0, ..] = *data {
+                    return Ok(ServoRequest::SetKeyLocked { enable: v0 != 0 });
+                }
+            }
+            ServoOpcode::SetGroupId => {
+                if let [v0, v1, ..] = *data {
+                    let id = u16::from_be_bytes([v0, v1]);
+                    check_range(opcode, "id", id.into(), 0, 0x7ff)?;
+                    return Ok(ServoRequest::SetGroupId { id });
+                }
+            }
+            ServoOpcode::SetHome => {
+                if let [v0, v1, v2, v3, v4, ..] = *data {
+                    let home_trig =
+                        HomeTrig::from_u8(v0).ok_or_else(|| invalid("home_trig", v0))?;
+                    let home_dir = Direction::from_u8(v1).ok_or_else(|| invalid("home_dir", v1))?;
+                    let home_speed = u16::from_be_bytes([v2, v3]);
+                    check_range(opcode, "home_speed", home_speed.into(), 0, MAX_SPEED)?;
+                    return Ok(ServoRequest::SetHome {
+                        home_trig,
+                        home_dir,
+                        home_speed,
+                        end_limit: v4 != 0,
+                    });
+                }
+            }
+            ServoOpcode::GoHome => return Ok(ServoRequest::GoHome),
+            ServoOpcode::SetAxisZero => return Ok(ServoRequest::SetAxisZero),
+            ServoOpcode::SetZeroOnPowerOnMode => {
+                if let [v0, v1, v2, v3, ..] = *data {
+                    let zero_mode =
+                        ZeroMode::from_u8(v0).ok_or_else(|| invalid("zero_mode", v0))?;
+                    let speed = ZeroModeSpeed::from_u8(v2).ok_or_else(|| invalid("speed", v2))?;
+                    let dir = Direction::from_u8(v3).ok_or_else(|| invalid("dir", v3))?;
+                    return Ok(ServoRequest::SetZeroOnPoweronMode {
+                        zero_mode,
+                        enable: v1 != 0,
+                        speed,
+                        dir,
+                    });
+                }
+            }
+            ServoOpcode::RestoreDefaults => return Ok(ServoRequest::RestoreDefaults),
+            ServoOpcode::QueryStatus => return Ok(ServoRequest::QueryStatus),
+            ServoOpcode::Enable => {
+                if let [v0, ..] = *data {
+                    return Ok(ServoRequest::Enable { enabled: v0 != 0 });
+                }
+            }
+            ServoOpcode::RunSpeedMode => {
+                if let [v0, v1, v2, ..] = *data {
+                    let (dir, speed) = split_dir_speed(v0, v1);
+                    check_range(opcode, "speed", speed.into(), 0, MAX_SPEED)?;
+                    return Ok(ServoRequest::RunSpeedMode {
+                        dir,
+                        speed,
+                        acc: v2,
+                    });
+                }
+            }
+            ServoOpcode::SaveRunModeParams => {
+                if let [v0, ..] = *data {
+                    let save_state =
+                        SaveState::from_u8(v0).ok_or_else(|| invalid("save_state", v0))?;
+                    return Ok(ServoRequest::SaveRunModeParams { save_state });
+                }
+            }
+            ServoOpcode::RunPositionRelativePulsesMode => {
+                if let [v0, v1, v2, v3, v4, ..] = *data {
+                    let (dir, speed) = split_dir_speed(v0, v1);
+                    check_range(opcode, "speed", speed.into(), 0, MAX_SPEED)?;
+                    return Ok(ServoRequest::RunPositionRelativePulsesMode {
+                        dir,
+                        speed,
+                        acc: v2,
+                        pulses: u16::from_be_bytes([v3, v4]),
+                    });
+                }
+            }
+            ServoOpcode::RunPositionRelativeMotionMode => {
+                if let [v0, v1, v2, v3, v4, v5, ..] = *data {
+                    let speed = u16::from_be_bytes([v0, v1]);
+                    check_range(opcode, "speed", speed.into(), 0, MAX_SPEED)?;
+                    return Ok(ServoRequest::RunPositionRelativeMotionMode {
+                        speed,
+                        acc: v2,
+                        rel_axis: i24_from_be_bytes([v3, v4, v5]),
+                    });
+                }
+            }
+            ServoOpcode::RunPositionAbsoluteMotionMode => {
+                if let [v0, v1, v2, v3, v4, v5, ..] = *data {
+                    let speed = u16::from_be_bytes([v0, v1]);
+                    check_range(opcode, "speed", speed.into(), 0, MAX_SPEED)?;
+                    return Ok(ServoRequest::RunPositionAbsoluteMotionMode {
+                        speed,
+                        accel: v2,
+                        abs_axis: i24_from_be_bytes([v3, v4, v5]),
+                    });
+                }
+            }
+        }
 
-        *crc_ref = crc;
-        socketcan::CanFrame::new(id, data).ok_or_else(|| anyhow::format_err!("malformed frame"))
+        Err(ProtocolError::TooShort {
+            opcode,
+            len: data.len(),
+        })
     }
 }
 
 impl ServoResponse {
-    pub fn from_frame(id: socketcan::Id, frame: socketcan::CanFrame) -> anyhow::Result<Self> {
+    /// The opcode of the request that this is a response to.
+    pub fn opcode(&self) -> ServoOpcode {
+        match *self {
+            ServoResponse::ReadEncoderValueCarry { .. } => ServoOpcode::ReadEncoderValueCarry,
+            ServoResponse::ReadEncoderValueAddition { .. } => ServoOpcode::ReadEncoderValueAddition,
+            ServoResponse::ReadSpeed { .. } => ServoOpcode::ReadSpeed,
+            ServoResponse::ReadPulses { .. } => ServoOpcode::ReadPulses,
+            ServoResponse::ReadIOPorts { .. } => ServoOpcode::ReadIOPorts,
+            ServoResponse::ReadError { .. } => ServoOpcode::ReadError,
+            ServoResponse::ReadEnPin { .. } => ServoOpcode::ReadEnPin,
+            ServoResponse::ReadGoBackToZeroOnPowerOnStatus { .. } => {
+                ServoOpcode::ReadGoBackToZeroOnPowerOnStatus
+            }
+            ServoResponse::ReleaseMotorShaft { .. } => ServoOpcode::ReleaseMotorShaft,
+            ServoResponse::ReadMotorShaftLockedRotor { .. } => {
+                ServoOpcode::ReadMotorShaftLockedRotor
+            }
+            ServoResponse::Calibrate { .. } => ServoOpcode::Calibrate,
+            ServoResponse::SetWorkMode { .. } => ServoOpcode::SetWorkMode,
+            ServoResponse::SetCurrent { .. } => ServoOpcode::SetCurrent,
+            ServoResponse::SetSubdivision { .. } => ServoOpcode::SetSubdivision,
+            ServoResponse::SetEnPinActiveMode { .. } => ServoOpcode::SetEnPinActiveMode,
+            ServoResponse::SetDir { .. } => ServoOpcode::SetDir,
+            ServoResponse::SetAutoSSD { .. } => ServoOpcode::SetAutoSSD,
+            ServoResponse::SetMotorShaftLockedRotor { .. } => ServoOpcode::SetMotorShaftLockedRotor,
+            ServoResponse::SetSubdivisionInterpolation { .. } => {
+                ServoOpcode::SetSubdivisionInterpolation
+            }
+            ServoResponse::SetCanBitRate { .. } => ServoOpcode::SetCanBitRate,
+            ServoResponse::SetCanId { .. } => ServoOpcode::SetCanId,
+            ServoResponse::SetCanEnableResponses { .. } => ServoOpcode::SetCanEnableResponses,
+            ServoResponse::SetKeyLocked { .. } => ServoOpcode::SetKeyLocked,
+            ServoResponse::SetGroupId { .. } => ServoOpcode::SetGroupId,
+            ServoResponse::SetHome { .. } => ServoOpcode::SetHome,
+            ServoResponse::GoHome { .. } => ServoOpcode::GoHome,
+            ServoResponse::SetAxisZero { .. } => ServoOpcode::SetAxisZero,
+            ServoResponse::SetZeroOnPowerOnMode { .. } => ServoOpcode::SetZeroOnPowerOnMode,
+            ServoResponse::RestoreDefaults { .. } => ServoOpcode::RestoreDefaults,
+            ServoResponse::QueryStatus { .. } => ServoOpcode::QueryStatus,
+            ServoResponse::Enable { .. } => ServoOpcode::Enable,
+            ServoResponse::RunSpeedMode { .. } => ServoOpcode::RunSpeedMode,
+            ServoResponse::SaveRunModeParams { .. } => ServoOpcode::SaveRunModeParams,
+            ServoResponse::RunPositionRelativePulsesMode { .. } => {
+                ServoOpcode::RunPositionRelativePulsesMode
+            }
+            ServoResponse::RunPositionRelativeMotionMode { .. } => {
+                ServoOpcode::RunPositionRelativeMotionMode
+            }
+            ServoResponse::RunPositionAbsoluteMotionMode { .. } => {
+                ServoOpcode::RunPositionAbsoluteMotionMode
+            }
+        }
+    }
+
+    pub fn from_frame<F: embedded_can::Frame>(
+        id: embedded_can::Id,
+        frame: &F,
+    ) -> Result<Self, ProtocolError> {
         use num_traits::FromPrimitive as _;
-        use socketcan::EmbeddedFrame as _;
 
-        let data = frame.data();
-        let data = Self::check_crc(id, data)?;
-        let (&opcode, data) = data
-            .split_first()
-            .ok_or_else(|| anyhow::format_err!("frame has no opcode"))?;
-        let opcode = ServoOpcode::from_u8(opcode)
-            .ok_or_else(|| anyhow::format_err!("unrecognized opcode: {:#x}", opcode))?;
+        let (opcode, data) = split_opcode(id, frame.data())?;
+        let invalid = |field, value| ProtocolError::InvalidValue {
+            opcode,
+            field,
+            value,
+        };
 
         match opcode {
             ServoOpcode::ReadEncoderValueCarry => {
@@ -614,7 +1080,7 @@ impl ServoResponse {
             }
             ServoOpcode::ReadEncoderValueAddition => {
                 if let [v0, v1, v2, v3, v4, v5, ..] = *data {
-                    let sign_extend = if v0 > 0x80 { 0xff } else { 0x00 };
+                    let sign_extend = if v0 >= 0x80 { 0xff } else { 0x00 };
                     return Ok(ServoResponse::ReadEncoderValueAddition {
                         value: i64::from_be_bytes([
                             sign_extend,
@@ -667,9 +1133,8 @@ impl ServoResponse {
             }
             ServoOpcode::ReadGoBackToZeroOnPowerOnStatus => {
                 if let [v0, ..] = *data {
-                    let status = ProgressStatus::from_u8(v0).ok_or_else(|| {
-                        anyhow::format_err!("invalid value for ProgressStatus: {}", v0)
-                    })?;
+                    let status =
+                        ProgressStatus::from_u8(v0).ok_or_else(|| invalid("status", v0))?;
                     return Ok(ServoResponse::ReadGoBackToZeroOnPowerOnStatus { status });
                 }
             }
@@ -685,9 +1150,8 @@ impl ServoResponse {
             }
             ServoOpcode::Calibrate => {
                 if let [v0, ..] = *data {
-                    let status = ProgressStatus::from_u8(v0).ok_or_else(|| {
-                        anyhow::format_err!("invalid value for ProgressStatus: {}", v0)
-                    })?;
+                    let status =
+                        ProgressStatus::from_u8(v0).ok_or_else(|| invalid("status", v0))?;
                     return Ok(ServoResponse::Calibrate { status });
                 }
             }
@@ -767,10 +1231,7 @@ impl ServoResponse {
                         0 => ProgressStatus::Fail,
                         1 => ProgressStatus::Busy,
                         2 => ProgressStatus::Success,
-                        n => anyhow::bail!(
-                            "invalid value for ProgressStatus (GoHome specific mapping): {}",
-                            n
-                        ),
+                        _ => return Err(invalid("progress", v0)),
                     };
                     return Ok(ServoResponse::GoHome { progress });
                 }
@@ -795,9 +1256,7 @@ impl ServoResponse {
                     let status = if v0 == 0 {
                         None
                     } else {
-                        Some(MotorStatus::from_u8(v0).ok_or_else(|| {
-                            anyhow::format_err!("invalid value for MotorStatus: {}", v0)
-                        })?)
+                        Some(MotorStatus::from_u8(v0).ok_or_else(|| invalid("status", v0))?)
                     };
                     return Ok(ServoResponse::QueryStatus { status });
                 }
@@ -809,9 +1268,7 @@ impl ServoResponse {
             }
             ServoOpcode::RunSpeedMode => {
                 if let [v0, ..] = *data {
-                    let status = MotionStatus::from_u8(v0).ok_or_else(|| {
-                        anyhow::format_err!("invalid value for MotionStatus: {}", v0)
-                    })?;
+                    let status = MotionStatus::from_u8(v0).ok_or_else(|| invalid("status", v0))?;
                     return Ok(ServoResponse::RunSpeedMode { status });
                 }
             }
@@ -822,59 +1279,255 @@ impl ServoResponse {
             }
             ServoOpcode::RunPositionRelativePulsesMode => {
                 if let [v0, ..] = *data {
-                    let status = MotionStatus::from_u8(v0).ok_or_else(|| {
-                        anyhow::format_err!("invalid value for MotionStatus: {}", v0)
-                    })?;
+                    let status = MotionStatus::from_u8(v0).ok_or_else(|| invalid("status", v0))?;
                     return Ok(ServoResponse::RunPositionRelativePulsesMode { status });
                 }
             }
             ServoOpcode::RunPositionRelativeMotionMode => {
                 if let [v0, ..] = *data {
-                    let status = MotionStatus::from_u8(v0).ok_or_else(|| {
-                        anyhow::format_err!("invalid value for MotionStatus: {}", v0)
-                    })?;
+                    let status = MotionStatus::from_u8(v0).ok_or_else(|| invalid("status", v0))?;
                     return Ok(ServoResponse::RunPositionRelativeMotionMode { status });
                 }
             }
             ServoOpcode::RunPositionAbsoluteMotionMode => {
                 if let [v0, ..] = *data {
-                    let status = MotionStatus::from_u8(v0).ok_or_else(|| {
-                        anyhow::format_err!("invalid value for MotionStatus: {}", v0)
-                    })?;
+                    let status = MotionStatus::from_u8(v0).ok_or_else(|| invalid("status", v0))?;
                     return Ok(ServoResponse::RunPositionAbsoluteMotionMode { status });
                 }
             }
         }
 
-        anyhow::bail!(
-            "response data too short; opcode={:?}, len={}",
+        Err(ProtocolError::TooShort {
             opcode,
-            data.len()
-        );
+            len: data.len(),
+        })
     }
-    fn check_crc(id: socketcan::Id, data: &[u8]) -> anyhow::Result<&[u8]> {
-        let (&crc_actual, rest) = data
-            .split_last()
-            .ok_or_else(|| anyhow::format_err!("cannot compute CRC for empty frame"))?;
-        let crc_expected = compute_crc(id, rest);
-        if crc_actual != crc_expected {
-            anyhow::bail!(
-                "CRC mismatch; expected {} but got {}",
-                crc_expected,
-                crc_actual
-            );
+
+    /// Encodes the response, as a servo would send it.
+    pub fn to_frame<F: embedded_can::Frame>(
+        &self,
+        id: embedded_can::Id,
+    ) -> Result<F, ProtocolError> {
+        // Ensure there's always a zero byte for CRC at the end of every slice passed to add_crc
+        match *self {
+            ServoResponse::ReadEncoderValueCarry { carry, value } => {
+                let [c0, c1, c2, c3] = carry.to_be_bytes();
+                let [v0, v1] = value.to_be_bytes();
+                add_crc(
+                    id,
+                    &mut [
+                        ServoOpcode::ReadEncoderValueCarry as u8,
+                        c0,
+                        c1,
+                        c2,
+                        c3,
+                        v0,
+                        v1,
+                        0,
+                    ],
+                )
+            }
+            ServoResponse::ReadEncoderValueAddition { value } => {
+                check_range(
+                    ServoOpcode::ReadEncoderValueAddition,
+                    "value",
+                    value,
+                    I48_MIN,
+                    I48_MAX,
+                )?;
+                let [_, _, v0, v1, v2, v3, v4, v5] = value.to_be_bytes();
+                add_crc(
+                    id,
+                    &mut [
+                        ServoOpcode::ReadEncoderValueAddition as u8,
+                        v0,
+                        v1,
+                        v2,
+                        v3,
+                        v4,
+                        v5,
+                        0,
+                    ],
+                )
+            }
+            ServoResponse::ReadSpeed { speed } => {
+                let [v0, v1] = speed.to_be_bytes();
+                add_crc(id, &mut [ServoOpcode::ReadSpeed as u8, v0, v1, 0])
+            }
+            ServoResponse::ReadPulses { pulses } => {
+                let [v0, v1, v2, v3] = pulses.to_be_bytes();
+                add_crc(id, &mut [ServoOpcode::ReadPulses as u8, v0, v1, v2, v3, 0])
+            }
+            ServoResponse::ReadIOPorts {
+                out_1,
+                out_2,
+                in_1,
+                in_2,
+            } => {
+                let status =
+                    in_1 as u8 | (in_2 as u8) << 1 | (out_1 as u8) << 2 | (out_2 as u8) << 3;
+                add_crc(id, &mut [ServoOpcode::ReadIOPorts as u8, status, 0])
+            }
+            ServoResponse::ReadError { error } => {
+                let [v0, v1, v2, v3] = error.to_be_bytes();
+                add_crc(id, &mut [ServoOpcode::ReadError as u8, v0, v1, v2, v3, 0])
+            }
+            ServoResponse::ReadEnPin { enabled } => {
+                add_crc(id, &mut [ServoOpcode::ReadEnPin as u8, enabled as u8, 0])
+            }
+            ServoResponse::ReadGoBackToZeroOnPowerOnStatus { status }
+            | ServoResponse::Calibrate { status } => {
+                add_crc(id, &mut [self.opcode() as u8, status as u8, 0])
+            }
+            ServoResponse::ReadMotorShaftLockedRotor { locked } => add_crc(
+                id,
+                &mut [
+                    ServoOpcode::ReadMotorShaftLockedRotor as u8,
+                    locked as u8,
+                    0,
+                ],
+            ),
+            ServoResponse::GoHome { progress } => {
+                // GoHome reports its progress with a different mapping than other commands.
+                let progress = match progress {
+                    ProgressStatus::Fail => 0,
+                    ProgressStatus::Busy => 1,
+                    ProgressStatus::Success => 2,
+                };
+                add_crc(id, &mut [ServoOpcode::GoHome as u8, progress, 0])
+            }
+            ServoResponse::QueryStatus { status } => add_crc(
+                id,
+                &mut [
+                    ServoOpcode::QueryStatus as u8,
+                    status.map_or(0, |s| s as u8),
+                    0,
+                ],
+            ),
+            ServoResponse::RunSpeedMode { status }
+            | ServoResponse::RunPositionRelativePulsesMode { status }
+            | ServoResponse::RunPositionRelativeMotionMode { status }
+            | ServoResponse::RunPositionAbsoluteMotionMode { status } => {
+                add_crc(id, &mut [self.opcode() as u8, status as u8, 0])
+            }
+            ServoResponse::ReleaseMotorShaft { success }
+            | ServoResponse::SetWorkMode { success }
+            | ServoResponse::SetCurrent { success }
+            | ServoResponse::SetSubdivision { success }
+            | ServoResponse::SetEnPinActiveMode { success }
+            | ServoResponse::SetDir { success }
+            | ServoResponse::SetAutoSSD { success }
+            | ServoResponse::SetMotorShaftLockedRotor { success }
+            | ServoResponse::SetSubdivisionInterpolation { success }
+            | ServoResponse::SetCanBitRate { success }
+            | ServoResponse::SetCanId { success }
+            | ServoResponse::SetCanEnableResponses { success }
+            | ServoResponse::SetKeyLocked { success }
+            | ServoResponse::SetGroupId { success }
+            | ServoResponse::SetHome { success }
+            | ServoResponse::SetAxisZero { success }
+            | ServoResponse::SetZeroOnPowerOnMode { success }
+            | ServoResponse::RestoreDefaults { success }
+            | ServoResponse::Enable { success }
+            | ServoResponse::SaveRunModeParams { success } => {
+                add_crc(id, &mut [self.opcode() as u8, success as u8, 0])
+            }
         }
-        Ok(rest)
     }
 }
 
-fn compute_crc(id: socketcan::Id, data: &[u8]) -> u8 {
+/// The range of the 24 bit signed integers used for axis positions.
+const I24_MIN: i64 = -(1 << 23);
+const I24_MAX: i64 = (1 << 23) - 1;
+/// The range of the 48 bit signed integers used for encoder values.
+const I48_MIN: i64 = -(1 << 47);
+const I48_MAX: i64 = (1 << 47) - 1;
+/// The maximum speed of the servos, in RPM.
+const MAX_SPEED: i64 = 3000;
+
+fn check_range(
+    opcode: ServoOpcode,
+    field: &'static str,
+    value: i64,
+    min: i64,
+    max: i64,
+) -> Result<(), ProtocolError> {
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(ProtocolError::OutOfRange {
+            opcode,
+            field,
+            value,
+            min,
+            max,
+        })
+    }
+}
+
+fn add_crc<F: embedded_can::Frame>(
+    id: embedded_can::Id,
+    data: &mut [u8],
+) -> Result<F, ProtocolError> {
+    let (crc_ref, rest) = data.split_last_mut().ok_or(ProtocolError::EmptyFrame)?;
+    // Every caller is in this module, and builds the data as a literal array that ends in a zero
+    // placeholder for the CRC, so this only catches mistakes in new encoders.
+    debug_assert_eq!(
+        0, *crc_ref,
+        "must pass in zero CRC byte at the end of the frame data"
+    );
+
+    let crc = compute_crc(id, rest);
+
+    *crc_ref = crc;
+    F::new(id, data).ok_or(ProtocolError::MalformedFrame)
+}
+
+/// Checks the CRC of the frame data, and splits the rest into the opcode and its arguments.
+fn split_opcode(id: embedded_can::Id, data: &[u8]) -> Result<(ServoOpcode, &[u8]), ProtocolError> {
+    use num_traits::FromPrimitive as _;
+
+    let (&crc_actual, rest) = data.split_last().ok_or(ProtocolError::EmptyFrame)?;
+    let crc_expected = compute_crc(id, rest);
+    if crc_actual != crc_expected {
+        return Err(ProtocolError::CrcMismatch {
+            expected: crc_expected,
+            actual: crc_actual,
+        });
+    }
+    let (&opcode, rest) = rest.split_first().ok_or(ProtocolError::MissingOpcode)?;
+    let opcode = ServoOpcode::from_u8(opcode).ok_or(ProtocolError::UnknownOpcode { opcode })?;
+    Ok((opcode, rest))
+}
+
+/// Packs a direction and a speed into two bytes, with the direction in the most significant bit.
+fn dir_speed_bytes(dir: Direction, speed: u16) -> [u8; 2] {
+    [((dir as u8) << 7) | ((speed >> 8) as u8), speed as u8]
+}
+
+fn split_dir_speed(b0: u8, b1: u8) -> (Direction, u16) {
+    let dir = if b0 & 0x80 == 0 {
+        Direction::CW
+    } else {
+        Direction::CCW
+    };
+    (dir, u16::from_be_bytes([b0 & 0x7f, b1]))
+}
+
+fn i24_from_be_bytes([b0, b1, b2]: [u8; 3]) -> i32 {
+    let sign_extend = if b0 >= 0x80 { 0xff } else { 0x00 };
+    i32::from_be_bytes([sign_extend, b0, b1, b2])
+}
+
+/// Computes the checksum byte that ends every frame to or from a servo, from the CAN id and the
+/// rest of the frame data.
+pub fn compute_crc(id: embedded_can::Id, data: &[u8]) -> u8 {
     // This is not really Cyclic Redundancy Checking, but the manual calls it CRC, so...
     match id {
-        socketcan::Id::Standard(id) => {
+        embedded_can::Id::Standard(id) => {
             compute_crc_id_bytes(id.as_raw().to_be_bytes().as_slice(), data)
         }
-        socketcan::Id::Extended(id) => {
+        embedded_can::Id::Extended(id) => {
             compute_crc_id_bytes(id.as_raw().to_be_bytes().as_slice(), data)
         }
     }
@@ -891,3 +1544,594 @@ fn compute_crc_id_bytes(id_bytes: &[u8], data: &[u8]) -> u8 {
         // Cast to u8 to finally truncate result
         .sum::<u16>() as u8
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_can::Frame as _;
+
+    /// A classic CAN frame, so that the codec can be tested without a CAN interface.
+    #[derive(Debug)]
+    struct Frame {
+        id: embedded_can::Id,
+        data: [u8; 8],
+        len: usize,
+    }
+
+    impl embedded_can::Frame for Frame {
+        fn new(id: impl Into<embedded_can::Id>, data: &[u8]) -> Option<Self> {
+            let mut frame = Frame {
+                id: id.into(),
+                data: [0; 8],
+                len: data.len(),
+            };
+            frame.data.get_mut(..data.len())?.copy_from_slice(data);
+            Some(frame)
+        }
+
+        fn new_remote(_id: impl Into<embedded_can::Id>, _dlc: usize) -> Option<Self> {
+            None
+        }
+
+        fn is_extended(&self) -> bool {
+            matches!(self.id, embedded_can::Id::Extended(_))
+        }
+
+        fn is_remote_frame(&self) -> bool {
+            false
+        }
+
+        fn id(&self) -> embedded_can::Id {
+            self.id
+        }
+
+        fn dlc(&self) -> usize {
+            self.len
+        }
+
+        fn data(&self) -> &[u8] {
+            &self.data[..self.len]
+        }
+    }
+
+    fn id() -> embedded_can::Id {
+        embedded_can::StandardId::new(1).unwrap().into()
+    }
+
+    /// A frame with the given opcode and arguments, followed by their CRC.
+    fn frame(data: &[u8]) -> Frame {
+        let mut bytes = [0; 8];
+        bytes[..data.len()].copy_from_slice(data);
+        bytes[data.len()] = compute_crc(id(), data);
+        Frame::new(id(), &bytes[..=data.len()]).unwrap()
+    }
+
+    #[test]
+    fn empty_frame() {
+        let frame = Frame::new(id(), &[]).unwrap();
+        assert_eq!(
+            ServoRequest::from_frame(id(), &frame),
+            Err(ProtocolError::EmptyFrame)
+        );
+        assert_eq!(
+            ServoResponse::from_frame(id(), &frame),
+            Err(ProtocolError::EmptyFrame)
+        );
+    }
+
+    #[test]
+    fn missing_opcode() {
+        assert_eq!(
+            ServoResponse::from_frame(id(), &frame(&[])),
+            Err(ProtocolError::MissingOpcode)
+        );
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        // The CRC is the sum of the id bytes 0x00 0x01 and the opcode 0x30.
+        let corrupt = Frame::new(id(), &[0x30, 0x00]).unwrap();
+        assert_eq!(
+            ServoResponse::from_frame(id(), &corrupt),
+            Err(ProtocolError::CrcMismatch {
+                expected: 0x31,
+                actual: 0x00
+            })
+        );
+        // A frame is only valid for the id it was sent with.
+        let other = embedded_can::StandardId::new(2).unwrap().into();
+        assert_eq!(
+            ServoRequest::from_frame(other, &frame(&[0x30])),
+            Err(ProtocolError::CrcMismatch {
+                expected: 0x32,
+                actual: 0x31
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        assert_eq!(
+            ServoRequest::from_frame(id(), &frame(&[0x00])),
+            Err(ProtocolError::UnknownOpcode { opcode: 0x00 })
+        );
+    }
+
+    #[test]
+    fn too_short() {
+        assert_eq!(
+            ServoResponse::from_frame(id(), &frame(&[0x31, 0x00, 0x00])),
+            Err(ProtocolError::TooShort {
+                opcode: ServoOpcode::ReadEncoderValueAddition,
+                len: 2
+            })
+        );
+        assert_eq!(
+            ServoRequest::from_frame(id(), &frame(&[0xf5, 0x00, 0x10, 0x02])),
+            Err(ProtocolError::TooShort {
+                opcode: ServoOpcode::RunPositionAbsoluteMotionMode,
+                len: 3
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_value() {
+        assert_eq!(
+            ServoRequest::from_frame(id(), &frame(&[0x82, 0x06])),
+            Err(ProtocolError::InvalidValue {
+                opcode: ServoOpcode::SetWorkMode,
+                field: "work_mode",
+                value: 0x06
+            })
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        let request = ServoRequest::RunPositionAbsoluteMotionMode {
+            speed: 100,
+            accel: 2,
+            abs_axis: 1 << 23,
+        };
+        assert_eq!(
+            request.to_frame::<Frame>(id()).unwrap_err(),
+            ProtocolError::OutOfRange {
+                opcode: ServoOpcode::RunPositionAbsoluteMotionMode,
+                field: "abs_axis",
+                value: 1 << 23,
+                min: I24_MIN,
+                max: I24_MAX
+            }
+        );
+        let request = ServoRequest::RunSpeedMode {
+            dir: Direction::CW,
+            speed: 3001,
+            acc: 2,
+        };
+        assert_eq!(
+            request.to_frame::<Frame>(id()).unwrap_err(),
+            ProtocolError::OutOfRange {
+                opcode: ServoOpcode::RunSpeedMode,
+                field: "speed",
+                value: 3001,
+                min: 0,
+                max: MAX_SPEED
+            }
+        );
+    }
+
+    /// Every request, with the extremes of its fields.
+    const REQUESTS: &[ServoRequest] = &[
+        ServoRequest::ReadEncoderValueCarry,
+        ServoRequest::ReadEncoderValueAddition,
+        ServoRequest::ReadSpeed,
+        ServoRequest::ReadPulses,
+        ServoRequest::ReadIOPorts,
+        ServoRequest::ReadError,
+        ServoRequest::ReadEnPin,
+        ServoRequest::ReadGoBackToZeroOnPowerOnStatus,
+        ServoRequest::ReleaseMotorShaft,
+        ServoRequest::ReadMotorShaftLockedRotor,
+        ServoRequest::Calibrate,
+        ServoRequest::SetWorkMode {
+            work_mode: WorkMode::CrOpen,
+        },
+        ServoRequest::SetWorkMode {
+            work_mode: WorkMode::SrVFoc,
+        },
+        ServoRequest::SetCurrent { current: 0 },
+        ServoRequest::SetCurrent { current: u16::MAX },
+        ServoRequest::SetSubdivision { microsteps: 0 },
+        ServoRequest::SetSubdivision {
+            microsteps: u8::MAX,
+        },
+        ServoRequest::SetEnPinActiveMode {
+            active: EnPinActiveMode::Low,
+        },
+        ServoRequest::SetEnPinActiveMode {
+            active: EnPinActiveMode::Always,
+        },
+        ServoRequest::SetDir { dir: Direction::CW },
+        ServoRequest::SetDir {
+            dir: Direction::CCW,
+        },
+        ServoRequest::SetAutoSSD { enable: false },
+        ServoRequest::SetAutoSSD { enable: true },
+        ServoRequest::SetMotorShaftLockedRotor { enable: false },
+        ServoRequest::SetMotorShaftLockedRotor { enable: true },
+        ServoRequest::SetSubdivisionInterpolation { enable: false },
+        ServoRequest::SetSubdivisionInterpolation { enable: true },
+        ServoRequest::SetCanBitRate {
+            bit_rate: CanBitRate::B125K,
+        },
+        ServoRequest::SetCanBitRate {
+            bit_rate: CanBitRate::B1M,
+        },
+        ServoRequest::SetCanId { id: 0 },
+        ServoRequest::SetCanId { id: 0x7ff },
+        ServoRequest::SetCanEnableResponses { enable: false },
+        ServoRequest::SetCanEnableResponses { enable: true },
+        ServoRequest::SetKeyLocked { enable: false },
+        ServoRequest::SetKeyLocked { enable: true },
+        ServoRequest::SetGroupId { id: 0 },
+        ServoRequest::SetGroupId { id: 0x7ff },
+        ServoRequest::SetHome {
+            home_trig: HomeTrig::Low,
+            home_dir: Direction::CW,
+            home_speed: 0,
+            end_limit: false,
+        },
+        ServoRequest::SetHome {
+            home_trig: HomeTrig::High,
+            home_dir: Direction::CCW,
+            home_speed: 3000,
+            end_limit: true,
+        },
+        ServoRequest::GoHome,
+        ServoRequest::SetAxisZero,
+        ServoRequest::SetZeroOnPoweronMode {
+            zero_mode: ZeroMode::Disable,
+            enable: false,
+            speed: ZeroModeSpeed::Speed0,
+            dir: Direction::CW,
+        },
+        ServoRequest::SetZeroOnPoweronMode {
+            zero_mode: ZeroMode::NearMode,
+            enable: true,
+            speed: ZeroModeSpeed::Speed3,
+            dir: Direction::CCW,
+        },
+        ServoRequest::RestoreDefaults,
+        ServoRequest::QueryStatus,
+        ServoRequest::Enable { enabled: false },
+        ServoRequest::Enable { enabled: true },
+        ServoRequest::RunSpeedMode {
+            dir: Direction::CW,
+            speed: 0,
+            acc: 0,
+        },
+        ServoRequest::RunSpeedMode {
+            dir: Direction::CCW,
+            speed: 3000,
+            acc: u8::MAX,
+        },
+        ServoRequest::SaveRunModeParams {
+            save_state: SaveState::Save,
+        },
+        ServoRequest::SaveRunModeParams {
+            save_state: SaveState::Clean,
+        },
+        ServoRequest::RunPositionRelativePulsesMode {
+            dir: Direction::CW,
+            speed: 0,
+            acc: 0,
+            pulses: 0,
+        },
+        ServoRequest::RunPositionRelativePulsesMode {
+            dir: Direction::CCW,
+            speed: 3000,
+            acc: u8::MAX,
+            pulses: u16::MAX,
+        },
+        ServoRequest::RunPositionRelativeMotionMode {
+            speed: 0,
+            acc: 0,
+            rel_axis: -(1 << 23),
+        },
+        ServoRequest::RunPositionRelativeMotionMode {
+            speed: 3000,
+            acc: u8::MAX,
+            rel_axis: (1 << 23) - 1,
+        },
+        ServoRequest::RunPositionAbsoluteMotionMode {
+            speed: 0,
+            accel: 0,
+            abs_axis: -(1 << 23),
+        },
+        ServoRequest::RunPositionAbsoluteMotionMode {
+            speed: 3000,
+            accel: u8::MAX,
+            abs_axis: (1 << 23) - 1,
+        },
+    ];
+
+    /// Every response, with the extremes of its fields.
+    const RESPONSES: &[ServoResponse] = &[
+        ServoResponse::ReadEncoderValueCarry {
+            carry: i32::MIN,
+            value: 0,
+        },
+        ServoResponse::ReadEncoderValueCarry {
+            carry: i32::MAX,
+            value: 0x3fff,
+        },
+        ServoResponse::ReadEncoderValueAddition { value: -(1 << 47) },
+        ServoResponse::ReadEncoderValueAddition { value: -1 },
+        ServoResponse::ReadEncoderValueAddition {
+            value: (1 << 47) - 1,
+        },
+        ServoResponse::ReadSpeed { speed: i16::MIN },
+        ServoResponse::ReadSpeed { speed: i16::MAX },
+        ServoResponse::ReadPulses { pulses: i32::MIN },
+        ServoResponse::ReadPulses { pulses: i32::MAX },
+        ServoResponse::ReadIOPorts {
+            out_1: false,
+            out_2: false,
+            in_1: false,
+            in_2: false,
+        },
+        ServoResponse::ReadIOPorts {
+            out_1: true,
+            out_2: false,
+            in_1: false,
+            in_2: true,
+        },
+        ServoResponse::ReadIOPorts {
+            out_1: true,
+            out_2: true,
+            in_1: true,
+            in_2: true,
+        },
+        ServoResponse::ReadError { error: i32::MIN },
+        ServoResponse::ReadError { error: i32::MAX },
+        ServoResponse::ReadEnPin { enabled: false },
+        ServoResponse::ReadEnPin { enabled: true },
+        ServoResponse::ReadGoBackToZeroOnPowerOnStatus {
+            status: ProgressStatus::Busy,
+        },
+        ServoResponse::ReadGoBackToZeroOnPowerOnStatus {
+            status: ProgressStatus::Fail,
+        },
+        ServoResponse::ReleaseMotorShaft { success: false },
+        ServoResponse::ReleaseMotorShaft { success: true },
+        ServoResponse::ReadMotorShaftLockedRotor { locked: false },
+        ServoResponse::ReadMotorShaftLockedRotor { locked: true },
+        ServoResponse::Calibrate {
+            status: ProgressStatus::Busy,
+        },
+        ServoResponse::Calibrate {
+            status: ProgressStatus::Fail,
+        },
+        ServoResponse::SetWorkMode { success: true },
+        ServoResponse::SetCurrent { success: true },
+        ServoResponse::SetSubdivision { success: true },
+        ServoResponse::SetEnPinActiveMode { success: true },
+        ServoResponse::SetDir { success: true },
+        ServoResponse::SetAutoSSD { success: true },
+        ServoResponse::SetMotorShaftLockedRotor { success: true },
+        ServoResponse::SetSubdivisionInterpolation { success: true },
+        ServoResponse::SetCanBitRate { success: true },
+        ServoResponse::SetCanId { success: true },
+        ServoResponse::SetCanEnableResponses { success: true },
+        ServoResponse::SetKeyLocked { success: true },
+        ServoResponse::SetGroupId { success: true },
+        ServoResponse::SetHome { success: true },
+        ServoResponse::GoHome {
+            progress: ProgressStatus::Fail,
+        },
+        ServoResponse::GoHome {
+            progress: ProgressStatus::Busy,
+        },
+        ServoResponse::GoHome {
+            progress: ProgressStatus::Success,
+        },
+        ServoResponse::SetAxisZero { success: false },
+        ServoResponse::SetAxisZero { success: true },
+        ServoResponse::SetZeroOnPowerOnMode { success: true },
+        ServoResponse::RestoreDefaults { success: true },
+        ServoResponse::QueryStatus { status: None },
+        ServoResponse::QueryStatus {
+            status: Some(MotorStatus::MotorStopped),
+        },
+        ServoResponse::QueryStatus {
+            status: Some(MotorStatus::MotorHoming),
+        },
+        ServoResponse::Enable { success: false },
+        ServoResponse::Enable { success: true },
+        ServoResponse::RunSpeedMode {
+            status: MotionStatus::Fail,
+        },
+        ServoResponse::RunSpeedMode {
+            status: MotionStatus::LimitReached,
+        },
+        ServoResponse::SaveRunModeParams { success: true },
+        ServoResponse::RunPositionRelativePulsesMode {
+            status: MotionStatus::Busy,
+        },
+        ServoResponse::RunPositionRelativeMotionMode {
+            status: MotionStatus::Success,
+        },
+        ServoResponse::RunPositionAbsoluteMotionMode {
+            status: MotionStatus::LimitReached,
+        },
+    ];
+
+    #[test]
+    fn every_opcode_is_covered() {
+        use num_traits::FromPrimitive as _;
+
+        for opcode in (0..=u8::MAX).filter_map(ServoOpcode::from_u8) {
+            assert!(
+                REQUESTS.iter().any(|request| request.opcode() == opcode),
+                "no request for {opcode:?}"
+            );
+            assert!(
+                RESPONSES.iter().any(|response| response.opcode() == opcode),
+                "no response for {opcode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        for request in REQUESTS {
+            let frame = request.to_frame::<Frame>(id()).unwrap();
+            assert_eq!(frame.data()[0], request.opcode() as u8);
+            assert_eq!(
+                ServoRequest::from_frame(id(), &frame),
+                Ok(*request),
+                "{request:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        for response in RESPONSES {
+            let frame = response.to_frame::<Frame>(id()).unwrap();
+            assert_eq!(frame.data()[0], response.opcode() as u8);
+            assert_eq!(
+                ServoResponse::from_frame(id(), &frame),
+                Ok(*response),
+                "{response:?}"
+            );
+        }
+    }
+
+    /// A xorshift generator, so that randomized tests are repeatable.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// A frame with the given opcode, random arguments and a valid CRC.  Half of the bytes
+        /// are small, to hit the few valid values of enums and flags often enough.
+        fn frame(&mut self, opcode: ServoOpcode) -> Frame {
+            let mut data = [opcode as u8, 0, 0, 0, 0, 0, 0];
+            for byte in &mut data[1..] {
+                let random = self.next();
+                *byte = if random & 0x100 == 0 {
+                    random as u8 % 4
+                } else {
+                    random as u8
+                };
+            }
+            frame(&data)
+        }
+    }
+
+    #[test]
+    fn random_round_trips() {
+        use num_traits::FromPrimitive as _;
+
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for opcode in (0..=u8::MAX).filter_map(ServoOpcode::from_u8) {
+            // Random frames fill every field with random values, and those that decode have to
+            // encode to a frame that decodes to the same again.
+            let (mut requests, mut responses) = (0, 0);
+            for _ in 0..1000 {
+                let frame = rng.frame(opcode);
+                if let Ok(request) = ServoRequest::from_frame(id(), &frame) {
+                    let encoded = request.to_frame::<Frame>(id()).unwrap();
+                    assert_eq!(ServoRequest::from_frame(id(), &encoded), Ok(request));
+                    requests += 1;
+                }
+                if let Ok(response) = ServoResponse::from_frame(id(), &frame) {
+                    let encoded = response.to_frame::<Frame>(id()).unwrap();
+                    assert_eq!(ServoResponse::from_frame(id(), &encoded), Ok(response));
+                    responses += 1;
+                }
+            }
+            assert!(requests > 0, "no request for {opcode:?} decoded");
+            assert!(responses > 0, "no response for {opcode:?} decoded");
+        }
+    }
+
+    #[test]
+    fn request_encoding() {
+        // Direction in the top bit of the speed, and positions as 24 bit two's complement.
+        let request = ServoRequest::RunSpeedMode {
+            dir: Direction::CCW,
+            speed: 0x123,
+            acc: 2,
+        };
+        assert_eq!(
+            request.to_frame::<Frame>(id()).unwrap().data(),
+            frame(&[0xf6, 0x81, 0x23, 0x02]).data()
+        );
+        let request = ServoRequest::RunPositionAbsoluteMotionMode {
+            speed: 600,
+            accel: 2,
+            abs_axis: -2,
+        };
+        assert_eq!(
+            request.to_frame::<Frame>(id()).unwrap().data(),
+            frame(&[0xf5, 0x02, 0x58, 0x02, 0xff, 0xff, 0xfe]).data()
+        );
+    }
+
+    #[test]
+    fn decoder_rejects_what_encoder_rejects() {
+        let cases = [
+            (ServoRequest::SetCanId { id: 0x800 }, [0x8b, 0x08, 0x00]),
+            (ServoRequest::SetGroupId { id: 0xffff }, [0x8d, 0xff, 0xff]),
+        ];
+        for (request, data) in cases {
+            let error = request.to_frame::<Frame>(id()).unwrap_err();
+            assert!(matches!(error, ProtocolError::OutOfRange { .. }));
+            assert_eq!(ServoRequest::from_frame(id(), &frame(&data)), Err(error));
+        }
+        let request = ServoRequest::RunSpeedMode {
+            dir: Direction::CCW,
+            speed: 3001,
+            acc: 0,
+        };
+        let error = request.to_frame::<Frame>(id()).unwrap_err();
+        assert_eq!(
+            ServoRequest::from_frame(id(), &frame(&[0xf6, 0x8b, 0xb9, 0x00])),
+            Err(error)
+        );
+        let cases = [
+            (
+                ServoRequest::RunPositionRelativeMotionMode {
+                    speed: 3001,
+                    acc: 0,
+                    rel_axis: 0,
+                },
+                [0xf4, 0x0b, 0xb9, 0x00, 0x00, 0x00, 0x00],
+            ),
+            (
+                ServoRequest::RunPositionAbsoluteMotionMode {
+                    speed: u16::MAX,
+                    accel: 0,
+                    abs_axis: 0,
+                },
+                [0xf5, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00],
+            ),
+        ];
+        for (request, data) in cases {
+            let error = request.to_frame::<Frame>(id()).unwrap_err();
+            assert!(matches!(
+                error,
+                ProtocolError::OutOfRange { field: "speed", .. }
+            ));
+            assert_eq!(ServoRequest::from_frame(id(), &frame(&data)), Err(error));
+        }
+    }
+}
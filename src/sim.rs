@@ -0,0 +1,215 @@
+//! Simulated servos, for trying out commands without an arm.
+//!
+//! A servo is simulated for every CAN id that frames are sent to, so that servos besides the
+//! axes, such as one driving a gripper, can be tried out too.  Servos start out enabled at their
+//! origin, as if the arm had been brought up, and acknowledge every request as successful.
+//! Motions in position mode complete immediately, and the simulated encoder reports the position
+//! that was last moved to.  In speed mode, the position advances with time at the requested
+//! speed.
+use std::collections;
+use std::convert;
+use std::time;
+
+use futures::{channel::mpsc, future, sink, stream};
+use socketcan::EmbeddedFrame as _;
+
+use crate::servo_cmd::{self, ServoRequest, ServoResponse};
+
+#[derive(Debug)]
+struct Servo {
+    /// Encoder value, where `0x4000` is a full turn.
+    position: i64,
+    enabled: bool,
+    /// Speed in RPM, where positive speeds make the encoder count up, and when it was set.
+    running: Option<(f64, time::Instant)>,
+}
+
+impl Default for Servo {
+    /// A servo of an arm that has been brought up: enabled, and at its origin.
+    fn default() -> Self {
+        Self {
+            position: 0,
+            enabled: true,
+            running: None,
+        }
+    }
+}
+
+impl Servo {
+    /// Advances the position by however far the servo ran since it was last advanced.
+    fn advance(&mut self) {
+        if let Some((speed, since)) = &mut self.running {
+            let now = time::Instant::now();
+            let rotations = *speed / 60.0 * now.duration_since(*since).as_secs_f64();
+            self.position += (rotations * 0x4000 as f64).round() as i64;
+            *since = now;
+        }
+    }
+
+    fn speed(&self) -> f64 {
+        self.running.map_or(0.0, |(speed, _)| speed)
+    }
+}
+
+/// Creates a fake bus with simulated servos, for use with
+/// [`crate::bus::Dispatcher::new`].
+///
+/// The returned future drives the simulation, and must be polled for as long as the bus is in
+/// use.
+pub fn simulate() -> (
+    impl sink::Sink<socketcan::CanFrame, Error = mpsc::SendError> + Unpin,
+    impl stream::Stream<Item = Result<socketcan::CanFrame, convert::Infallible>> + Unpin,
+    impl future::Future<Output = ()>,
+) {
+    use sink::SinkExt as _;
+    use stream::StreamExt as _;
+
+    let (can_tx, mut sent) = mpsc::channel::<socketcan::CanFrame>(1);
+    let (mut received, can_rx) = mpsc::channel(1);
+    let driver = async move {
+        let mut servos = collections::BTreeMap::<socketcan::Id, Servo>::new();
+        while let Some(frame) = sent.next().await {
+            let request = match ServoRequest::from_frame(frame.id(), &frame) {
+                Ok(request) => request,
+                Err(err) => {
+                    tracing::warn!("simulated servo {:?} ignoring frame: {err}", frame.id());
+                    continue;
+                }
+            };
+            for response in respond(servos.entry(frame.id()).or_default(), request) {
+                let frame = response
+                    .to_frame(frame.id())
+                    .expect("valid simulated response");
+                if received.send(Ok(frame)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    };
+    (can_tx, can_rx, driver)
+}
+
+/// The responses of a servo to a request, in order.
+fn respond(servo: &mut Servo, request: ServoRequest) -> Vec<ServoResponse> {
+    use servo_cmd::{MotionStatus, ProgressStatus};
+
+    servo.advance();
+    let success = true;
+    let motion = |status| match request {
+        ServoRequest::RunPositionRelativeMotionMode { .. } => {
+            ServoResponse::RunPositionRelativeMotionMode { status }
+        }
+        ServoRequest::RunPositionAbsoluteMotionMode { .. } => {
+            ServoResponse::RunPositionAbsoluteMotionMode { status }
+        }
+        _ => ServoResponse::RunPositionRelativePulsesMode { status },
+    };
+    let response = match request {
+        ServoRequest::ReadEncoderValueCarry => ServoResponse::ReadEncoderValueCarry {
+            carry: servo.position.div_euclid(0x4000) as i32,
+            value: servo.position.rem_euclid(0x4000) as u16,
+        },
+        ServoRequest::ReadEncoderValueAddition => ServoResponse::ReadEncoderValueAddition {
+            value: servo.position,
+        },
+        ServoRequest::ReadSpeed => ServoResponse::ReadSpeed {
+            speed: servo.speed().round() as i16,
+        },
+        ServoRequest::ReadPulses => ServoResponse::ReadPulses { pulses: 0 },
+        ServoRequest::ReadIOPorts => ServoResponse::ReadIOPorts {
+            out_1: false,
+            out_2: false,
+            in_1: false,
+            in_2: false,
+        },
+        ServoRequest::ReadError => ServoResponse::ReadError { error: 0 },
+        ServoRequest::ReadEnPin => ServoResponse::ReadEnPin {
+            enabled: servo.enabled,
+        },
+        ServoRequest::ReadGoBackToZeroOnPowerOnStatus => {
+            ServoResponse::ReadGoBackToZeroOnPowerOnStatus {
+                status: ProgressStatus::Success,
+            }
+        }
+        ServoRequest::ReleaseMotorShaft => ServoResponse::ReleaseMotorShaft { success },
+        ServoRequest::ReadMotorShaftLockedRotor => {
+            ServoResponse::ReadMotorShaftLockedRotor { locked: false }
+        }
+        ServoRequest::Calibrate => ServoResponse::Calibrate {
+            status: ProgressStatus::Success,
+        },
+        ServoRequest::SetWorkMode { .. } => ServoResponse::SetWorkMode { success },
+        ServoRequest::SetCurrent { .. } => ServoResponse::SetCurrent { success },
+        ServoRequest::SetSubdivision { .. } => ServoResponse::SetSubdivision { success },
+        ServoRequest::SetEnPinActiveMode { .. } => ServoResponse::SetEnPinActiveMode { success },
+        ServoRequest::SetDir { .. } => ServoResponse::SetDir { success },
+        ServoRequest::SetAutoSSD { .. } => ServoResponse::SetAutoSSD { success },
+        ServoRequest::SetMotorShaftLockedRotor { .. } => {
+            ServoResponse::SetMotorShaftLockedRotor { success }
+        }
+        ServoRequest::SetSubdivisionInterpolation { .. } => {
+            ServoResponse::SetSubdivisionInterpolation { success }
+        }
+        ServoRequest::SetCanBitRate { .. } => ServoResponse::SetCanBitRate { success },
+        ServoRequest::SetCanId { .. } => ServoResponse::SetCanId { success },
+        ServoRequest::SetCanEnableResponses { .. } => {
+            ServoResponse::SetCanEnableResponses { success }
+        }
+        ServoRequest::SetKeyLocked { .. } => ServoResponse::SetKeyLocked { success },
+        ServoRequest::SetGroupId { .. } => ServoResponse::SetGroupId { success },
+        ServoRequest::SetHome { .. } => ServoResponse::SetHome { success },
+        ServoRequest::GoHome => {
+            servo.running = None;
+            servo.position = 0;
+            return vec![
+                ServoResponse::GoHome {
+                    progress: ProgressStatus::Busy,
+                },
+                ServoResponse::GoHome {
+                    progress: ProgressStatus::Success,
+                },
+            ];
+        }
+        ServoRequest::SetAxisZero => {
+            servo.position = 0;
+            ServoResponse::SetAxisZero { success }
+        }
+        ServoRequest::SetZeroOnPoweronMode { .. } => {
+            ServoResponse::SetZeroOnPowerOnMode { success }
+        }
+        ServoRequest::RestoreDefaults => ServoResponse::RestoreDefaults { success },
+        ServoRequest::QueryStatus => ServoResponse::QueryStatus {
+            status: Some(servo_cmd::MotorStatus::MotorStopped),
+        },
+        ServoRequest::Enable { enabled } => {
+            servo.enabled = enabled;
+            ServoResponse::Enable { success }
+        }
+        ServoRequest::RunSpeedMode { dir, speed, .. } => {
+            let speed = match dir {
+                servo_cmd::Direction::CW => f64::from(speed),
+                servo_cmd::Direction::CCW => -f64::from(speed),
+            };
+            servo.running = (speed != 0.0).then(|| (speed, time::Instant::now()));
+            // Real servos acknowledge speed mode with a status of 1, which decodes as busy.
+            ServoResponse::RunSpeedMode {
+                status: MotionStatus::Busy,
+            }
+        }
+        ServoRequest::SaveRunModeParams { .. } => ServoResponse::SaveRunModeParams { success },
+        ServoRequest::RunPositionRelativePulsesMode { .. } => {
+            return vec![motion(MotionStatus::Busy), motion(MotionStatus::Success)];
+        }
+        ServoRequest::RunPositionRelativeMotionMode { rel_axis, .. } => {
+            servo.running = None;
+            servo.position += i64::from(rel_axis);
+            return vec![motion(MotionStatus::Busy), motion(MotionStatus::Success)];
+        }
+        ServoRequest::RunPositionAbsoluteMotionMode { abs_axis, .. } => {
+            servo.running = None;
+            servo.position = i64::from(abs_axis);
+            return vec![motion(MotionStatus::Busy), motion(MotionStatus::Success)];
+        }
+    };
+    vec![response]
+}
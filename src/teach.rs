@@ -0,0 +1,318 @@
+//! Teaching the arm by hand: disabling the axes, capturing waypoints or whole demonstrations, and
+//! replaying them.
+//!
+//! Waypoints are saved as a TOML file with the raw positions of all axes:
+//!
+//! ```toml
+//! [[waypoint]]
+//! name = "pick"
+//! positions = { x = 1.5, y = -20.25, z = 3.0, a = 0.0, b = 7.5, c = 0.0 }
+//! ```
+//!
+//! Demonstrations are saved as a CSV file with a row for every sample, with the time in seconds
+//! and the raw positions of all axes.  They are smoothed when they are played back, so that the
+//! smoothing can be tuned without recording again.
+use std::collections;
+use std::path;
+
+use anyhow::Context as _;
+use arctos_can_driver::arm::moves::PositionSample;
+use arctos_can_driver::{motion, Axis};
+
+use crate::{gcode, output, BusOptions, Request};
+
+/// A position of the arm, captured while teaching.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Waypoint {
+    pub name: String,
+    /// Raw positions in number of servo rotations from origin.
+    pub positions: collections::BTreeMap<Axis, f64>,
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WaypointFile {
+    #[serde(default, rename = "waypoint")]
+    waypoints: Vec<Waypoint>,
+}
+
+/// Reads the waypoints of a file.
+pub fn load(path: &path::Path) -> anyhow::Result<Vec<Waypoint>> {
+    let file = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let file: WaypointFile = toml::from_str(&file)
+        .with_context(|| format!("invalid waypoint file {}", path.display()))?;
+    Ok(file.waypoints)
+}
+
+fn save(path: &path::Path, waypoints: &[Waypoint]) -> anyhow::Result<()> {
+    let file = toml::to_string(&WaypointFile {
+        waypoints: waypoints.to_vec(),
+    })?;
+    std::fs::write(path, file).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Disables `axes`, and captures a waypoint every time a line is read from stdin, until it is
+/// closed.  The line is the name of the waypoint.
+pub async fn record(
+    bus: &BusOptions,
+    format: output::OutputFormat,
+    socket_path: &path::Path,
+    path: &path::Path,
+    axes: Vec<Axis>,
+) -> anyhow::Result<()> {
+    use tokio::io::AsyncBufReadExt as _;
+
+    let mut waypoints = if path.exists() {
+        load(path)?
+    } else {
+        Vec::new()
+    };
+    disable(bus, format, socket_path, axes).await?;
+    tracing::info!(
+        "move the arm by hand, then type a name and press Enter to capture a waypoint, or press \
+         Ctrl-D when done"
+    );
+
+    let mut columns = vec!["name".to_owned()];
+    columns.extend(Axis::ALL.iter().map(|axis| axis_name(*axis)));
+    let mut stream = output::RecordStream::new(format, columns)?;
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        let name = match line.trim() {
+            "" => format!("waypoint {}", waypoints.len() + 1),
+            name => name.to_owned(),
+        };
+        if waypoints.iter().any(|waypoint| waypoint.name == name) {
+            tracing::warn!("there already is a waypoint named {name:?}, try another name");
+            continue;
+        }
+
+        let read = Request::Read {
+            axes: Axis::ALL.to_vec(),
+        };
+        let records = crate::dispatch(bus, socket_path, read, |_| {
+            anyhow::bail!("unexpected streamed record")
+        })
+        .await?;
+        let mut positions = collections::BTreeMap::new();
+        for record in records {
+            let axis: Axis =
+                serde_json::from_value(record.get("axis").cloned().unwrap_or_default())?;
+            match record.get("rotations").and_then(serde_json::Value::as_f64) {
+                Some(rotations) => positions.insert(axis, rotations),
+                None => {
+                    let error = record.get("error").cloned().unwrap_or_default();
+                    anyhow::bail!("failed to read the position of axis {axis:?}: {error}");
+                }
+            };
+        }
+
+        let mut captured = output::Record::new();
+        captured.insert("name".to_owned(), name.clone().into());
+        for (&axis, &rotations) in &positions {
+            captured.insert(axis_name(axis), rotations.into());
+        }
+        waypoints.push(Waypoint { name, positions });
+        save(path, &waypoints)?;
+        stream.write(&captured)?;
+    }
+    tracing::info!("saved {} waypoints to {}", waypoints.len(), path.display());
+    Ok(())
+}
+
+/// Disables `axes`, and records the positions of all axes `rate` times per second until
+/// interrupted.  Samples are written to the file as they are taken.
+pub async fn demonstrate(
+    bus: &BusOptions,
+    format: output::OutputFormat,
+    socket_path: &path::Path,
+    path: &path::Path,
+    axes: Vec<Axis>,
+    rate: f64,
+) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_path(path)
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    disable(bus, format, socket_path, axes).await?;
+    tracing::info!("recording, move the arm by hand and press Ctrl-C when done");
+
+    let mut count = 0;
+    let on_record = |record: output::Record| {
+        let sample: PositionSample = serde_json::from_value(record.into())?;
+        writer.serialize(sample)?;
+        // Flush every sample, so that nothing is lost if the process is killed.
+        writer.flush()?;
+        count += 1;
+        Ok(())
+    };
+    let sample = Request::SamplePositions { rate };
+    tokio::select! {
+        result = crate::dispatch(bus, socket_path, sample, on_record) => {
+            result?;
+        }
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    tracing::info!("saved {count} samples to {}", path.display());
+    Ok(())
+}
+
+/// Reads a demonstration, and fits a path to it; see [`motion::Spline::fit`].
+pub fn load_path(
+    path: &path::Path,
+    smoothing: f64,
+    interval: f64,
+    timing: motion::Timing,
+) -> anyhow::Result<motion::Spline> {
+    let mut reader = csv::Reader::from_path(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let mut samples = Vec::new();
+    for sample in reader.deserialize() {
+        let sample: PositionSample =
+            sample.with_context(|| format!("invalid demonstration {}", path.display()))?;
+        samples.push((sample.time, sample.positions()));
+    }
+    if samples.len() < 2 {
+        anyhow::bail!(
+            "demonstration {} has fewer than two samples",
+            path.display()
+        );
+    }
+    if samples.windows(2).any(|pair| pair[1].0 < pair[0].0) {
+        anyhow::bail!(
+            "samples of demonstration {} are out of order",
+            path.display()
+        );
+    }
+    Ok(motion::Spline::fit(&samples, smoothing, interval, timing))
+}
+
+/// Enables all axes, and plays back a path at `speed` times its timing, with `rate` setpoints
+/// per second.
+pub async fn play(
+    bus: &BusOptions,
+    format: output::OutputFormat,
+    socket_path: &path::Path,
+    path: motion::Spline,
+    speed: f64,
+    rate: f64,
+) -> anyhow::Result<()> {
+    let enable = Request::Enable {
+        axes: Axis::ALL.to_vec(),
+    };
+    let records = crate::dispatch(bus, socket_path, enable, |_| {
+        anyhow::bail!("unexpected streamed record")
+    })
+    .await?;
+    output::ensure_success(&records)?;
+    let request = Request::PlayPath { path, speed, rate };
+    crate::call(bus, format, socket_path, request).await
+}
+
+/// Disables (powers off) `axes`, so that they can be moved by hand.
+async fn disable(
+    bus: &BusOptions,
+    format: output::OutputFormat,
+    socket_path: &path::Path,
+    axes: Vec<Axis>,
+) -> anyhow::Result<()> {
+    crate::call(bus, format, socket_path, Request::Disable { axes }).await
+}
+
+/// The blocks of a program that enables all axes, and visits the waypoints in order.  The line
+/// number of a block is the number of its waypoint, starting at 1.
+pub fn replay(waypoints: &[Waypoint]) -> Vec<gcode::Block> {
+    let enable = gcode::Block {
+        line: 0,
+        code: "enable".to_owned(),
+        action: gcode::Action::Request(Request::Enable {
+            axes: Axis::ALL.to_vec(),
+        }),
+    };
+    let visits = waypoints
+        .iter()
+        .enumerate()
+        .map(|(index, waypoint)| gcode::Block {
+            line: index + 1,
+            code: waypoint.name.clone(),
+            action: gcode::Action::Request(Request::MovePositions {
+                positions: waypoint.positions.clone(),
+            }),
+        });
+    [enable].into_iter().chain(visits).collect()
+}
+
+/// The name of an axis, as used for columns and in waypoint files.
+fn axis_name(axis: Axis) -> String {
+    match serde_json::to_value(axis) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => unreachable!("axes serialize as their names"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a demonstration to a file of its own, for [`load_path`].
+    fn demonstration(name: &str, csv: &str) -> path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "arctos-can-driver-{}-{name}.csv",
+            std::process::id()
+        ));
+        std::fs::write(&path, csv).unwrap();
+        path
+    }
+
+    fn load(name: &str, csv: &str) -> anyhow::Result<motion::Spline> {
+        let path = demonstration(name, csv);
+        let spline = load_path(&path, 0.0, 0.1, motion::Timing::Preserved);
+        std::fs::remove_file(path).unwrap();
+        spline
+    }
+
+    #[test]
+    fn load_demonstration() {
+        let spline = load(
+            "valid",
+            "time,x,y,z,a,b,c\n\
+             0.0,0.0,0.0,0.0,0.0,0.0,0.0\n\
+             0.1,0.5,0.0,0.0,0.0,0.0,-1.0\n\
+             0.2,1.0,0.0,0.0,0.0,0.0,-2.0\n",
+        )
+        .unwrap();
+        assert!((spline.duration() - 0.2).abs() < 1e-9);
+        assert_eq!(spline.position(0.1)[0], 0.5);
+        assert_eq!(spline.position(0.2), [1.0, 0.0, 0.0, 0.0, 0.0, -2.0]);
+    }
+
+    #[test]
+    fn rejected_demonstrations() {
+        for (name, csv, error) in [
+            ("empty", "time,x,y,z,a,b,c\n", "fewer than two samples"),
+            (
+                "single",
+                "time,x,y,z,a,b,c\n0.0,0.0,0.0,0.0,0.0,0.0,0.0\n",
+                "fewer than two samples",
+            ),
+            (
+                "unordered",
+                "time,x,y,z,a,b,c\n\
+                 0.0,0.0,0.0,0.0,0.0,0.0,0.0\n\
+                 0.2,1.0,0.0,0.0,0.0,0.0,0.0\n\
+                 0.1,0.5,0.0,0.0,0.0,0.0,0.0\n",
+                "out of order",
+            ),
+            (
+                "columns",
+                "time,x,y\n0.0,0.0,0.0\n0.1,0.5,0.0\n",
+                "invalid demonstration",
+            ),
+        ] {
+            let err = load(name, csv).unwrap_err();
+            assert!(err.to_string().contains(error), "{name}: {err}");
+        }
+        let missing = std::env::temp_dir().join("arctos-can-driver-missing.csv");
+        assert!(load_path(&missing, 0.0, 0.1, motion::Timing::Preserved).is_err());
+    }
+}